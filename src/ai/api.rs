@@ -0,0 +1,64 @@
+/// Language whose public declarations `summarize_api_changes` looks for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Python,
+    Go,
+    TypeScript,
+}
+
+/// Rust items that start a public declaration after `pub `
+const RUST_ITEMS: &[&str] = &["fn ", "async fn ", "const fn ", "unsafe fn ", "struct ", "enum ", "trait ", "type ", "const ", "static ", "mod ", "use ", "union ", "macro "];
+
+impl Language {
+    /// Whether a changed line declares a public item. `context` is the enclosing
+    /// section from the hunk header (e.g. `pub trait Store {`), which makes the
+    /// `fn` lines of a public trait count even though they aren't marked `pub`.
+    pub fn is_public_item(&self, line: &str, context: &str) -> bool {
+        let line = line.trim_start();
+        match self {
+            Language::Rust => {
+                let in_public_trait = context.trim_start().starts_with("pub trait ");
+                match line.strip_prefix("pub ") {
+                    Some(item) => RUST_ITEMS.iter().any(|prefix| item.starts_with(prefix)),
+                    None => in_public_trait && (line.starts_with("fn ") || line.starts_with("type ") || line.starts_with("const ")),
+                }
+            }
+            Language::Python => ["def ", "async def ", "class "].iter()
+                .filter_map(|prefix| line.strip_prefix(prefix))
+                .any(|name| !name.starts_with('_') || name.starts_with("__init__")),
+            // Exported Go names start with an upper-case letter; methods have a receiver first
+            Language::Go => {
+                let name = match line.strip_prefix("func ") {
+                    Some(rest) if rest.starts_with('(') => rest.split_once(')').map(|(_, name)| name.trim_start()),
+                    Some(rest) => Some(rest),
+                    None => line.strip_prefix("type ").or_else(|| line.strip_prefix("const ")).or_else(|| line.strip_prefix("var ")),
+                };
+                name.and_then(|name| name.chars().next()).is_some_and(|c| c.is_uppercase())
+            }
+            Language::TypeScript => line.starts_with("export "),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_public_items() {
+        let rust = Language::Rust;
+        assert!(rust.is_public_item("    pub fn parse(input: &str) -> Result<Ast> {", ""));
+        assert!(rust.is_public_item("pub struct Config {", ""));
+        assert!(rust.is_public_item("    fn get(&self, key: &str) -> Option<Value>;", "pub trait Store {"));
+        assert!(!rust.is_public_item("    fn helper() {}", "impl Parser {"));
+        assert!(!rust.is_public_item("pub(crate) fn internal() {}", ""));
+        assert!(!rust.is_public_item("let public = true;", ""));
+
+        assert!(Language::Python.is_public_item("def load(path):", ""));
+        assert!(!Language::Python.is_public_item("    def _cache(self):", ""));
+        assert!(Language::Go.is_public_item("func (s *Server) Start() error {", ""));
+        assert!(!Language::Go.is_public_item("func helper() {", ""));
+        assert!(Language::TypeScript.is_public_item("export function render(): void {", ""));
+    }
+}
@@ -0,0 +1,196 @@
+use git2::{DiffDelta, DiffLineType, Repository};
+use std::fs;
+use tracing::debug;
+
+/// Name of the ignore file read from the repository root
+pub const IGNORE_FILE: &str = ".gitwiseignore";
+
+/// A single pattern line from `.gitwiseignore`
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    negated: bool,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // A separator anywhere but the end anchors the pattern to the repo root
+        let anchored = line.contains('/');
+        let pattern = line.trim_start_matches('/').to_string();
+
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Self { pattern, negated, anchored, dir_only })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let components: Vec<&str> = path.split('/').collect();
+        let last = components.len() - 1;
+
+        (0..components.len()).any(|i| {
+            if self.dir_only && i == last {
+                return false;
+            }
+            if self.anchored {
+                glob_match(self.pattern.as_bytes(), components[..=i].join("/").as_bytes())
+            } else {
+                glob_match(self.pattern.as_bytes(), components[i].as_bytes())
+            }
+        })
+    }
+}
+
+/// Match `text` against a glob supporting `*`, `**` and `?`
+fn glob_match(pat: &[u8], text: &[u8]) -> bool {
+    match pat.first() {
+        None => text.is_empty(),
+        Some(b'*') if pat.get(1) == Some(&b'*') => {
+            let rest = &pat[2..];
+            match rest.strip_prefix(b"/") {
+                // `**/` matches zero or more leading directories
+                Some(rest) => glob_match(rest, text)
+                    || text.iter().enumerate().any(|(i, &c)| c == b'/' && glob_match(rest, &text[i + 1..])),
+                None => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+            }
+        }
+        Some(b'*') => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != b'/')
+            .any(|i| glob_match(&pat[1..], &text[i..])),
+        Some(b'?') => text.first().is_some_and(|&c| c != b'/') && glob_match(&pat[1..], &text[1..]),
+        Some(&c) => text.first() == Some(&c) && glob_match(&pat[1..], &text[1..]),
+    }
+}
+
+/// Glob patterns for files whose content should be left out of AI prompts
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Parse rules from the contents of an ignore file
+    pub fn parse(contents: &str) -> Self {
+        Self {
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    /// Load `.gitwiseignore` from the current repository root, if there is one,
+    /// after the given extra patterns so the file can override them
+    pub fn load(extra_patterns: &[String]) -> Self {
+        let mut rules = Self::parse(&extra_patterns.join("\n"));
+        let file_rules = Repository::open_from_env()
+            .ok()
+            .and_then(|repo| repo.workdir().map(|dir| dir.join(IGNORE_FILE)))
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| Self::parse(&contents))
+            .unwrap_or_default();
+        rules.rules.extend(file_rules.rules);
+        rules
+    }
+
+    /// Check whether a repository-relative path is excluded
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.matches(path) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+
+    /// Record the delta in `skipped` and return true if its file is excluded
+    pub fn skip_file(&self, delta: &DiffDelta, skipped: &mut SkippedFiles) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+            Some(path) => path.to_string_lossy().replace('\\', "/"),
+            None => return false,
+        };
+
+        if !self.is_ignored(&path) {
+            return false;
+        }
+
+        skipped.record_file(&path);
+        true
+    }
+}
+
+/// Files excluded from a prompt, with their line counts
+#[derive(Debug, Default)]
+pub struct SkippedFiles {
+    files: Vec<(String, usize, usize)>,
+}
+
+impl SkippedFiles {
+    fn record_file(&mut self, path: &str) {
+        debug!("Excluding {} from AI prompt ({})", path, IGNORE_FILE);
+        self.files.push((path.to_string(), 0, 0));
+    }
+
+    /// Count a line belonging to the most recently skipped file
+    pub fn record_line(&mut self, origin: DiffLineType) {
+        if let Some((_, added, removed)) = self.files.last_mut() {
+            match origin {
+                DiffLineType::Addition => *added += 1,
+                DiffLineType::Deletion => *removed += 1,
+                _ => (),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Describe the omitted files so the model still knows they changed
+    pub fn summary(&self) -> String {
+        let mut text = format!("\n{} ignored file(s) changed, content omitted:\n", self.files.len());
+        for (path, added, removed) in &self.files {
+            text.push_str(&format!("  {} (+{} -{})\n", path, added, removed));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_patterns() {
+        let rules = IgnoreRules::parse("# lockfiles\n*.lock\ngen/**/*.pb.rs\nvendor/\n!keep.lock\n");
+
+        assert!(rules.is_ignored("Cargo.lock"));
+        assert!(rules.is_ignored("web/yarn.lock"));
+        assert!(!rules.is_ignored("keep.lock"));
+        assert!(rules.is_ignored("gen/api.pb.rs"));
+        assert!(rules.is_ignored("gen/v1/api.pb.rs"));
+        assert!(!rules.is_ignored("src/gen/api.pb.rs"));
+        assert!(rules.is_ignored("vendor/lib.rs"));
+        assert!(rules.is_ignored("third_party/vendor/lib.rs"));
+        assert!(!rules.is_ignored("vendor"));
+        assert!(!rules.is_ignored("src/main.rs"));
+    }
+}
@@ -1,316 +1,1798 @@
-use anyhow::{Result, Context};
 use async_openai::{
     types::{
         ChatCompletionRequestSystemMessage,
         ChatCompletionRequestUserMessage,
         ChatCompletionRequestUserMessageContent,
         CreateChatCompletionRequest,
+        CreateChatCompletionResponse,
         Role,
     },
-    Client, config::OpenAIConfig,
+    Client, config::{AzureConfig, OpenAIConfig},
 };
 use anthropic::{
     client::{Client as AnthropicClient, ClientBuilder},
-    types::{MessagesRequest, Role as AnthropicRole, Message, ContentBlock},
+    types::{ContentBlock, ContentBlockDelta, Message, MessagesRequest, MessagesResponse, MessagesStreamEvent, Role as AnthropicRole},
 };
-use git2::Diff;
+use futures::StreamExt;
+use git2::{Delta, Diff, DiffDelta, DiffHunk, DiffLine, DiffLineType, Repository};
+use serde::Deserialize;
+use std::cell::RefCell;
 use std::env;
-use tracing::{debug, info};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::config::{Auto, AutoModel, Config, Prompts};
+use crate::error::{GitwiseError, Result};
+use crate::git::lint::{validate_commit_message, LintWarning};
+use crate::git::submodule::{describe_submodule_change, is_submodule};
+use crate::ui::spinner::Spinner;
+use crate::utils::{self, display_width};
+
+mod api;
+mod ignore;
+mod polish;
+mod rate_limit;
+mod redact;
+mod summary;
+mod usage;
+
+use ignore::{IgnoreRules, SkippedFiles};
+use rate_limit::PostError;
+pub use api::Language;
+pub use polish::TextConstraints;
+pub use summary::{diff_stats, file_types, FileTypeStats};
+pub use usage::Usage;
 
 // Constants for token limits
 const ANTHROPIC_MAX_TOKENS: usize = 4096;
 const OPENAI_MAX_TOKENS: u16 = 4096;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum ModelProvider {
-    Anthropic,
-    OpenAI,
-}
+// Default models, overridable with ANTHROPIC_MODEL / OPENAI_MODEL
+const DEFAULT_ANTHROPIC_MODEL: &str = "claude-3-sonnet-20240229";
+const DEFAULT_OPENAI_MODEL: &str = "gpt-3.5-turbo";
+
+// Sampling temperature, overridable with GITWISE_TEMPERATURE / --temperature.
+// Kept low so commit messages are consistent between runs.
+const DEFAULT_TEMPERATURE: f32 = 0.2;
+const MAX_TEMPERATURE: f32 = 2.0;
+// Anthropic only accepts 0.0-1.0, so higher values are clamped for it
+const ANTHROPIC_MAX_TEMPERATURE: f32 = 1.0;
+
+// Commit message format, overridable with --subject-len / --body-wrap
+const DEFAULT_SUBJECT_LEN: usize = 50;
+const DEFAULT_BODY_WRAP: usize = 72;
+// Pull request titles, overridable with `pr --title-len`
+const DEFAULT_PR_TITLE_LEN: usize = 72;
+
+// `--model auto` switches to the large model at this many estimated tokens, unless
+// `[auto]` sets a threshold
+const DEFAULT_AUTO_THRESHOLD: usize = 4000;
+
+// Estimated request size (in tokens) above which the user is asked before sending,
+// overridable with GITWISE_TOKEN_WARN; 0 disables the check
+const DEFAULT_TOKEN_WARN: usize = 50_000;
+
+// Seconds to wait for a provider before giving up, overridable with GITWISE_TIMEOUT;
+// 0 waits indefinitely. For streamed responses this applies to each chunk.
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Azure OpenAI API version, overridable with AZURE_OPENAI_API_VERSION
+const DEFAULT_AZURE_API_VERSION: &str = "2024-02-01";
+
+/// Placeholder key for OpenAI-compatible servers that don't check one
+const UNAUTHENTICATED_API_KEY: &str = "unauthenticated";
+
+/// System prompt for `generate_pr_title`; `{max}` is replaced with the length limit
+const PR_TITLE_PROMPT: &str = "You are a helpful AI that writes pull request titles. \
+    Write one title that tells a reviewer scanning a list of pull requests what this one achieves as a whole, \
+    naming the feature, fix or area rather than listing individual edits. \
+    Use imperative mood ('Add' not 'Added'), at most {max} characters, without a trailing period, quotes or a 'Title:' prefix. \
+    Reply with the title only.";
+
+/// System prompt for diff summaries, replaceable with `prompts.diff` in the config file
+const DIFF_PROMPT: &str = "You are a helpful AI that summarizes git diffs. Focus on the key changes and their implications. Be concise but informative.";
+
+/// System prompt for `summarize_api_changes`; a custom prompt is appended to it
+const API_PROMPT: &str = "You are a helpful AI that reviews changes to a library's public API. \
+    You are given only the added (+) and removed (-) declarations of public items, each followed by its file. \
+    Report, under the headings Added, Removed and Changed, the public items that were added, removed, or had their signature changed \
+    (a removal and an addition of the same item). Then list any changes that could break existing callers. \
+    Omit empty headings. Be concise.";
+
+/// Returned by `summarize_api_changes` when no public declarations changed
+const NO_API_CHANGES: &str = "No public API changes.";
+
+/// Diffs with at most this many added and removed lines get a subject-only message
+/// unless a body is asked for
+const ONELINE_MAX_CHANGED_LINES: usize = 3;
+
+/// Longer example messages are cut, so a few huge ones don't crowd out the diff
+const MAX_STYLE_EXAMPLE_CHARS: usize = 1000;
+
+/// Returned by `summarize_diff` when there is nothing to summarize
+pub const NO_CHANGES_SUMMARY: &str = "No changes to summarize.";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelProvider {
+    Anthropic,
+    OpenAI,
+    Azure,
+}
+
+/// Whether generated commit messages get a body under the subject line
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MessageBody {
+    /// Subject only for tiny diffs, otherwise with a body
+    #[default]
+    Auto,
+    /// Subject line only
+    Oneline,
+    /// Always write a body
+    Detailed,
+}
+
+/// Providers in the order they are preferred when none is enforced
+const PROVIDERS: [ModelProvider; 3] = [ModelProvider::Anthropic, ModelProvider::OpenAI, ModelProvider::Azure];
+
+/// Check that a temperature is within the range the providers accept
+fn validate_temperature(temperature: f32) -> Result<f32> {
+    if !(0.0..=MAX_TEMPERATURE).contains(&temperature) {
+        return Err(GitwiseError::InvalidInput(format!("Temperature must be between 0.0 and {:.1}, got {}", MAX_TEMPERATURE, temperature)));
+    }
+    Ok(temperature)
+}
+
+/// OpenAI client configuration, optionally pointed at a compatible server
+fn openai_config(api_key: String, api_base: Option<String>) -> OpenAIConfig {
+    let config = OpenAIConfig::new().with_api_key(api_key);
+    match api_base {
+        Some(base) => config.with_api_base(base.trim_end_matches('/')),
+        None => config,
+    }
+}
+
+/// Anthropic API base URL from `ANTHROPIC_BASE_URL`. The client appends `/v1/...` itself,
+/// so a trailing `/v1` (as some gateways document it) is dropped.
+fn anthropic_api_base(base: &str) -> String {
+    let base = base.trim().trim_end_matches('/');
+    base.strip_suffix("/v1").unwrap_or(base).to_string()
+}
+
+/// Rough token count for English text and code: about four characters per token
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Gitmoji offered to the model in `--emoji` mode, with what each one marks
+const GITMOJI: &[(&str, &str)] = &[
+    ("✨", "new feature"),
+    ("🐛", "bug fix"),
+    ("🚑️", "critical hotfix"),
+    ("♻️", "refactoring"),
+    ("⚡️", "performance improvement"),
+    ("📝", "documentation"),
+    ("✅", "tests"),
+    ("🎨", "code structure or formatting"),
+    ("🔥", "removing code or files"),
+    ("🔧", "configuration"),
+    ("⬆️", "dependency upgrade"),
+    ("🔒️", "security fix"),
+    ("💄", "UI or style changes"),
+    ("🚀", "deployment or release"),
+    ("👷", "CI or build system"),
+];
+
+/// Cut `text` to at most `limit` characters, noting how much was left out; 0 keeps everything
+fn truncate_chars(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) if limit > 0 => format!("{}... ({} more characters)", &text[..end], text[end..].chars().count()),
+        _ => text.to_string(),
+    }
+}
+
+/// Tidy a reply to `PR_TITLE_PROMPT` into a title of at most `max` columns
+fn clean_pr_title(reply: &str, max: usize) -> String {
+    let title = subject_only(reply);
+    let title = title.strip_prefix("Title:").unwrap_or(&title).trim();
+    let title = title.trim_matches(|c| matches!(c, '"' | '\'' | '`' | '*'));
+    let title = truncate_subject(title, max);
+    title.trim_end_matches(|c: char| c.is_whitespace() || ".,;:!".contains(c)).to_string()
+}
+
+/// The first non-empty line of a message, for when the model adds a body it wasn't asked for
+fn subject_only(message: &str) -> String {
+    message.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("").to_string()
+}
+
+/// Shorten the first line of a commit message to `max` columns, cutting at a word boundary if possible
+fn truncate_subject(message: &str, max: usize) -> String {
+    let (subject, rest) = message.split_once('\n').unwrap_or((message, ""));
+    if display_width(subject) <= max {
+        return message.to_string();
+    }
+
+    let mut cut = String::new();
+    for c in subject.chars() {
+        if display_width(&cut) + display_width(c.encode_utf8(&mut [0; 4])) > max {
+            break;
+        }
+        cut.push(c);
+    }
+    // Prefer the end of the last whole word, unless that drops most of the line
+    let at_word_end = subject[cut.len()..].starts_with(char::is_whitespace);
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(i) if !at_word_end && display_width(&cut[..i]) >= max / 2 => &cut[..i],
+        _ => cut.as_str(),
+    };
+    let cut = cut.trim_end_matches(|c: char| c.is_whitespace() || ",;:.-".contains(c));
+
+    if rest.is_empty() {
+        cut.to_string()
+    } else {
+        format!("{}\n{}", cut, rest)
+    }
+}
+
+/// How serious a review comment is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[serde(alias = "critical", alias = "high")]
+    Error,
+    #[serde(alias = "medium")]
+    Warning,
+    #[serde(alias = "low", alias = "suggestion", alias = "nit")]
+    Info,
+}
+
+/// A single code-review comment from `review_diff`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ReviewComment {
+    pub file: String,
+    /// Line in the new version of the file, when the comment refers to one
+    #[serde(default)]
+    pub line: Option<u32>,
+    pub severity: Severity,
+    pub comment: String,
+}
+
+/// Parse a JSON reply, tolerating a surrounding markdown code fence
+fn parse_json_reply<T: serde::de::DeserializeOwned>(response: &str) -> serde_json::Result<T> {
+    let trimmed = response.trim();
+    let unfenced = trimmed.strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|body| body.trim_start_matches("json").trim())
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced)
+}
+
+/// Instruct the model to fill in a pull request template instead of writing free-form prose
+pub fn template_instruction(template: &str) -> String {
+    format!(
+        "Fill in the following pull request template rather than writing free-form prose. \
+         Keep every heading and checkbox exactly as written, put your content under the matching headings, \
+         and leave checkboxes unticked unless the changes clearly satisfy them:\n\n{}\n",
+        template.trim()
+    )
+}
+
+/// A piece of a diff handed to a `format_files` renderer
+enum DiffPart<'a> {
+    /// Start of a file; emitted once per delta
+    File(&'a DiffDelta<'a>),
+    /// A hunk header along with its index within the file
+    Hunk(&'a DiffDelta<'a>, &'a DiffHunk<'a>, usize),
+    /// A single content line
+    Line(&'a DiffDelta<'a>, &'a DiffLine<'a>),
+}
+
+/// Whether either side of a delta holds binary content
+fn is_binary(delta: &DiffDelta<'_>) -> bool {
+    delta.flags().is_binary() || delta.old_file().is_binary() || delta.new_file().is_binary()
+}
+
+/// Display path for a delta, falling back to the old path for deletions
+fn delta_path(delta: &DiffDelta<'_>) -> String {
+    delta.new_file().path()
+        .or_else(|| delta.old_file().path())
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+/// Describe a renamed or copied file, so the model doesn't see a delete plus an add
+fn rename_label(delta: &DiffDelta<'_>) -> Option<String> {
+    let verb = match delta.status() {
+        Delta::Renamed => "renamed",
+        Delta::Copied => "copied",
+        _ => return None,
+    };
+    let old = delta.old_file().path()?;
+    let new = delta.new_file().path()?;
+    Some(format!("({} {} -> {})", verb, old.display(), new.display()))
+}
+
+/// A file proposed for staging by `analyze_changes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSelection {
+    pub path: String,
+    /// Hunk indices to stage, or `None` for the whole file
+    pub hunks: Option<Vec<usize>>,
+}
+
+impl fmt::Display for FileSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.hunks {
+            Some(hunks) => {
+                let hunks: Vec<String> = hunks.iter().map(|h| h.to_string()).collect();
+                write!(f, "{} (hunks {})", self.path, hunks.join(", "))
+            }
+            None => write!(f, "{}", self.path),
+        }
+    }
+}
+
+/// Merge the smallest groups until at most `max` remain. A group's size is its
+/// number of hunks, counting a whole file as one; each merge moves the smallest
+/// group into the next smallest, which keeps its place in the list.
+fn cap_groups(mut groups: Vec<Vec<FileSelection>>, max: usize) -> Vec<Vec<FileSelection>> {
+    let size = |group: &[FileSelection]| -> usize {
+        group.iter().map(|file| file.hunks.as_ref().map_or(1, |hunks| hunks.len().max(1))).sum()
+    };
+    while groups.len() > max.max(1) {
+        let mut by_size: Vec<usize> = (0..groups.len()).collect();
+        by_size.sort_by_key(|&i| size(&groups[i]));
+        let (smallest, target) = (by_size[0], by_size[1]);
+        let merged = groups.remove(smallest);
+        let target = if smallest < target { target - 1 } else { target };
+        for file in merged {
+            match groups[target].iter_mut().find(|existing| existing.path == file.path) {
+                // Staging the whole file covers any hunks of it
+                Some(existing) => existing.hunks = match (existing.hunks.take(), file.hunks) {
+                    (Some(mut hunks), Some(more)) => {
+                        hunks.extend(more);
+                        hunks.sort_unstable();
+                        hunks.dedup();
+                        Some(hunks)
+                    }
+                    _ => None,
+                },
+                None => groups[target].push(file),
+            }
+        }
+    }
+    groups
+}
+
+/// Group entries as returned by the model: a bare path or a file with hunk indices
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawSelection {
+    Path(String),
+    Hunks { file: String, hunks: Vec<usize> },
+}
+
+impl From<RawSelection> for FileSelection {
+    fn from(raw: RawSelection) -> Self {
+        match raw {
+            RawSelection::Path(path) => Self { path, hunks: None },
+            // An empty hunk list can only sensibly mean the whole file
+            RawSelection::Hunks { file, hunks } if hunks.is_empty() => Self { path: file, hunks: None },
+            RawSelection::Hunks { file, hunks } => Self { path: file, hunks: Some(hunks) },
+        }
+    }
+}
+
+/// A failed API request, classified by whether another provider might succeed
+#[derive(Debug)]
+pub struct ApiFailure {
+    message: String,
+    /// The provider was overloaded, rate limited or unreachable, rather than
+    /// rejecting the request or the credentials
+    retryable: bool,
+}
+
+impl ApiFailure {
+    fn anthropic(model: &str, error: anthropic::error::AnthropicError) -> Self {
+        use anthropic::error::AnthropicError;
+        let retryable = match &error {
+            AnthropicError::Reqwest(e) => is_unavailable(e),
+            AnthropicError::ApiError(e) => matches!(e.r#type.as_str(), "overloaded_error" | "api_error" | "rate_limit_error"),
+            AnthropicError::StreamError(e) => is_unavailable_stream(&e.message),
+            _ => false,
+        };
+        Self { message: format!("Anthropic API error (model '{}'): {}", model, error), retryable }
+    }
+
+    fn openai(model: &str, error: async_openai::error::OpenAIError) -> Self {
+        use async_openai::error::OpenAIError;
+        let retryable = match &error {
+            OpenAIError::Reqwest(e) => is_unavailable(e),
+            OpenAIError::ApiError(e) => {
+                matches!(e.r#type.as_deref(), Some("server_error" | "rate_limit_error" | "requests" | "tokens"))
+                    || e.code.as_ref().and_then(|code| code.as_str()) == Some("rate_limit_exceeded")
+            }
+            OpenAIError::StreamError(message) => is_unavailable_stream(message),
+            _ => false,
+        };
+        Self { message: format!("OpenAI API error (model '{}'): {}", model, error), retryable }
+    }
+
+    /// A failed `rate_limit::post_json`, classified by the client library's error type
+    fn post<E>(model: &str, error: PostError, classify: fn(&str, E) -> Self, convert: fn(PostError) -> E) -> Self {
+        let unavailable = error.is_unavailable();
+        let failure = classify(model, convert(error));
+        Self { retryable: failure.retryable || unavailable, ..failure }
+    }
+
+    /// The provider didn't answer within the configured timeout
+    fn timeout(provider: &str, model: &str, timeout: Duration) -> Self {
+        Self {
+            message: format!("{} API request (model '{}') timed out after {}s; raise GITWISE_TIMEOUT to wait longer", provider, model, timeout.as_secs()),
+            retryable: true,
+        }
+    }
+
+    /// Whether the provider was unavailable, so another provider or a later retry may succeed
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+}
+
+impl fmt::Display for ApiFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiFailure {}
+
+/// The error for a fallback request that failed too, naming the first failure
+fn fallback_error(first: &GitwiseError, error: GitwiseError) -> GitwiseError {
+    match error {
+        GitwiseError::ApiError(failure) => GitwiseError::ApiError(ApiFailure {
+            message: format!("Fallback after {}: {}", first, failure.message),
+            retryable: failure.retryable,
+        }),
+        other => other,
+    }
+}
+
+/// Whether a streaming error means the service couldn't be reached, was overloaded or
+/// rate limited the request, going by the event stream's transport and status errors
+fn is_unavailable_stream(message: &str) -> bool {
+    let status = message.strip_prefix("Invalid status code: ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|code| code.parse::<u16>().ok());
+    status.is_some_and(|code| code == 429 || code >= 500)
+        || message.starts_with("error sending request")
+}
+
+/// Whether an HTTP error means the service couldn't be reached or is overloaded
+fn is_unavailable(error: &reqwest::Error) -> bool {
+    error.is_timeout()
+        || error.is_connect()
+        || error.status().is_some_and(|status| status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS)
+}
+
+pub struct AiEngine {
+    openai_client: Option<Client<OpenAIConfig>>,
+    anthropic_client: Option<AnthropicClient>,
+    azure_client: Option<Client<AzureConfig>>,
+    /// Sends the chat completions requests, so rate-limited ones can honor `Retry-After`
+    http: reqwest::Client,
+    /// Azure deployment name, sent as the model
+    azure_deployment: String,
+    enforced_provider: Option<ModelProvider>,
+    ignore: IgnoreRules,
+    language: Option<String>,
+    anthropic_model: String,
+    openai_model: String,
+    temperature: f32,
+    subject_len: usize,
+    body_wrap: usize,
+    pr_title_len: usize,
+    emoji: bool,
+    message_body: MessageBody,
+    token_warn: usize,
+    /// How long to wait for a provider, or `None` to wait indefinitely
+    timeout: Option<Duration>,
+    assume_yes: bool,
+    fallback: bool,
+    /// Replace likely secrets in diffs before they are sent
+    redact: bool,
+    /// System prompts from the config file, used instead of the built-in ones
+    prompts: Prompts,
+    /// Print each prompt to stderr, truncated to this many characters (0 for no limit)
+    show_prompt: Option<usize>,
+    /// Recent commit messages from the repository for commit messages to imitate
+    style_examples: Vec<String>,
+    /// Repository whose submodule checkouts describe submodule pointer moves
+    repo: Option<Repository>,
+    /// Models to choose between by request size (`--model auto`)
+    auto: Option<Auto>,
+    /// `[auto.small]` from the config file, for `polish_text` even without `--model auto`
+    small_model: AutoModel,
+    usage: Mutex<Usage>,
+}
+
+impl AiEngine {
+    /// Create a new AI engine, preferring Claude if available
+    #[allow(dead_code)]
+    pub fn new() -> Result<Self> {
+        Self::from_config(&Config::default())
+    }
+
+    /// Create an AI engine using `config` for anything not set in the environment
+    pub fn from_config(config: &Config) -> Result<Self> {
+        dotenv::dotenv().ok();
+        
+        // Try to create Anthropic client first
+        // A base URL routes requests through a gateway or regional endpoint
+        let anthropic_base = env::var("ANTHROPIC_BASE_URL").ok().filter(|base| !base.trim().is_empty());
+        let anthropic_client = match env::var("ANTHROPIC_API_KEY") {
+            Ok(api_key) => {
+                debug!("Found Anthropic API key");
+                let mut builder = ClientBuilder::default();
+                builder.api_key(api_key);
+                if let Some(base) = &anthropic_base {
+                    debug!("Sending Anthropic requests to {}", base);
+                    builder.api_base(anthropic_api_base(base));
+                }
+                Some(builder
+                    .build()
+                    .map_err(|e| GitwiseError::InvalidInput(format!("Failed to create Anthropic client: {}", e)))?)
+            },
+            Err(_) => {
+                debug!("No Anthropic API key found");
+                None
+            }
+        };
+
+        // Try to create OpenAI client as fallback. A custom base URL points it at any
+        // OpenAI-compatible server; local ones often need no key at all.
+        let openai_base = env::var("OPENAI_BASE_URL").or_else(|_| env::var("OPENAI_API_BASE")).ok()
+            .filter(|base| !base.trim().is_empty());
+        let openai_client = match (env::var("OPENAI_API_KEY"), openai_base) {
+            (Ok(api_key), base) => {
+                debug!("Found OpenAI API key");
+                Some(Client::with_config(openai_config(api_key, base)))
+            },
+            (Err(_), Some(base)) => {
+                debug!("No OpenAI API key found; using {} without authentication", base);
+                Some(Client::with_config(openai_config(UNAUTHENTICATED_API_KEY.to_string(), Some(base))))
+            },
+            (Err(_), None) => {
+                debug!("No OpenAI API key found");
+                None
+            }
+        };
+
+        // Azure OpenAI needs an endpoint, key and deployment together
+        let azure = (env::var("AZURE_OPENAI_ENDPOINT"), env::var("AZURE_OPENAI_API_KEY"), env::var("AZURE_OPENAI_DEPLOYMENT"));
+        let (azure_client, azure_deployment) = match azure {
+            (Ok(endpoint), Ok(api_key), Ok(deployment)) => {
+                debug!("Found Azure OpenAI deployment {}", deployment);
+                let api_version = env::var("AZURE_OPENAI_API_VERSION")
+                    .unwrap_or_else(|_| DEFAULT_AZURE_API_VERSION.to_string());
+                let config = AzureConfig::new()
+                    .with_api_base(endpoint.trim_end_matches('/'))
+                    .with_api_key(api_key)
+                    .with_deployment_id(deployment.clone())
+                    .with_api_version(api_version);
+                (Some(Client::with_config(config)), deployment)
+            },
+            (Ok(_), _, _) => {
+                warn!("AZURE_OPENAI_ENDPOINT is set, but Azure OpenAI also needs AZURE_OPENAI_API_KEY and AZURE_OPENAI_DEPLOYMENT");
+                (None, String::new())
+            },
+            _ => {
+                debug!("No Azure OpenAI endpoint found");
+                (None, String::new())
+            }
+        };
+
+        let temperature = match env::var("GITWISE_TEMPERATURE") {
+            Ok(value) => {
+                let temperature = value.trim().parse::<f32>()
+                    .map_err(|_| GitwiseError::InvalidInput(format!("Invalid GITWISE_TEMPERATURE '{}'", value)))?;
+                validate_temperature(temperature)
+                    .map_err(|e| GitwiseError::InvalidInput(format!("Invalid GITWISE_TEMPERATURE: {}", e)))?
+            }
+            Err(_) => match config.temperature {
+                Some(temperature) => validate_temperature(temperature)
+                    .map_err(|e| GitwiseError::InvalidInput(format!("Invalid temperature in config file: {}", e)))?,
+                None => DEFAULT_TEMPERATURE,
+            },
+        };
+
+        let token_warn = match env::var("GITWISE_TOKEN_WARN") {
+            Ok(value) => value.trim().parse::<usize>()
+                .map_err(|_| GitwiseError::InvalidInput(format!("Invalid GITWISE_TOKEN_WARN '{}'", value)))?,
+            Err(_) => DEFAULT_TOKEN_WARN,
+        };
+
+        let timeout = match env::var("GITWISE_TIMEOUT") {
+            Ok(value) => value.trim().parse::<u64>()
+                .map_err(|_| GitwiseError::InvalidInput(format!("Invalid GITWISE_TIMEOUT '{}': expected a number of seconds", value)))?,
+            Err(_) => DEFAULT_TIMEOUT_SECS,
+        };
+
+        Ok(Self {
+            openai_client,
+            anthropic_client,
+            azure_client,
+            http: reqwest::Client::new(),
+            azure_deployment,
+            enforced_provider: config.provider.clone(),
+            ignore: IgnoreRules::load(&config.ignore),
+            language: env::var("GITWISE_LANG").ok()
+                .or_else(|| config.language.clone())
+                .filter(|lang| !lang.trim().is_empty()),
+            anthropic_model: env::var("ANTHROPIC_MODEL").ok()
+                .or_else(|| config.anthropic_model.clone())
+                .unwrap_or_else(|| DEFAULT_ANTHROPIC_MODEL.to_string()),
+            openai_model: env::var("OPENAI_MODEL").ok()
+                .or_else(|| config.openai_model.clone())
+                .unwrap_or_else(|| DEFAULT_OPENAI_MODEL.to_string()),
+            temperature,
+            subject_len: config.subject_len.unwrap_or(DEFAULT_SUBJECT_LEN).max(1),
+            body_wrap: config.body_wrap.unwrap_or(DEFAULT_BODY_WRAP).max(1),
+            pr_title_len: config.pr_title_len.unwrap_or(DEFAULT_PR_TITLE_LEN).max(1),
+            emoji: false,
+            message_body: MessageBody::Auto,
+            token_warn,
+            timeout: (timeout > 0).then(|| Duration::from_secs(timeout)),
+            assume_yes: false,
+            fallback: true,
+            redact: true,
+            prompts: config.prompts.clone(),
+            show_prompt: None,
+            style_examples: Vec::new(),
+            repo: None,
+            auto: None,
+            small_model: config.auto.small.clone(),
+            usage: Mutex::new(Usage::default()),
+        })
+    }
+
+    /// Set the enforced model provider
+    pub fn with_provider(mut self, provider: ModelProvider) -> Self {
+        self.enforced_provider = Some(provider);
+        self
+    }
+
+    /// Use the given model name regardless of provider
+    pub fn with_model_name(mut self, model: String) -> Self {
+        self.anthropic_model = model.clone();
+        self.openai_model = model;
+        self
+    }
+
+    /// Set the sampling temperature; must be between 0.0 and 2.0
+    pub fn with_temperature(mut self, temperature: f32) -> Result<Self> {
+        self.temperature = validate_temperature(temperature)?;
+        Ok(self)
+    }
+
+    /// Pick the provider and model for each request by its estimated size (`--model auto`)
+    pub fn with_auto_models(mut self, auto: Auto) -> Self {
+        self.auto = Some(auto);
+        self
+    }
+
+    /// Maximum length of generated commit subject lines
+    pub fn with_subject_len(mut self, subject_len: usize) -> Self {
+        self.subject_len = subject_len.max(1);
+        self
+    }
+
+    /// Column at which generated commit message bodies are wrapped
+    pub fn with_body_wrap(mut self, body_wrap: usize) -> Self {
+        self.body_wrap = body_wrap.max(1);
+        self
+    }
+
+    /// Maximum length of generated pull request titles
+    pub fn with_pr_title_len(mut self, pr_title_len: usize) -> Self {
+        self.pr_title_len = pr_title_len.max(1);
+        self
+    }
+
+    /// Prefix generated commit subjects with a gitmoji
+    pub fn with_emoji(mut self, emoji: bool) -> Self {
+        self.emoji = emoji;
+        self
+    }
+
+    /// Whether generated commit messages get a body (`--oneline`, `--detailed`)
+    pub fn with_message_body(mut self, message_body: MessageBody) -> Self {
+        self.message_body = message_body;
+        self
+    }
+
+    /// Show these commit messages as examples of the repository's style when writing new ones
+    pub fn with_style_examples(mut self, examples: Vec<String>) -> Self {
+        self.style_examples = examples;
+        self
+    }
+
+    /// Describe submodule pointer moves in diffs by the commits in `repo`'s submodule checkouts
+    pub fn with_repo(mut self, repo: Repository) -> Self {
+        self.repo = Some(repo);
+        self
+    }
+
+    /// Send large requests without asking for confirmation
+    pub fn with_assume_yes(mut self, assume_yes: bool) -> Self {
+        self.assume_yes = assume_yes;
+        self
+    }
+
+    /// Whether to retry on the other provider when the preferred one is unavailable
+    pub fn with_fallback(mut self, fallback: bool) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Print every prompt to stderr, cut to `limit` characters unless it is 0 (`--show-prompt`)
+    pub fn with_show_prompt(mut self, limit: usize) -> Self {
+        self.show_prompt = Some(limit);
+        self
+    }
+
+    /// Send diffs as-is instead of redacting likely secrets (`--no-redact`)
+    pub fn with_redact(mut self, redact: bool) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    /// Set the language generated text should be written in
+    pub fn with_language(mut self, language: String) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    /// Few-shot examples of the repository's commit messages, if `--learn-style` provided any
+    fn style_instruction(&self) -> String {
+        if self.style_examples.is_empty() {
+            return String::new();
+        }
+        let examples: Vec<String> = self.style_examples.iter()
+            .map(|message| format!("<example>\n{}\n</example>", truncate_chars(message, MAX_STYLE_EXAMPLE_CHARS)))
+            .collect();
+        format!(
+            "\n\nMatch the style of this repository's recent commit messages below: their tone, structure, prefixes \
+             (such as a component or type before the subject), capitalization and level of detail. \
+             Where they conflict with the rules above, keep to the length limits but follow the examples' format. \
+             Describe only the changes in the diff, never the examples' content.\n{}",
+            examples.join("\n")
+        )
+    }
+
+    /// Extra system-prompt instruction for non-English output
+    fn language_instruction(&self) -> String {
+        let lang = match self.language.as_deref().map(str::trim) {
+            Some(lang) if !lang.is_empty() => lang,
+            _ => return String::new(),
+        };
+        if lang.eq_ignore_ascii_case("english") || lang.eq_ignore_ascii_case("en") {
+            return String::new();
+        }
+
+        format!(
+            " Write your response in {}, but keep code identifiers, file paths, and commands exactly as they appear in the diff.",
+            lang
+        )
+    }
+
+    /// The provider `generate_text` will dispatch to, if any is configured
+    pub fn active_provider(&self) -> Option<ModelProvider> {
+        match &self.enforced_provider {
+            Some(provider) => Some(provider.clone()).filter(|provider| self.has_client(provider)),
+            None => PROVIDERS.into_iter().find(|provider| self.has_client(provider)),
+        }
+    }
+
+    /// Whether a request could be sent: the enforced provider, or any provider, is configured
+    pub fn has_provider(&self) -> bool {
+        self.active_provider().is_some()
+    }
+
+    /// Fail unless `has_provider`, so commands stop before doing work for the AI
+    pub fn require_provider(&self) -> Result<()> {
+        match self.has_provider() {
+            true => Ok(()),
+            false => Err(self.no_provider_error()),
+        }
+    }
+
+    fn no_provider_error(&self) -> GitwiseError {
+        info!("No AI provider available");
+        let setup = match &self.enforced_provider {
+            Some(ModelProvider::Anthropic) => "the Anthropic provider was selected, but ANTHROPIC_API_KEY is not set",
+            Some(ModelProvider::OpenAI) => "the OpenAI provider was selected, but neither OPENAI_API_KEY nor OPENAI_BASE_URL is set",
+            Some(ModelProvider::Azure) => "the Azure provider was selected, but AZURE_OPENAI_ENDPOINT, AZURE_OPENAI_API_KEY and AZURE_OPENAI_DEPLOYMENT are not all set",
+            None => "please set ANTHROPIC_API_KEY or OPENAI_API_KEY (or the AZURE_OPENAI_* environment variables)",
+        };
+        GitwiseError::NoProvider(setup.to_string())
+    }
+
+    /// Every provider with a configured client, in order of preference
+    pub fn configured_providers(&self) -> Vec<ModelProvider> {
+        PROVIDERS.into_iter().filter(|provider| self.has_client(provider)).collect()
+    }
+
+    /// The model requests to `provider` use
+    pub fn model_name(&self, provider: &ModelProvider) -> &str {
+        match provider {
+            ModelProvider::Anthropic => &self.anthropic_model,
+            ModelProvider::OpenAI => &self.openai_model,
+            ModelProvider::Azure => &self.azure_deployment,
+        }
+    }
+
+    /// Identify the model configuration, the diff context (`-U`) and the settings that shape
+    /// the output for cache keys, so cached output is not reused after changing any of them
+    pub fn cache_key(&self) -> String {
+        let language = self.language.as_deref().unwrap_or("");
+        let context = crate::git::context_lines();
+        let settings = format!(
+            "{:?}|{}|{}|{}|{}|{}|{:?}|{:?}",
+            self.prompts, self.temperature, self.redact, self.subject_len, self.body_wrap, self.emoji, self.message_body, self.style_examples,
+        );
+        let settings = utils::fnv1a(settings.as_bytes());
+        if let Some(auto) = &self.auto {
+            return format!("auto:{:?}:{:?}:{}:U{}:{:016x}", self.active_provider(), auto, language, context, settings);
+        }
+        let model = self.active_provider()
+            .map(|provider| self.model_name(&provider))
+            .unwrap_or("");
+        format!("{:?}:{}:{}:U{}:{:016x}", self.active_provider(), model, language, context, settings)
+    }
+
+    /// Ask before sending `text` if it is estimated to exceed the GITWISE_TOKEN_WARN threshold
+    fn check_request_size(&self, text: &str) -> Result<()> {
+        let tokens = estimate_tokens(text);
+        if self.token_warn == 0 || tokens <= self.token_warn {
+            return Ok(());
+        }
+
+        warn!("This request is roughly {} tokens, above the GITWISE_TOKEN_WARN threshold of {}", tokens, self.token_warn);
+        if self.assume_yes || crate::utils::confirm("Send it to the AI provider anyway?")? {
+            return Ok(());
+        }
+        Err(GitwiseError::Aborted(format!("Aborted a request of roughly {} tokens; pass --yes or raise GITWISE_TOKEN_WARN to send it", tokens)))
+    }
+
+    /// Tokens used by every request made through this engine so far
+    pub fn usage(&self) -> Usage {
+        *self.usage.lock().unwrap()
+    }
+
+    fn record_usage(&self, model: &str, prompt_tokens: u64, completion_tokens: u64) {
+        debug!("{} used {} prompt and {} completion tokens", model, prompt_tokens, completion_tokens);
+        self.usage.lock().unwrap().add(model, prompt_tokens, completion_tokens);
+    }
+
+    /// Helper to generate text using available AI provider
+    pub async fn generate_text(&self, system_prompt: &str, user_message: &str) -> Result<String> {
+        let (text, provider) = self.generate_text_with_provider(system_prompt, user_message).await?;
+        debug!("Response generated by {:?}", provider);
+        Ok(text)
+    }
+
+    /// Like `generate_text`, but also returns the provider that produced the response.
+    /// If the preferred provider is unavailable (overloaded, rate limited, unreachable)
+    /// and the other one is configured, the request is retried there unless fallback is disabled.
+    pub async fn generate_text_with_provider(&self, system_prompt: &str, user_message: &str) -> Result<(String, ModelProvider)> {
+        self.generate_with_fallback(self.route(system_prompt, user_message), system_prompt, user_message).await
+    }
+
+    /// Send a request to `target`, falling back to the other provider like `generate_text_with_provider`
+    async fn generate_with_fallback(&self, target: Option<(ModelProvider, String)>, system_prompt: &str, user_message: &str) -> Result<(String, ModelProvider)> {
+        self.log_prompt(system_prompt, user_message);
+
+        let Some((provider, model)) = target else {
+            return Err(self.no_provider_error());
+        };
+
+        let error = match self.generate_with(&provider, &model, system_prompt, user_message).await {
+            Ok(text) => return Ok((text, provider)),
+            Err(error) => error,
+        };
+
+        let Some(fallback) = self.fallback_for(&provider, &error) else {
+            return Err(error);
+        };
+        warn!("{}; falling back to {:?}", error, fallback);
+        let text = self.generate_with(&fallback, self.model_name(&fallback), system_prompt, user_message).await
+            .map_err(|e| fallback_error(&error, e))?;
+        Ok((text, fallback))
+    }
+
+    /// The provider to retry on after `provider` failed with `error`: the other configured
+    /// one, if the failure means `provider` was unavailable and fallback is enabled
+    fn fallback_for(&self, provider: &ModelProvider, error: &GitwiseError) -> Option<ModelProvider> {
+        let retryable = matches!(error, GitwiseError::ApiError(failure) if failure.is_retryable());
+        PROVIDERS.into_iter()
+            .find(|other| other != provider && self.has_client(other))
+            .filter(|_| self.fallback && retryable)
+    }
+
+    /// Provider and model for a request: with `--model auto`, the `[auto]` choice for its
+    /// estimated size; otherwise the active provider and its model
+    fn route(&self, system_prompt: &str, user_message: &str) -> Option<(ModelProvider, String)> {
+        let Some(auto) = &self.auto else {
+            let provider = self.active_provider()?;
+            let model = self.model_name(&provider).to_string();
+            return Some((provider, model));
+        };
+
+        let tokens = estimate_tokens(system_prompt) + estimate_tokens(user_message);
+        let small = tokens < auto.threshold.unwrap_or(DEFAULT_AUTO_THRESHOLD);
+        let size = if small { "small" } else { "large" };
+        let (provider, model) = self.auto_choice(if small { &auto.small } else { &auto.large }, size)?;
+        debug!("Request is roughly {} tokens; using the {} model {} on {:?}", tokens, size, model, provider);
+        Some((provider, model))
+    }
+
+    /// Provider and model `[auto.<size>]` picks, defaulting to the active provider and
+    /// its configured model
+    fn auto_choice(&self, choice: &AutoModel, size: &str) -> Option<(ModelProvider, String)> {
+
+        let provider = match &choice.provider {
+            Some(provider) if self.has_client(provider) => provider.clone(),
+            Some(provider) => {
+                warn!("[auto.{}] names {:?}, which is not configured; using the default provider", size, provider);
+                self.active_provider()?
+            }
+            None => self.active_provider()?,
+        };
+        // A model only applies to the provider it was named for
+        let named_model = choice.model.clone()
+            .filter(|_| choice.provider.as_ref().is_none_or(|named| *named == provider));
+        let model = match (named_model, &provider) {
+            (_, ModelProvider::Azure) => self.azure_deployment.clone(),
+            (Some(model), _) => model,
+            (None, _) => self.model_name(&provider).to_string(),
+        };
+        Some((provider, model))
+    }
+
+    fn has_client(&self, provider: &ModelProvider) -> bool {
+        match provider {
+            ModelProvider::Anthropic => self.anthropic_client.is_some(),
+            ModelProvider::OpenAI => self.openai_client.is_some(),
+            ModelProvider::Azure => self.azure_client.is_some(),
+        }
+    }
+
+    /// Await a provider call, failing with a retryable `ApiFailure` if it exceeds the timeout.
+    /// Rate-limited retries of non-streaming requests happen inside this window.
+    async fn with_timeout<T>(&self, provider: &str, model: &str, request: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(timeout) = self.timeout else {
+            return request.await;
+        };
+        tokio::time::timeout(timeout, request).await
+            .map_err(|_| ApiFailure::timeout(provider, model, timeout))?
+    }
+
+    /// When a request starting now times out, for waits inside `with_timeout`
+    fn deadline(&self) -> Option<std::time::Instant> {
+        self.timeout.map(|timeout| std::time::Instant::now() + timeout)
+    }
+
+    /// Log the assembled prompt, and print it to stderr with `--show-prompt`
+    fn log_prompt(&self, system_prompt: &str, user_message: &str) {
+        debug!("System prompt: {}", system_prompt);
+        debug!("User message: {}", user_message);
+        if let Some(limit) = self.show_prompt {
+            eprintln!("{}", utils::paint(utils::GRAY, format!("--- system prompt ---\n{}\n--- user message ---\n{}\n---", truncate_chars(system_prompt, limit), truncate_chars(user_message, limit))));
+        }
+    }
+
+    /// Send a request to `provider` if given, bypassing the enforced provider and fallback;
+    /// otherwise behave like `generate_text`
+    async fn generate_on(&self, provider: Option<&ModelProvider>, system_prompt: &str, user_message: &str) -> Result<String> {
+        match provider {
+            Some(provider) => {
+                self.log_prompt(system_prompt, user_message);
+                self.generate_with(provider, self.model_name(provider), system_prompt, user_message).await
+            }
+            None => self.generate_text(system_prompt, user_message).await,
+        }
+    }
+
+    /// Send the smallest possible request to `provider`, to check the key and connection
+    pub async fn ping(&self, provider: &ModelProvider) -> Result<()> {
+        self.generate_on(Some(provider), "You are a health check.", "Reply with OK.").await?;
+        Ok(())
+    }
+
+    /// Send a request to one specific provider and model. Azure always uses its deployment.
+    async fn generate_with(&self, provider: &ModelProvider, model: &str, system_prompt: &str, user_message: &str) -> Result<String> {
+        match (provider, &self.anthropic_client, &self.openai_client, &self.azure_client) {
+            (ModelProvider::Anthropic, Some(client), _, _) => {
+                info!("Using Anthropic's Claude model");
+                self.generate_anthropic(client, model, system_prompt, user_message).await
+            },
+            (ModelProvider::OpenAI, _, Some(client), _) => {
+                info!("Using OpenAI's GPT model");
+                self.generate_openai(client, model, system_prompt, user_message).await
+            },
+            (ModelProvider::Azure, _, _, Some(client)) => {
+                info!("Using Azure OpenAI deployment {}", self.azure_deployment);
+                self.generate_openai(client, &self.azure_deployment, system_prompt, user_message).await
+            },
+            _ => Err(GitwiseError::NoProvider(format!("no {:?} API key configured", provider))),
+        }
+    }
+
+    /// Like `generate_text`, but calls `on_token` with each piece of text as it arrives.
+    /// Returns the full response. If the provider fails before the first token, the request
+    /// falls back to the other one like `generate_text` does. The streaming APIs don't report
+    /// token usage, so streamed requests are not counted in `usage()`.
+    pub async fn generate_text_streaming<F>(&self, system_prompt: &str, user_message: &str, mut on_token: F) -> Result<String>
+    where
+        F: FnMut(&str),
+    {
+        self.log_prompt(system_prompt, user_message);
+
+        let Some((provider, model)) = self.route(system_prompt, user_message) else {
+            return Err(self.no_provider_error());
+        };
+        let mut streamed = false;
+        let result = self.stream_with(&provider, &model, system_prompt, user_message, |token| {
+            streamed = true;
+            on_token(token);
+        }).await;
+        let error = match result {
+            Ok(text) => return Ok(text),
+            Err(error) => error,
+        };
+
+        // Text already shown can't be taken back, so only a failure before it falls back
+        let Some(fallback) = self.fallback_for(&provider, &error).filter(|_| !streamed) else {
+            return Err(error);
+        };
+        warn!("{}; falling back to {:?}", error, fallback);
+        self.stream_with(&fallback, self.model_name(&fallback), system_prompt, user_message, on_token).await
+            .map_err(|e| fallback_error(&error, e))
+    }
+
+    /// Stream a response from one specific provider and model. Azure always uses its deployment.
+    async fn stream_with<F>(&self, provider: &ModelProvider, model: &str, system_prompt: &str, user_message: &str, on_token: F) -> Result<String>
+    where
+        F: FnMut(&str),
+    {
+        match (provider, &self.anthropic_client, &self.openai_client, &self.azure_client) {
+            (ModelProvider::Anthropic, Some(client), _, _) => {
+                info!("Using Anthropic's Claude model");
+                self.stream_anthropic(client, model, system_prompt, user_message, on_token).await
+            },
+            (ModelProvider::OpenAI, _, Some(client), _) => {
+                info!("Using OpenAI's GPT model");
+                self.stream_openai(client, model, system_prompt, user_message, on_token).await
+            },
+            (ModelProvider::Azure, _, _, Some(client)) => {
+                info!("Using Azure OpenAI deployment {}", self.azure_deployment);
+                self.stream_openai(client, &self.azure_deployment, system_prompt, user_message, on_token).await
+            },
+            _ => Err(GitwiseError::NoProvider(format!("no {:?} API key configured", provider))),
+        }
+    }
+
+    fn anthropic_request(&self, model: &str, system_prompt: &str, user_message: &str) -> MessagesRequest {
+        MessagesRequest {
+            model: model.to_string(),
+            system: system_prompt.to_string(),
+            messages: vec![
+                Message {
+                    role: AnthropicRole::User,
+                    content: vec![ContentBlock::Text { text: user_message.to_string() }],
+                }
+            ],
+            max_tokens: ANTHROPIC_MAX_TOKENS,
+            temperature: Some(self.temperature.min(ANTHROPIC_MAX_TEMPERATURE) as f64),
+            ..Default::default()
+        }
+    }
+
+    /// Send a single request to Anthropic's messages API
+    async fn generate_anthropic(&self, client: &AnthropicClient, model: &str, system_prompt: &str, user_message: &str) -> Result<String> {
+        let request = self.anthropic_request(model, system_prompt, user_message);
+
+        debug!("Sending request to Anthropic API (model {})", model);
+        let response: MessagesResponse = self.with_timeout("Anthropic", model, async {
+            rate_limit::post_json("Anthropic", self.deadline(), || {
+                client.http_client.post(format!("{}/v1/messages", client.api_base()))
+                    .bearer_auth(client.api_key())
+                    .headers(client.headers())
+                    .json(&request)
+            }).await
+                .map_err(|e| ApiFailure::post(model, e, ApiFailure::anthropic, PostError::into_anthropic).into())
+        }).await?;
+        
+        debug!("Received response from Anthropic API");
+        self.record_usage(model, response.usage.input_tokens as u64, response.usage.output_tokens as u64);
+        let text = response.content.into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(text)
+    }
+
+    /// Stream a response from Anthropic's messages API
+    async fn stream_anthropic<F>(&self, client: &AnthropicClient, model: &str, system_prompt: &str, user_message: &str, mut on_token: F) -> Result<String>
+    where
+        F: FnMut(&str),
+    {
+        let request = MessagesRequest {
+            stream: true,
+            ..self.anthropic_request(model, system_prompt, user_message)
+        };
+
+        debug!("Streaming from Anthropic API (model {})", model);
+        let api_error = |e| GitwiseError::from(ApiFailure::anthropic(model, e));
+        let mut stream = self.with_timeout("Anthropic", model, async {
+            client.messages_stream(request).await.map_err(api_error)
+        }).await?;
+
+        let mut text = String::new();
+        while let Some(event) = self.with_timeout("Anthropic", model, async { Ok(stream.next().await) }).await? {
+            if let MessagesStreamEvent::ContentBlockDelta { delta: ContentBlockDelta::TextDelta { text: token }, .. } = event.map_err(api_error)? {
+                on_token(&token);
+                text.push_str(&token);
+            }
+        }
+        debug!("Finished streaming from Anthropic API");
+        Ok(text)
+    }
+
+    fn openai_request(&self, model: &str, system_prompt: &str, user_message: &str) -> CreateChatCompletionRequest {
+        let messages = vec![
+            ChatCompletionRequestSystemMessage {
+                content: Some(system_prompt.to_string()),
+                name: None,
+                role: Role::System,
+            }.into(),
+            ChatCompletionRequestUserMessage {
+                content: Some(ChatCompletionRequestUserMessageContent::Text(
+                    user_message.to_string()
+                )),
+                name: None,
+                role: Role::User,
+            }.into(),
+        ];
+
+        CreateChatCompletionRequest {
+            model: model.to_string(),
+            messages,
+            temperature: Some(self.temperature),
+            max_tokens: Some(OPENAI_MAX_TOKENS),
+            ..Default::default()
+        }
+    }
+
+    /// Send a single request to a chat completions API (OpenAI or Azure OpenAI)
+    async fn generate_openai<C: async_openai::config::Config>(&self, client: &Client<C>, model: &str, system_prompt: &str, user_message: &str) -> Result<String> {
+        let request = self.openai_request(model, system_prompt, user_message);
+
+        debug!("Sending request to OpenAI API (model {})", model);
+        let config = client.config();
+        let response: CreateChatCompletionResponse = self.with_timeout("OpenAI", model, async {
+            rate_limit::post_json("OpenAI", self.deadline(), || {
+                self.http.post(config.url("/chat/completions"))
+                    .query(&config.query())
+                    .headers(config.headers())
+                    .json(&request)
+            }).await
+                .map_err(|e| ApiFailure::post(model, e, ApiFailure::openai, PostError::into_openai).into())
+        }).await?;
+        debug!("Received response from OpenAI API");
+        if let Some(usage) = &response.usage {
+            self.record_usage(model, usage.prompt_tokens as u64, usage.completion_tokens as u64);
+        }
+        Ok(response.choices[0]
+            .message
+            .content
+            .clone()
+            .unwrap_or_else(|| "No response available.".to_string()))
+    }
+
+    /// Stream a response from a chat completions API (OpenAI or Azure OpenAI)
+    async fn stream_openai<C, F>(&self, client: &Client<C>, model: &str, system_prompt: &str, user_message: &str, mut on_token: F) -> Result<String>
+    where
+        C: async_openai::config::Config,
+        F: FnMut(&str),
+    {
+        let request = CreateChatCompletionRequest {
+            stream: Some(true),
+            ..self.openai_request(model, system_prompt, user_message)
+        };
+
+        debug!("Streaming from OpenAI API (model {})", model);
+        let api_error = |e| GitwiseError::from(ApiFailure::openai(model, e));
+        let mut stream = self.with_timeout("OpenAI", model, async {
+            client.chat().create_stream(request).await.map_err(api_error)
+        }).await?;
+
+        let mut text = String::new();
+        while let Some(chunk) = self.with_timeout("OpenAI", model, async { Ok(stream.next().await) }).await? {
+            for choice in chunk.map_err(api_error)?.choices {
+                if let Some(token) = choice.delta.content {
+                    on_token(&token);
+                    text.push_str(&token);
+                }
+            }
+        }
+        debug!("Finished streaming from OpenAI API");
+        Ok(text)
+    }
+
+    /// Render a diff into text for the model, one chunk per file in diff order.
+    /// Files excluded by `.gitwiseignore` produce no chunk and are recorded in `skipped`,
+    /// binary files are reduced to a one-line note, submodule pointer changes are described
+    /// by the commits they move across (when `with_repo` gave a repository to find them
+    /// in), and likely secrets are redacted.
+    fn format_files<F>(&self, diff: &Diff<'_>, skipped: &mut SkippedFiles, render: F) -> Result<Vec<String>>
+    where
+        F: FnMut(DiffPart<'_>) -> Option<String>,
+    {
+        struct State<'s, F> {
+            files: Vec<Option<String>>,
+            hunk_index: usize,
+            /// Whether the current file is described by a note instead of its lines
+            opaque: bool,
+            skipped: &'s mut SkippedFiles,
+            render: F,
+        }
+
+        let state = RefCell::new(State { files: Vec::new(), hunk_index: 0, opaque: false, skipped, render });
+
+        diff.foreach(
+            &mut |delta, _progress| {
+                let state = &mut *state.borrow_mut();
+                state.hunk_index = 0;
+                state.opaque = is_binary(&delta) || is_submodule(&delta);
+                if self.ignore.skip_file(&delta, state.skipped) {
+                    state.files.push(None);
+                } else if is_submodule(&delta) {
+                    state.files.push(Some(describe_submodule_change(self.repo.as_ref(), &delta)));
+                } else if state.opaque {
+                    state.files.push(Some(format!("(binary file changed: {})\n", delta_path(&delta))));
+                } else {
+                    let header = (state.render)(DiffPart::File(&delta)).unwrap_or_default();
+                    state.files.push(Some(header));
+                }
+                true
+            },
+            None,
+            Some(&mut |delta, hunk| {
+                let state = &mut *state.borrow_mut();
+                if state.opaque {
+                    return true;
+                }
+                let index = state.hunk_index;
+                state.hunk_index += 1;
+                if let Some(Some(text)) = state.files.last_mut() {
+                    if let Some(rendered) = (state.render)(DiffPart::Hunk(&delta, &hunk, index)) {
+                        text.push_str(&rendered);
+                    }
+                }
+                true
+            }),
+            Some(&mut |delta, _hunk, line| {
+                let state = &mut *state.borrow_mut();
+                match state.files.last_mut() {
+                    // Raw binary content would only be mojibake in the prompt, and a
+                    // submodule's "Subproject commit" lines are just ids
+                    Some(Some(_)) if state.opaque => (),
+                    Some(Some(text)) => {
+                        if let Some(rendered) = (state.render)(DiffPart::Line(&delta, &line)) {
+                            text.push_str(&rendered);
+                        }
+                    }
+                    _ => state.skipped.record_line(line.origin_value()),
+                }
+                true
+            }),
+        )?;
+
+        let files: Vec<String> = state.into_inner().files.into_iter().flatten().collect();
+        if !self.redact {
+            return Ok(files);
+        }
+        let mut redactions = 0;
+        let files = files.into_iter()
+            .map(|text| {
+                let (text, count) = redact::redact(&text);
+                redactions += count;
+                text
+            })
+            .collect();
+        if redactions > 0 {
+            debug!("Redacted {} likely secret(s) before sending the diff", redactions);
+        }
+        Ok(files)
+    }
+
+    /// Render a whole diff with `format_files`, noting any ignored files at the end
+    fn format_diff_text<F>(&self, diff: &Diff<'_>, render: F) -> Result<String>
+    where
+        F: FnMut(DiffPart<'_>) -> Option<String>,
+    {
+        let mut skipped = SkippedFiles::default();
+        let mut text = self.format_files(diff, &mut skipped, render)?.concat();
+        if !skipped.is_empty() {
+            text.push_str(&skipped.summary());
+        }
+        Ok(text)
+    }
+
+    /// Render a diff as plain patch lines with rename notes
+    fn patch_text(&self, diff: &Diff<'_>) -> Result<String> {
+        self.format_diff_text(diff, |part| match part {
+            DiffPart::File(delta) => rename_label(delta).map(|label| format!("{}\n", label)),
+            DiffPart::Hunk(..) => None,
+            DiffPart::Line(_, line) => match line.origin_value() {
+                DiffLineType::Addition => Some(format!("+{}", String::from_utf8_lossy(line.content()))),
+                DiffLineType::Deletion => Some(format!("-{}", String::from_utf8_lossy(line.content()))),
+                DiffLineType::Context => Some(format!(" {}", String::from_utf8_lossy(line.content()))),
+                _ => None,
+            },
+        })
+    }
+
+    /// Summarize a git diff using AI
+    pub async fn summarize_diff(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<String> {
+        if diff.deltas().len() == 0 {
+            return Ok(NO_CHANGES_SUMMARY.to_string());
+        }
+        self.require_provider()?;
+
+        let (prompt, message) = self.summary_prompts(diff, custom_prompt)?;
+        let _spinner = Spinner::start("Summarizing diff…");
+        self.generate_text(&prompt, &message).await
+    }
+
+    /// Summarize a git diff, passing the summary to `on_token` as it is generated
+    pub async fn summarize_diff_streaming<F>(&self, diff: &Diff<'_>, custom_prompt: Option<&str>, mut on_token: F) -> Result<String>
+    where
+        F: FnMut(&str),
+    {
+        if diff.deltas().len() == 0 {
+            on_token(NO_CHANGES_SUMMARY);
+            return Ok(NO_CHANGES_SUMMARY.to_string());
+        }
+        self.require_provider()?;
+
+        let (prompt, message) = self.summary_prompts(diff, custom_prompt)?;
+        // Only until the first words arrive
+        let mut spinner = Some(Spinner::start("Summarizing diff…"));
+        self.generate_text_streaming(&prompt, &message, |token| {
+            spinner.take();
+            on_token(token);
+        }).await
+    }
 
-pub struct AiEngine {
-    openai_client: Option<Client<OpenAIConfig>>,
-    anthropic_client: Option<AnthropicClient>,
-    enforced_provider: Option<ModelProvider>,
-}
+    /// System prompt and user message for summarizing a diff
+    fn summary_prompts(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<(String, String)> {
+        let diff_text = self.patch_text(diff)?;
+        self.check_request_size(&diff_text)?;
 
-impl AiEngine {
-    /// Create a new AI engine, preferring Claude if available
-    pub fn new() -> Result<Self> {
-        dotenv::dotenv().ok();
-        
-        // Try to create Anthropic client first
-        let anthropic_client = match env::var("ANTHROPIC_API_KEY") {
-            Ok(api_key) => {
-                debug!("Found Anthropic API key");
-                Some(ClientBuilder::default()
-                    .api_key(api_key)
-                    .build()
-                    .context("Failed to create Anthropic client")?)
-            },
-            Err(_) => {
-                debug!("No Anthropic API key found");
-                None
-            }
+        let base_prompt = self.prompts.diff.as_deref().unwrap_or(DIFF_PROMPT);
+        let mut prompt = if let Some(custom) = custom_prompt {
+            format!("{}. Additional instruction: {}", base_prompt, custom)
+        } else {
+            base_prompt.to_string()
         };
+        prompt.push_str(&self.language_instruction());
 
-        // Try to create OpenAI client as fallback
-        let openai_client = match env::var("OPENAI_API_KEY") {
-            Ok(api_key) => {
-                debug!("Found OpenAI API key");
-                Some(Client::with_config(OpenAIConfig::new().with_api_key(api_key)))
-            },
-            Err(_) => {
-                debug!("No OpenAI API key found");
+        let file_types = summary::file_type_line(&summary::file_types(diff)?);
+        Ok((prompt, format!("Please summarize this git diff.\n{}\n```\n{}\n```", file_types, diff_text)))
+    }
+
+    /// Summarize only the changes to public declarations in `language`, calling out
+    /// additions, removals and signature changes
+    pub async fn summarize_api_changes(&self, diff: &Diff<'_>, language: Language, custom_prompt: Option<&str>) -> Result<String> {
+        self.require_provider()?;
+        // The hunk header names the enclosing section, e.g. a public trait
+        let mut context = String::new();
+        let mut skipped = SkippedFiles::default();
+        let changes = self.format_files(diff, &mut skipped, |part| match part {
+            DiffPart::File(_) => None,
+            DiffPart::Hunk(_, hunk, _) => {
+                let header = String::from_utf8_lossy(hunk.header());
+                context = header.splitn(3, "@@").nth(2).unwrap_or("").trim().to_string();
                 None
             }
+            DiffPart::Line(delta, line) => {
+                let text = String::from_utf8_lossy(line.content());
+                let sign = match line.origin_value() {
+                    DiffLineType::Addition => '+',
+                    DiffLineType::Deletion => '-',
+                    _ => return None,
+                };
+                language.is_public_item(&text, &context)
+                    .then(|| format!("{} {} ({})\n", sign, text.trim(), delta_path(delta)))
+            }
+        })?.concat();
+
+        if changes.is_empty() {
+            return Ok(NO_API_CHANGES.to_string());
+        }
+        self.check_request_size(&changes)?;
+
+        let mut prompt = match custom_prompt {
+            Some(custom) => format!("{} Additional instruction: {}", API_PROMPT, custom),
+            None => API_PROMPT.to_string(),
         };
+        prompt.push_str(&self.language_instruction());
 
-        Ok(Self {
-            openai_client,
-            anthropic_client,
-            enforced_provider: None,
-        })
+        let _spinner = Spinner::start("Summarizing API changes…");
+        self.generate_text(&prompt, &format!("Public {:?} declarations changed in this diff:\n```\n{}```", language, changes)).await
     }
 
-    /// Set the enforced model provider
-    pub fn with_provider(mut self, provider: ModelProvider) -> Self {
-        self.enforced_provider = Some(provider);
-        self
+    /// Generate a commit message for the given diff
+    pub async fn generate_commit_message(&self, diff: &Diff<'_>) -> Result<String> {
+        self.generate_commit_message_with(diff, None).await
     }
 
-    /// Helper to generate text using available AI provider
-    pub async fn generate_text(&self, system_prompt: &str, user_message: &str) -> Result<String> {
-        debug!("Generating text with system prompt: {}", system_prompt);
-        debug!("User message: {}", user_message);
+    /// Like `generate_commit_message`, but asks `provider` (when given) regardless of
+    /// the enforced provider, so several providers can be compared on the same diff
+    pub async fn generate_commit_message_with(&self, diff: &Diff<'_>, provider: Option<&ModelProvider>) -> Result<String> {
+        if provider.is_none() {
+            self.require_provider()?;
+        }
+        let Some((prompt, request)) = self.commit_message_request(diff)? else {
+            return Ok("No changes detected.".to_string());
+        };
+        self.check_request_size(&request)?;
 
-        match (self.enforced_provider.as_ref(), &self.anthropic_client, &self.openai_client) {
-            // Enforced Anthropic
-            (Some(ModelProvider::Anthropic), Some(client), _) => {
-                info!("Using Anthropic's Claude model");
-                let request = MessagesRequest {
-                    model: "claude-3-sonnet-20240229".to_string(),
-                    system: system_prompt.to_string(),
-                    messages: vec![
-                        Message {
-                            role: AnthropicRole::User,
-                            content: vec![ContentBlock::Text { text: user_message.to_string() }],
-                        }
-                    ],
-                    max_tokens: ANTHROPIC_MAX_TOKENS,
-                    ..Default::default()
-                };
+        let oneline = self.wants_oneline(diff)?;
 
-                debug!("Sending request to Anthropic API");
-                let response = client.messages(request).await
-                    .map_err(|e| anyhow::anyhow!("Anthropic API error: {}", e))?;
-                
-                debug!("Received response from Anthropic API");
-                let text = response.content.into_iter()
-                    .filter_map(|block| match block {
-                        ContentBlock::Text { text } => Some(text),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                Ok(text)
-            },
-            // Enforced OpenAI
-            (Some(ModelProvider::OpenAI), _, Some(client)) => {
-                info!("Using OpenAI's GPT model");
-                let messages = vec![
-                    ChatCompletionRequestSystemMessage {
-                        content: Some(system_prompt.to_string()),
-                        name: None,
-                        role: Role::System,
-                    }.into(),
-                    ChatCompletionRequestUserMessage {
-                        content: Some(ChatCompletionRequestUserMessageContent::Text(
-                            user_message.to_string()
-                        )),
-                        name: None,
-                        role: Role::User,
-                    }.into(),
-                ];
-
-                let request = CreateChatCompletionRequest {
-                    model: "gpt-3.5-turbo".into(),
-                    messages,
-                    temperature: Some(0.7),
-                    max_tokens: Some(OPENAI_MAX_TOKENS),
-                    ..Default::default()
-                };
+        let _spinner = Spinner::start("Writing commit message…");
+        let message = self.generate_on(provider, &prompt, &request).await?;
+        let message = if oneline { subject_only(&message) } else { message };
+        let subject_len = display_width(message.lines().next().unwrap_or(""));
+        if subject_len <= self.subject_len {
+            return Ok(message);
+        }
 
-                debug!("Sending request to OpenAI API");
-                let response = client.chat().create(request).await?;
-                debug!("Received response from OpenAI API");
-                Ok(response.choices[0]
-                    .message
-                    .content
-                    .clone()
-                    .unwrap_or_else(|| "No response available.".to_string()))
-            },
-            // Default behavior: prefer Anthropic if available
-            (None, Some(client), _) => {
-                info!("Using default provider: Anthropic's Claude model");
-                let request = MessagesRequest {
-                    model: "claude-3-sonnet-20240229".to_string(),
-                    system: system_prompt.to_string(),
-                    messages: vec![
-                        Message {
-                            role: AnthropicRole::User,
-                            content: vec![ContentBlock::Text { text: user_message.to_string() }],
-                        }
-                    ],
-                    max_tokens: ANTHROPIC_MAX_TOKENS,
-                    ..Default::default()
-                };
+        // Ask once for a shorter subject, then cut it down ourselves
+        debug!("Subject line is {} characters, asking for at most {}", subject_len, self.subject_len);
+        let retry = format!(
+            "{}\n\nYou previously wrote:\n```\n{}\n```\nIts first line is {} characters long. Rewrite the message so the first line is at most {} characters. Reply with the complete commit message only.",
+            request, message, subject_len, self.subject_len
+        );
+        let message = self.generate_on(provider, &prompt, &retry).await?;
+        let message = if oneline { subject_only(&message) } else { message };
+        Ok(truncate_subject(&message, self.subject_len))
+    }
 
-                debug!("Sending request to Anthropic API");
-                let response = client.messages(request).await
-                    .map_err(|e| anyhow::anyhow!("Anthropic API error: {}", e))?;
-                
-                debug!("Received response from Anthropic API");
-                let text = response.content.into_iter()
-                    .filter_map(|block| match block {
-                        ContentBlock::Text { text } => Some(text),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                Ok(text)
-            },
-            // Fallback to OpenAI
-            (None, None, Some(client)) => {
-                info!("Using fallback provider: OpenAI's GPT model");
-                let messages = vec![
-                    ChatCompletionRequestSystemMessage {
-                        content: Some(system_prompt.to_string()),
-                        name: None,
-                        role: Role::System,
-                    }.into(),
-                    ChatCompletionRequestUserMessage {
-                        content: Some(ChatCompletionRequestUserMessageContent::Text(
-                            user_message.to_string()
-                        )),
-                        name: None,
-                        role: Role::User,
-                    }.into(),
-                ];
-
-                let request = CreateChatCompletionRequest {
-                    model: "gpt-3.5-turbo".into(),
-                    messages,
-                    temperature: Some(0.7),
-                    max_tokens: Some(OPENAI_MAX_TOKENS),
-                    ..Default::default()
-                };
+    /// Whether the commit message for `diff` should be a subject line only
+    fn wants_oneline(&self, diff: &Diff<'_>) -> Result<bool> {
+        Ok(match self.message_body {
+            MessageBody::Oneline => true,
+            MessageBody::Detailed => false,
+            MessageBody::Auto => {
+                let stats = diff.stats()?;
+                stats.insertions() + stats.deletions() <= ONELINE_MAX_CHANGED_LINES
+            }
+        })
+    }
 
-                debug!("Sending request to OpenAI API");
-                let response = client.chat().create(request).await?;
-                debug!("Received response from OpenAI API");
-                Ok(response.choices[0]
-                    .message
-                    .content
-                    .clone()
-                    .unwrap_or_else(|| "No response available.".to_string()))
-            },
-            // No available clients
-            _ => {
-                info!("No AI provider available");
-                Err(anyhow::anyhow!("No AI provider available. Please set ANTHROPIC_API_KEY or OPENAI_API_KEY environment variable."))
-            },
+    /// Generate a pull request title describing the diff as a whole
+    pub async fn generate_pr_title(&self, diff: &Diff<'_>) -> Result<String> {
+        self.require_provider()?;
+        let changes = self.patch_text(diff)?;
+        if changes.is_empty() {
+            return Err(GitwiseError::InvalidInput("No changes to title; the branch has nothing the base doesn't".to_string()));
+        }
+        let request = format!("Write a pull request title for these changes:\n\n{}\n\n```\n{}\n```", summary::file_list(diff)?, changes);
+        self.check_request_size(&request)?;
+
+        let prompt = format!("{}{}", PR_TITLE_PROMPT.replace("{max}", &self.pr_title_len.to_string()), self.language_instruction());
+        let _spinner = Spinner::start("Writing PR title…");
+        let reply = self.generate_text(&prompt, &request).await?;
+        let title = clean_pr_title(&reply, self.pr_title_len);
+        if title.is_empty() {
+            return Err(GitwiseError::ParseFailure(format!("The AI returned no PR title. Response was: {}", reply)));
         }
+        Ok(title)
     }
 
-    /// Summarize a git diff using AI
-    pub async fn summarize_diff(&self, diff: &Diff<'_>, custom_prompt: Option<&str>) -> Result<String> {
-        let mut diff_text = String::new();
-        diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
-            use git2::DiffLineType::*;
-            match line.origin_value() {
-                Addition => diff_text.push_str(&format!("+{}", String::from_utf8_lossy(line.content()))),
-                Deletion => diff_text.push_str(&format!("-{}", String::from_utf8_lossy(line.content()))),
-                Context => diff_text.push_str(&format!(" {}", String::from_utf8_lossy(line.content()))),
-                _ => (),
-            }
-            true
+    /// Check a commit message against the configured subject length and body wrap
+    pub fn validate_commit_message(&self, message: &str) -> Vec<LintWarning> {
+        validate_commit_message(message, self.subject_len, self.body_wrap)
+    }
+
+    /// The configured subject length and body wrap, for `polish_text`
+    pub fn commit_message_constraints(&self) -> TextConstraints {
+        TextConstraints::commit_message(self.subject_len, self.body_wrap)
+    }
+
+    /// Fix grammar and tighten the wording of generated `text` with a second request, to
+    /// the `[auto.small]` model when the config file names one and the usual model otherwise.
+    /// The text comes back as it was when the model finds nothing to fix, or when its edit
+    /// breaks the format `constraints` asks for.
+    pub async fn polish_text(&self, text: &str, constraints: &TextConstraints) -> Result<String> {
+        if text.trim().is_empty() {
+            return Ok(text.to_string());
+        }
+        self.require_provider()?;
+        let prompt = format!("{}{}{}", polish::POLISH_PROMPT, constraints.instructions(), self.language_instruction());
+        self.check_request_size(text)?;
+
+        let _spinner = Spinner::start("Polishing wording…");
+        let reply = match (self.small_model != AutoModel::default()).then(|| self.auto_choice(&self.small_model, "small")) {
+            Some(target) => self.generate_with_fallback(target, &prompt, text).await?.0,
+            None => self.generate_on(None, &prompt, text).await?,
+        };
+        Ok(constraints.accept(text, &reply))
+    }
+
+    /// Ask for a new commit message that fixes the given rule violations
+    pub async fn revise_commit_message(&self, diff: &Diff<'_>, message: &str, warnings: &[LintWarning]) -> Result<String> {
+        self.require_provider()?;
+        let Some((prompt, request)) = self.commit_message_request(diff)? else {
+            return Ok(message.to_string());
+        };
+        let problems: Vec<String> = warnings.iter().map(|warning| format!("- {}", warning)).collect();
+        let retry = format!(
+            "{}\n\nYou previously wrote:\n```\n{}\n```\nIt breaks these rules:\n{}\nRewrite the message to fix them. Reply with the complete commit message only.",
+            request, message, problems.join("\n")
+        );
+
+        let _spinner = Spinner::start("Revising commit message…");
+        let message = self.generate_on(None, &prompt, &retry).await?;
+        let message = if self.wants_oneline(diff)? { subject_only(&message) } else { message };
+        Ok(truncate_subject(&message, self.subject_len))
+    }
+
+    /// System prompt and request for a commit message, or None when the diff has no changes
+    fn commit_message_request(&self, diff: &Diff<'_>) -> Result<Option<(String, String)>> {
+        let changes = self.format_diff_text(diff, |part| match part {
+            DiffPart::File(delta) => rename_label(delta).map(|label| format!("{}\n", label)),
+            // The header names the enclosing function or section, e.g. "@@ -10,6 +10,8 @@ fn run()"
+            DiffPart::Hunk(delta, hunk, _) => Some(format!("\n[{}] {}\n", delta_path(delta), String::from_utf8_lossy(hunk.header()).trim_end())),
+            DiffPart::Line(delta, line) => match line.origin_value() {
+                DiffLineType::Addition => Some(format!("+ {} ({})\n", String::from_utf8_lossy(line.content()), delta_path(delta))),
+                DiffLineType::Deletion => Some(format!("- {} ({})\n", String::from_utf8_lossy(line.content()), delta_path(delta))),
+                _ => None,
+            },
         })?;
 
-        let base_prompt = "You are a helpful AI that summarizes git diffs. Focus on the key changes and their implications. Be concise but informative.";
-        let prompt = if let Some(custom) = custom_prompt {
-            format!("{}. Additional instruction: {}", base_prompt, custom)
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        let files = summary::file_list(diff)?;
+        let oneline = self.wants_oneline(diff)?;
+
+        let emoji_instruction = if self.emoji {
+            let table: Vec<String> = GITMOJI.iter().map(|(emoji, meaning)| format!("{} {}", emoji, meaning)).collect();
+            format!(
+                "\n4. Start the first line with exactly one gitmoji and a space, chosen from this list by the nature of the change: {}. \
+                 The emoji counts as 2 characters towards the first line's limit.",
+                table.join(", ")
+            )
         } else {
-            base_prompt.to_string()
+            String::new()
         };
 
-        self.generate_text(&prompt, &format!("Please summarize this git diff:\n```\n{}\n```", diff_text)).await
+        let prompt = match &self.prompts.commit {
+            Some(custom) => format!(
+                "{}{}{}{}",
+                custom.replace("{subject_len}", &self.subject_len.to_string()).replace("{body_wrap}", &self.body_wrap.to_string()),
+                if oneline { "\nReply with the first line only, without a description." } else { "" },
+                emoji_instruction,
+                self.language_instruction(),
+            ),
+            None if oneline => format!(
+                "You are a helpful AI that generates git commit messages. Follow these rules strictly:\n\
+                 1. Reply with a single summary line and nothing else\n\
+                 2. The line must:\n\
+                    - Use imperative mood ('Add' not 'Added')\n\
+                    - Not end with a period\n\
+                    - Be max {subject} characters\n\
+                    - Accurately describe the main change in the diff\n\
+                 3. Do not add a blank line, description, bullet points or trailers{emoji}{language}",
+                subject = self.subject_len,
+                emoji = emoji_instruction,
+                language = self.language_instruction(),
+            ),
+            None => format!(
+                "You are a helpful AI that generates git commit messages. Follow these rules strictly:\n\
+                 1. Format must be:\n\
+                    - First line: Short summary in imperative mood, max {subject} chars\n\
+                    - Blank line\n\
+                    - Detailed description wrapped at {wrap} chars\n\
+                 2. First line must:\n\
+                    - Use imperative mood ('Add' not 'Added')\n\
+                    - Not end with a period\n\
+                    - Be max {subject} characters\n\
+                    - Accurately describe the main change in the diff\n\
+                 3. Description must:\n\
+                    - Start with a blank line after the summary\n\
+                    - Explain WHY the changes in the diff were made\n\
+                    - Wrap text at {wrap} characters\n\
+                    - Use proper punctuation\n\
+                    - Be specific to the actual changes shown\n\
+                    - Include affected files or components, using the \"Files changed\" list to say what changed where\n\
+                    - Not repeat the file list or its line counts verbatim{emoji}{language}",
+                subject = self.subject_len,
+                wrap = self.body_wrap,
+                emoji = emoji_instruction,
+                language = self.language_instruction(),
+            ),
+        };
+        let prompt = format!("{}{}", prompt, self.style_instruction());
+        let request = format!("Analyze these changes and create a commit summary. Lines of the form `[file] @@ ... @@ name` give the function or section the changes below them belong to:\n\n{}\n\n```\n{}\n```", files, changes);
+        Ok(Some((prompt, request)))
     }
 
-    /// Generate a commit message for the given diff
-    pub async fn generate_commit_message(&self, diff: &Diff<'_>) -> Result<String> {
-        let mut changes = String::new();
-        diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
-            if let Some(path) = delta.new_file().path() {
-                match line.origin_value() {
-                    git2::DiffLineType::Addition => changes.push_str(&format!("+ {} ({})\n", String::from_utf8_lossy(line.content()), path.display())),
-                    git2::DiffLineType::Deletion => changes.push_str(&format!("- {} ({})\n", String::from_utf8_lossy(line.content()), path.display())),
-                    _ => (),
+    /// Explain why a line was introduced, given the commit that last changed it
+    pub async fn explain_line(&self, diff: &Diff<'_>, commit_message: &str, path: &str, line: &str) -> Result<String> {
+        self.require_provider()?;
+        let diff_text = self.patch_text(diff)?;
+
+        let prompt = "You are a helpful AI that explains the history of code. \
+                      Given a commit and one line it introduced or changed, explain why the line exists: \
+                      what problem the commit was solving and the role this line plays in that change. \
+                      Base your answer on the commit message and diff; say so if the rationale is unclear.";
+        let prompt = format!("{}{}", prompt, self.language_instruction());
+
+        let _spinner = Spinner::start("Explaining line…");
+        self.generate_text(&prompt, &format!(
+            "Line in {}:\n```\n{}\n```\nCommit message:\n{}\n\nCommit diff:\n```\n{}\n```",
+            path, line, commit_message.trim(), diff_text
+        )).await
+    }
+
+    /// Review a diff, returning comments on potential problems.
+    /// If the reply isn't valid JSON the model is asked once more to fix it.
+    pub async fn review_diff(&self, diff: &Diff<'_>) -> Result<Vec<ReviewComment>> {
+        self.require_provider()?;
+        if diff.deltas().len() == 0 {
+            return Ok(vec![]);
+        }
+
+        // Prefix new-file line numbers so comments can point at them
+        let changes = self.format_diff_text(diff, |part| match part {
+            DiffPart::File(delta) => Some(format!("\n=== {} ===\n", delta_path(delta))),
+            DiffPart::Hunk(_, hunk, _) => Some(format!("{}\n", String::from_utf8_lossy(hunk.header()).trim_end())),
+            DiffPart::Line(_, line) => {
+                let content = String::from_utf8_lossy(line.content());
+                match (line.origin_value(), line.new_lineno()) {
+                    (DiffLineType::Addition, Some(n)) => Some(format!("{:>5} +{}", n, content)),
+                    (DiffLineType::Context, Some(n)) => Some(format!("{:>5}  {}", n, content)),
+                    (DiffLineType::Deletion, _) => Some(format!("{:>5} -{}", "", content)),
+                    _ => None,
                 }
             }
-            true
         })?;
 
-        if changes.is_empty() {
-            return Ok("No changes detected.".to_string());
+        let prompt = "You are an experienced code reviewer. Review the diff for bugs, security problems, \
+            performance issues, unclear code and missing error handling. Only comment on the changed lines and \
+            do not praise or restate the changes. \
+            IMPORTANT: Your response must be a valid JSON array of objects with the fields \
+            \"file\" (path as shown in the diff), \"line\" (the new line number shown at the start of each line, or null), \
+            \"severity\" (one of \"error\", \"warning\" or \"info\") and \"comment\". \
+            Example response format: [{\"file\": \"src/main.rs\", \"line\": 42, \"severity\": \"warning\", \"comment\": \"This unwrap panics on empty input.\"}] \
+            Return [] if there is nothing worth flagging. Only output the JSON array, no other text or explanations.";
+        let prompt = format!("{}{}", prompt, self.language_instruction());
+        let message = format!("Review these changes:\n```\n{}\n```", changes);
+        let _spinner = Spinner::start("Reviewing changes…");
+
+        let response = self.generate_text(&prompt, &message).await?;
+        let error = match parse_json_reply(&response) {
+            Ok(comments) => return Ok(comments),
+            Err(e) => e,
+        };
+
+        debug!("Review response was not valid JSON ({}), asking again", error);
+        let retry = format!(
+            "{}\n\nYour previous response could not be parsed ({}):\n{}\n\nRespond again with only the JSON array.",
+            message, error, response
+        );
+        let response = self.generate_text(&prompt, &retry).await?;
+        parse_json_reply(&response)
+            .map_err(|e| GitwiseError::ParseFailure(format!("Failed to parse AI response as JSON array of review comments ({}). Response was: {}", e, response)))
+    }
+
+    /// Write a pull request description from a branch's commits, given as
+    /// (message, summary) pairs in the order they were made, optionally filling in a template
+    pub async fn generate_pr_body_from_commits(&self, commits: &[(String, String)], template: Option<&str>) -> Result<String> {
+        self.require_provider()?;
+        let mut commit_text = String::new();
+        for (i, (message, summary)) in commits.iter().enumerate() {
+            commit_text.push_str(&format!("Commit {}:\nMessage:\n{}\nSummary of changes:\n{}\n\n", i + 1, message.trim(), summary.trim()));
         }
 
-        let prompt = "You are a helpful AI that generates git commit messages. Follow these rules strictly:\n\
-                     1. Format must be:\n\
-                        - First line: Short summary in imperative mood, max 50 chars\n\
-                        - Blank line\n\
-                        - Detailed description wrapped at 72 chars\n\
-                     2. First line must:\n\
-                        - Use imperative mood ('Add' not 'Added')\n\
-                        - Not end with a period\n\
-                        - Be max 50 characters\n\
-                        - Accurately describe the main change in the diff\n\
-                     3. Description must:\n\
-                        - Start with a blank line after the summary\n\
-                        - Explain WHY the changes in the diff were made\n\
-                        - Wrap text at 72 characters\n\
-                        - Use proper punctuation\n\
-                        - Be specific to the actual changes shown\n\
-                        - Include affected files or components";
-
-        self.generate_text(prompt, &format!("Analyze these changes and create a commit summary:\n```\n{}\n```", changes)).await
-    }
-
-    /// Analyze changes and group them by feature
-    pub async fn analyze_changes(&self, staged_diff: &Diff<'_>, unstaged_diff: &Diff<'_>, prompt: Option<&str>) -> Result<Vec<Vec<String>>> {
-        let mut all_changes = String::new();
-        
-        // Helper function to format diff
-        let mut format_diff = |diff: &Diff<'_>, prefix: &str| -> Result<()> {
-            diff.print(git2::DiffFormat::Patch, |delta, _hunk, line| {
-                if let Some(path) = delta.new_file().path() {
-                    match line.origin_value() {
-                        git2::DiffLineType::Addition => all_changes.push_str(&format!("{} +{} ({})\n", prefix, String::from_utf8_lossy(line.content()), path.display())),
-                        git2::DiffLineType::Deletion => all_changes.push_str(&format!("{} -{} ({})\n", prefix, String::from_utf8_lossy(line.content()), path.display())),
-                        _ => (),
-                    }
-                }
-                true
+        let prompt = "You are a helpful AI that writes pull request descriptions from a list of commits. \
+                      Start with a high-level summary of what the pull request does and why, drawing on the story the commits tell. \
+                      Then add a \"Commits\" section with one bullet per commit, in the order given, \
+                      each naming the commit's subject and briefly explaining what it changes.";
+        let template = template.map(template_instruction).unwrap_or_default();
+        let prompt = format!("{} {}{}", prompt, template, self.language_instruction());
+
+        let _spinner = Spinner::start("Describing pull request…");
+        self.generate_text(&prompt, &format!("Write a pull request description for these commits:\n```\n{}```", commit_text)).await
+    }
+
+    /// Write Markdown changelog entries for a release, grouped under Features, Fixes and Other.
+    /// `commits` holds each commit's message and a summary of its diff.
+    pub async fn generate_changelog(&self, commits: &[(String, String)]) -> Result<String> {
+        self.require_provider()?;
+        let mut commit_text = String::new();
+        for (i, (message, summary)) in commits.iter().enumerate() {
+            commit_text.push_str(&format!("Commit {}:\nMessage:\n{}\nSummary of changes:\n{}\n\n", i + 1, message.trim(), summary.trim()));
+        }
+
+        let prompt = "You are a helpful AI that writes release changelogs in Markdown. \
+                      Sort the changes into \"### Features\", \"### Fixes\" and \"### Other\" sections, in that order, \
+                      leaving out any section with no entries. Write one concise bullet per user-visible change, \
+                      merging commits that describe the same change and dropping purely internal noise such as typo fixes in comments. \
+                      Output only the sections, without a title or closing remarks.";
+        let prompt = format!("{}{}", prompt, self.language_instruction());
+
+        let _spinner = Spinner::start("Writing changelog…");
+        self.generate_text(&prompt, &format!("Write changelog entries for these commits:\n```\n{}```", commit_text)).await
+    }
+
+    /// The change listing `analyze_changes` sends to the AI: added and removed lines
+    /// of both diffs tagged `[Staged]`/`[Unstaged]`, after ignore rules and size limits.
+    /// With `by_hunk`, unstaged hunks are numbered so groups can select them.
+    pub fn format_changes_for_ai(&self, staged_diff: &Diff<'_>, unstaged_diff: &Diff<'_>, by_hunk: bool) -> Result<String> {
+        let mut skipped = SkippedFiles::default();
+
+        // Render one diff, numbering hunks when they may be staged individually
+        let mut format_diff = |diff: &Diff<'_>, prefix: &str, number_hunks: bool| -> Result<String> {
+            let files = self.format_files(diff, &mut skipped, |part| match part {
+                DiffPart::File(delta) => rename_label(delta).map(|label| format!("{} {}\n", prefix, label)),
+                DiffPart::Hunk(delta, hunk, index) if number_hunks => Some(format!(
+                    "{} {} hunk {}: {}\n", prefix, delta_path(delta), index, String::from_utf8_lossy(hunk.header()).trim_end()
+                )),
+                DiffPart::Hunk(..) => None,
+                DiffPart::Line(delta, line) => match line.origin_value() {
+                    DiffLineType::Addition => Some(format!("{} +{} ({})\n", prefix, String::from_utf8_lossy(line.content()), delta_path(delta))),
+                    DiffLineType::Deletion => Some(format!("{} -{} ({})\n", prefix, String::from_utf8_lossy(line.content()), delta_path(delta))),
+                    _ => None,
+                },
             })?;
-            Ok(())
+            Ok(files.concat())
         };
-        
-        // Format both staged and unstaged changes
-        format_diff(staged_diff, "[Staged]")?;
-        format_diff(unstaged_diff, "[Unstaged]")?;
-        
-        if all_changes.is_empty() {
-            return Ok(vec![]); // Return empty array if no changes
+
+        let mut all_changes = format_diff(staged_diff, "[Staged]", false)?;
+        all_changes.push_str(&format_diff(unstaged_diff, "[Unstaged]", by_hunk)?);
+        if !skipped.is_empty() {
+            all_changes.push_str(&skipped.summary());
         }
+        Ok(all_changes)
+    }
+
+    /// System prompt for `analyze_changes`: the grouping guidance (`prompts.analyze` if set,
+    /// plus the split instruction with `split`), the group limit and the response format
+    fn grouping_prompt(&self, by_hunk: bool, split: bool, max_groups: Option<usize>) -> String {
+        let split_instruction = "Split the changes into groups so that each group is one self-contained change that could be \
+            committed, reviewed and reverted on its own. \
+            \
+            Put changes in separate groups when they: \
+            - Implement different features or fix different bugs \
+            - Are an unrelated refactoring, formatting or cleanup \
+            - Touch unrelated areas of the codebase \
+            - Would need different commit messages to describe them \
+            \
+            Keep together changes that only make sense as a unit, such as a feature with its tests and docs. \
+            Use a single group only if every change genuinely belongs to the same effort.";
 
         let default_prompt = "You are an expert Git user who thinks holistically about changes. \
             FIRST AND MOST IMPORTANT RULE: If all the changes could reasonably be part of one development effort, \
@@ -339,25 +1821,76 @@ impl AiEngine {
             - STRONGLY PREFER one large group over multiple small ones \
             - If unsure, put everything in one group \
             - It's better to group too much than too little \
-            - Only split if it would be IMPOSSIBLE to describe the changes together \
-            \
-            IMPORTANT: Your response must be a valid JSON array where each element is an array of file paths. \
+            - Only split if it would be IMPOSSIBLE to describe the changes together";
+
+        let format_prompt = if by_hunk {
+            "IMPORTANT: Your response must be a valid JSON array where each element is an array of objects \
+            with a \"file\" path and the \"hunks\" (numbered as in the input) that belong to the group. \
+            A file's hunks may be split across groups. Files without numbered hunks may be given as plain path strings. \
+            Example response format: [[{\"file\": \"src/main.rs\", \"hunks\": [0, 2]}, \"docs.md\"], [{\"file\": \"src/main.rs\", \"hunks\": [1]}]] \
+            Only output the JSON array, no other text or explanations."
+        } else {
+            "IMPORTANT: Your response must be a valid JSON array where each element is an array of file paths. \
             Example response format: [[\"file1.rs\", \"file2.rs\", \"test1.rs\", \"mod.rs\", \"config.toml\", \"docs.md\"]] \
-            Note how the example shows everything in ONE group - this is what we usually want! \
-            Only output the JSON array, no other text or explanations.";
+            Only output the JSON array, no other text or explanations."
+        };
+        // A custom prompt replaces the built-in guidance, but --split still applies
+        let grouping_prompt = if let Some(custom) = &self.prompts.analyze {
+            match split {
+                true => format!("{} {}", custom.trim_end(), split_instruction),
+                false => custom.clone(),
+            }
+        } else if split {
+            format!("You are an expert Git user who keeps commits small and focused. {}", split_instruction)
+        } else if by_hunk {
+            default_prompt.to_string()
+        } else {
+            format!("{} Note how the example shows everything in ONE group - this is what we usually want!", default_prompt)
+        };
+
+        let limit_prompt = match max_groups {
+            Some(max) => format!(" Return at most {} groups; if there would be more, merge the most closely related ones.", max.max(1)),
+            None => String::new(),
+        };
+        format!("{}{} {}", grouping_prompt, limit_prompt, format_prompt)
+    }
+
+    /// Analyze changes and group them by feature.
+    /// With `by_hunk`, unstaged hunks are numbered and groups may select individual hunks.
+    /// With `split`, the model is asked to separate unrelated changes instead of merging them.
+    /// With `max_groups`, the model is asked for at most that many groups, and the smallest
+    /// groups are merged if it returns more.
+    pub async fn analyze_changes(&self, staged_diff: &Diff<'_>, unstaged_diff: &Diff<'_>, prompt: Option<&str>, by_hunk: bool, split: bool, max_groups: Option<usize>) -> Result<Vec<Vec<FileSelection>>> {
+        self.require_provider()?;
+        let all_changes = self.format_changes_for_ai(staged_diff, unstaged_diff, by_hunk)?;
+        if all_changes.is_empty() {
+            return Ok(vec![]); // Return empty array if no changes
+        }
+        self.check_request_size(&all_changes)?;
 
+        let system_prompt = self.grouping_prompt(by_hunk, split, max_groups);
+        let _spinner = Spinner::start("Grouping changes…");
         let response = self.generate_text(
-            default_prompt,
+            &system_prompt,
             &format!("Group these changes by feature (custom focus: {}):\n```\n{}\n```",
                 prompt.unwrap_or("none"),
                 all_changes)
         ).await?;
 
         // Try to parse the response
-        let groups: Vec<Vec<String>> = serde_json::from_str(&response)
-            .with_context(|| format!("Failed to parse AI response as JSON array of file groups. Response was: {}", response))?;
+        let groups: Vec<Vec<RawSelection>> = serde_json::from_str(&response)
+            .map_err(|e| GitwiseError::ParseFailure(format!("Failed to parse AI response as JSON array of file groups ({}). Response was: {}", e, response)))?;
 
-        Ok(groups)
+        let groups: Vec<Vec<FileSelection>> = groups.into_iter()
+            .map(|group| group.into_iter().map(FileSelection::from).collect())
+            .collect();
+        Ok(match max_groups {
+            Some(max) if groups.len() > max => {
+                debug!("Got {} groups, merging the smallest down to {}", groups.len(), max);
+                cap_groups(groups, max)
+            }
+            _ => groups,
+        })
     }
 }
 
@@ -376,6 +1909,379 @@ mod tests {
         // Create an empty diff
         let diff = repo.diff_tree_to_tree(None, None, None).unwrap();
         let summary = engine.summarize_diff(&diff, None).await.unwrap();
-        assert!(summary.contains("No summary available."));
+        assert_eq!(summary, NO_CHANGES_SUMMARY);
+
+        let mut streamed = String::new();
+        let summary = engine.summarize_diff_streaming(&diff, None, |token| streamed.push_str(token)).await.unwrap();
+        assert_eq!(summary, NO_CHANGES_SUMMARY);
+        assert_eq!(streamed, NO_CHANGES_SUMMARY);
+    }
+
+    #[test]
+    fn test_usage_accumulates() {
+        let engine = AiEngine::new().unwrap();
+        assert_eq!(engine.usage(), Usage::default());
+
+        engine.record_usage("gpt-3.5-turbo", 1000, 500);
+        engine.record_usage("gpt-3.5-turbo", 1000, 500);
+        let usage = engine.usage();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (2000, 1000));
+        assert!(usage.cost.is_some());
+    }
+
+    #[test]
+    fn test_binary_files_are_summarized() {
+        let engine = AiEngine::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        let mut builder = repo.treebuilder(None).unwrap();
+        let png = repo.blob(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff, 0xfe]).unwrap();
+        builder.insert("logo.png", png, 0o100644).unwrap();
+        builder.insert("notes.txt", repo.blob(b"hello\n").unwrap(), 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+
+        let text = engine.patch_text(&diff).unwrap();
+        assert_eq!(text, "(binary file changed: logo.png)\n+hello\n");
+    }
+
+    #[test]
+    fn test_cap_groups() {
+        let file = |path: &str, hunks: Option<Vec<usize>>| FileSelection { path: path.to_string(), hunks };
+        let groups = vec![
+            vec![file("src/a.rs", None), file("src/b.rs", None), file("src/c.rs", None)],
+            vec![file("src/main.rs", Some(vec![2]))],
+            vec![file("README.md", None), file("docs.md", None), file("CHANGELOG.md", None)],
+            vec![file("src/main.rs", Some(vec![0, 1]))],
+        ];
+
+        assert_eq!(cap_groups(groups.clone(), 4), groups);
+        assert_eq!(cap_groups(groups.clone(), 3), vec![
+            groups[0].clone(),
+            groups[2].clone(),
+            vec![file("src/main.rs", Some(vec![0, 1, 2]))],
+        ]);
+        let single = cap_groups(groups, 0);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].len(), 7);
+    }
+
+    #[test]
+    fn test_custom_grouping_prompt_keeps_split() {
+        let mut engine = AiEngine::new().unwrap();
+        assert!(engine.grouping_prompt(false, true, None).contains("self-contained change"));
+
+        engine.prompts.analyze = Some("Group by Jira ticket.".to_string());
+        let prompt = engine.grouping_prompt(true, true, Some(2));
+        assert!(prompt.starts_with("Group by Jira ticket. Split the changes"));
+        assert!(prompt.contains("at most 2 groups"));
+        assert!(prompt.contains("\"hunks\""));
+        assert!(engine.grouping_prompt(false, false, None).starts_with("Group by Jira ticket. IMPORTANT"));
+    }
+
+    #[test]
+    fn test_cache_key_tracks_output_settings() {
+        let engine = AiEngine::new().unwrap();
+        let key = engine.cache_key();
+        assert_eq!(AiEngine::new().unwrap().cache_key(), key);
+
+        let mut changed = AiEngine::new().unwrap();
+        changed.prompts.diff = Some("Summarize in one line".to_string());
+        assert_ne!(changed.cache_key(), key);
+        assert_ne!(AiEngine::new().unwrap().with_redact(false).cache_key(), key);
+        assert_ne!(AiEngine::new().unwrap().with_message_body(MessageBody::Oneline).cache_key(), key);
+        assert_ne!(AiEngine::new().unwrap().with_style_examples(vec!["Fix typo".to_string()]).cache_key(), key);
+        let mut changed = AiEngine::new().unwrap();
+        changed.temperature = 0.9;
+        assert_ne!(changed.cache_key(), key);
+    }
+
+    /// Serve one canned HTTP response per connection, returning the base URL
+    async fn serve(responses: Vec<String>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 8192];
+                let _ = socket.read(&mut request).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_streaming_falls_back_before_the_first_token() {
+        let unavailable = "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string();
+        let chunk = r#"{"id":"1","object":"chat.completion.chunk","created":1,"model":"gpt","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#;
+        let stream = format!("HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\ndata: {}\n\ndata: [DONE]\n\n", chunk);
+
+        let mut engine = AiEngine::new().unwrap();
+        engine.enforced_provider = None;
+        engine.azure_client = None;
+        let mut anthropic = ClientBuilder::default();
+        anthropic.api_key("key".to_string()).api_base(serve(vec![unavailable.clone(), unavailable]).await);
+        engine.anthropic_client = Some(anthropic.build().unwrap());
+        engine.openai_client = Some(Client::with_config(openai_config("key".to_string(), Some(serve(vec![stream]).await))));
+
+        let mut tokens = Vec::new();
+        let text = engine.generate_text_streaming("system", "user", |token| tokens.push(token.to_string())).await.unwrap();
+        assert_eq!(text, "Hello");
+        assert_eq!(tokens, ["Hello"]);
+
+        // --no-fallback reports the first failure
+        let engine = engine.with_fallback(false);
+        let error = engine.generate_text_streaming("system", "user", |_| {}).await.unwrap_err();
+        assert!(error.to_string().contains("503"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_review_comments() {
+        let reply = "```json\n[{\"file\": \"src/lib.rs\", \"line\": 3, \"severity\": \"critical\", \"comment\": \"Overflow\"}, \
+                     {\"file\": \"README.md\", \"line\": null, \"severity\": \"info\", \"comment\": \"Typo\"}]\n```";
+        let comments: Vec<ReviewComment> = parse_json_reply(reply).unwrap();
+
+        assert_eq!(comments.len(), 2);
+        assert_eq!(comments[0].severity, Severity::Error);
+        assert_eq!(comments[0].line, Some(3));
+        assert_eq!(comments[1].line, None);
+        assert!(parse_json_reply::<Vec<ReviewComment>>("Looks good to me!").is_err());
+    }
+
+    #[test]
+    fn test_temperature_range() {
+        let engine = AiEngine::new().unwrap();
+        assert!(engine.with_temperature(0.0).is_ok());
+        assert!(AiEngine::new().unwrap().with_temperature(2.0).is_ok());
+        assert!(AiEngine::new().unwrap().with_temperature(2.5).is_err());
+        assert!(AiEngine::new().unwrap().with_temperature(-0.1).is_err());
+    }
+
+    #[test]
+    fn test_anthropic_api_base() {
+        assert_eq!(anthropic_api_base("https://gateway.example.com/anthropic/"), "https://gateway.example.com/anthropic");
+        assert_eq!(anthropic_api_base(" https://eu.example.com/v1 "), "https://eu.example.com");
+    }
+
+    #[test]
+    fn test_require_provider() {
+        let mut engine = AiEngine::new().unwrap();
+        engine.anthropic_client = None;
+        engine.openai_client = None;
+        engine.azure_client = None;
+        assert!(!engine.has_provider());
+        let error = engine.require_provider().unwrap_err();
+        assert!(matches!(error, GitwiseError::NoProvider(_)));
+        assert!(error.to_string().contains("ANTHROPIC_API_KEY"));
+
+        engine.openai_client = Some(Client::with_config(openai_config("key".to_string(), None)));
+        assert!(engine.has_provider());
+        let engine = engine.with_provider(ModelProvider::Anthropic);
+        assert!(!engine.has_provider());
+    }
+
+    #[test]
+    fn test_request_size_guard() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcde"), 2);
+
+        let mut engine = AiEngine::new().unwrap();
+        engine.token_warn = 10;
+        assert!(engine.check_request_size("short").is_ok());
+        let engine = engine.with_assume_yes(true);
+        assert!(engine.check_request_size(&"x".repeat(100)).is_ok());
+    }
+
+    #[test]
+    fn test_oneline_messages() {
+        assert_eq!(subject_only("\nFix typo in README\n\nThe word was misspelled.\n"), "Fix typo in README");
+
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree_with = |content: &str| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("notes.txt", repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let tiny = repo.diff_tree_to_tree(None, Some(&tree_with("one\n")), None).unwrap();
+        let large = repo.diff_tree_to_tree(None, Some(&tree_with("1\n2\n3\n4\n")), None).unwrap();
+
+        let engine = AiEngine::new().unwrap();
+        assert!(engine.wants_oneline(&tiny).unwrap());
+        assert!(!engine.wants_oneline(&large).unwrap());
+        assert!(!engine.with_message_body(MessageBody::Detailed).wants_oneline(&tiny).unwrap());
+        assert!(AiEngine::new().unwrap().with_message_body(MessageBody::Oneline).wants_oneline(&large).unwrap());
+    }
+
+    #[test]
+    fn test_clean_pr_title() {
+        assert_eq!(clean_pr_title("\"Add OAuth login for the admin dashboard.\"\n\nThis adds...", 72), "Add OAuth login for the admin dashboard");
+        assert_eq!(clean_pr_title("Title: Speed up history summaries!", 72), "Speed up history summaries");
+        assert_eq!(clean_pr_title("Support GitHub Enterprise, Bitbucket and GitLab", 26), "Support GitHub Enterprise");
+    }
+
+    #[test]
+    fn test_auto_model_routing() {
+        let mut engine = AiEngine::new().unwrap();
+        engine.anthropic_client = Some(ClientBuilder::default().api_key("test".to_string()).build().unwrap());
+        engine.openai_client = Some(Client::with_config(openai_config("test".to_string(), None)));
+        engine.enforced_provider = None;
+        engine.anthropic_model = "claude-large".to_string();
+        assert_eq!(engine.route("", "diff"), Some((ModelProvider::Anthropic, "claude-large".to_string())));
+
+        let auto: Auto = toml::from_str("[auto]\nthreshold = 100\n[auto.large]\nprovider = \"openai\"\nmodel = \"gpt-4o\"\n")
+            .map(|config: Config| config.auto)
+            .unwrap();
+        let engine = engine.with_auto_models(auto);
+        // Without [auto.small], small requests keep the configured model
+        assert_eq!(engine.route("", "small diff"), Some((ModelProvider::Anthropic, "claude-large".to_string())));
+        assert_eq!(engine.route("", &"x".repeat(400)), Some((ModelProvider::OpenAI, "gpt-4o".to_string())));
+
+        let auto: Auto = toml::from_str("[auto]\nthreshold = 100\n[auto.small]\nmodel = \"claude-small\"\n")
+            .map(|config: Config| config.auto)
+            .unwrap();
+        let engine = engine.with_auto_models(auto);
+        assert_eq!(engine.route("", "small diff"), Some((ModelProvider::Anthropic, "claude-small".to_string())));
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(truncate_chars("héllo world", 5), "héllo... (6 more characters)");
+        assert_eq!(truncate_chars("short", 5), "short");
+        assert_eq!(truncate_chars("no limit", 0), "no limit");
+    }
+
+    #[test]
+    fn test_truncate_subject() {
+        let message = "Add configurable subject length limits to commit messages\n\nBody text.";
+        assert_eq!(truncate_subject(message, 72), message);
+        assert_eq!(truncate_subject(message, 30), "Add configurable subject\n\nBody text.");
+        assert_eq!(truncate_subject("Supercalifragilistic", 10), "Supercalif");
+
+        // Emoji take two columns, and the variation selector none
+        assert_eq!(display_width("♻️ Split parser"), 15);
+        assert_eq!(truncate_subject("✨ Add emoji mode to commit", 12), "✨ Add emoji");
+    }
+
+    #[test]
+    fn test_only_availability_errors_fall_back() {
+        let anthropic_error = |kind: &str| anthropic::error::AnthropicError::ApiError(anthropic::error::ApiError {
+            message: "failed".to_string(),
+            r#type: kind.to_string(),
+            param: None,
+            code: None,
+        });
+        assert!(ApiFailure::anthropic("claude", anthropic_error("overloaded_error")).retryable);
+        assert!(!ApiFailure::anthropic("claude", anthropic_error("authentication_error")).retryable);
+        assert!(!ApiFailure::anthropic("claude", anthropic_error("invalid_request_error")).retryable);
+
+        let openai_error = |kind: &str, code: &str| async_openai::error::OpenAIError::ApiError(async_openai::error::ApiError {
+            message: "failed".to_string(),
+            r#type: Some(kind.to_string()),
+            param: None,
+            code: Some(serde_json::Value::String(code.to_string())),
+        });
+        assert!(ApiFailure::openai("gpt", openai_error("server_error", "")).retryable);
+        assert!(ApiFailure::openai("gpt", openai_error("requests", "rate_limit_exceeded")).retryable);
+        assert!(!ApiFailure::openai("gpt", openai_error("invalid_request_error", "invalid_api_key")).retryable);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_is_retryable() {
+        let mut engine = AiEngine::new().unwrap();
+        engine.timeout = Some(Duration::from_millis(10));
+        let error = engine.with_timeout("OpenAI", "gpt", std::future::pending::<Result<()>>()).await.unwrap_err();
+        assert!(matches!(&error, GitwiseError::ApiError(failure) if failure.is_retryable()));
+        assert!(error.to_string().contains("timed out"));
+
+        engine.timeout = None;
+        assert_eq!(engine.with_timeout("OpenAI", "gpt", async { Ok(1) }).await.unwrap(), 1);
+    }
+
+    /// A tree of `count` one-line files named `file0000.txt` onwards
+    fn synthetic_tree(repo: &Repository, count: usize) -> git2::Tree<'_> {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for i in 0..count {
+            let blob = repo.blob(format!("content {}\n", i).as_bytes()).unwrap();
+            builder.insert(format!("file{:04}.txt", i), blob, 0o100644).unwrap();
+        }
+        repo.find_tree(builder.write().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_format_files_keeps_diff_order() {
+        let engine = AiEngine::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = synthetic_tree(&repo, 5000);
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+
+        let mut skipped = SkippedFiles::default();
+        let files = engine.format_files(&diff, &mut skipped, |part| match part {
+            DiffPart::File(delta) => Some(format!("{}\n", delta_path(delta))),
+            DiffPart::Hunk(..) => None,
+            DiffPart::Line(_, line) => Some(String::from_utf8_lossy(line.content()).to_string()),
+        }).unwrap();
+
+        assert_eq!(files.len(), 5000);
+        for (i, text) in files.iter().enumerate() {
+            assert_eq!(text, &format!("file{:04}.txt\ncontent {}\n", i, i));
+        }
+    }
+
+    /// Timing for the diff formatting behind commit messages and grouping; run with
+    /// `cargo test --release -- --ignored --nocapture format_files_timing`
+    #[test]
+    #[ignore]
+    fn test_format_files_timing() {
+        let engine = AiEngine::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = synthetic_tree(&repo, 5000);
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+        let unchanged = repo.diff_tree_to_tree(Some(&tree), Some(&tree), None).unwrap();
+
+        let runs = 10;
+        let started = std::time::Instant::now();
+        for _ in 0..runs {
+            let text = engine.patch_text(&diff).unwrap();
+            assert!(text.ends_with("+content 4999\n"));
+        }
+        let patch = started.elapsed() / runs;
+
+        let started = std::time::Instant::now();
+        for _ in 0..runs {
+            let text = engine.format_changes_for_ai(&diff, &unchanged, false).unwrap();
+            assert!(text.ends_with("[Staged] +content 4999\n (file4999.txt)\n"));
+        }
+        let analysis = started.elapsed() / runs;
+
+        println!("5000-file diff: patch text {:?}, grouping input {:?} per run", patch, analysis);
+        assert!(patch < std::time::Duration::from_secs(5) && analysis < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_format_changes_for_ai() {
+        let engine = AiEngine::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        let tree_with = |content: &[u8]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("lib.rs", repo.blob(content).unwrap(), 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let (old, new) = (tree_with(b"fn a() {}\n"), tree_with(b"fn b() {}\n"));
+        let staged = repo.diff_tree_to_tree(None, Some(&old), None).unwrap();
+        let unstaged = repo.diff_tree_to_tree(Some(&old), Some(&new), None).unwrap();
+
+        assert_eq!(
+            engine.format_changes_for_ai(&staged, &unstaged, false).unwrap(),
+            "[Staged] +fn a() {}\n (lib.rs)\n[Unstaged] -fn a() {}\n (lib.rs)\n[Unstaged] +fn b() {}\n (lib.rs)\n"
+        );
+        assert!(engine.format_changes_for_ai(&staged, &unstaged, true).unwrap().contains("[Unstaged] lib.rs hunk 0: @@ -1 +1 @@\n"));
     }
 }
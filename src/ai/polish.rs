@@ -0,0 +1,89 @@
+use crate::git::lint::validate_commit_message;
+
+/// System prompt for the copy-editing pass of `AiEngine::polish_text`
+pub const POLISH_PROMPT: &str = "You are a careful copy editor. Fix spelling and grammar and tighten awkward or wordy phrasing \
+                                 in the text you are given, without changing its meaning, adding information or dropping details. \
+                                 Keep identifiers, file names, commands and code exactly as they are. \
+                                 If the text already reads cleanly, reply with UNCHANGED and nothing else; \
+                                 otherwise reply with the edited text only.";
+
+/// Reply meaning the text needs no edits
+const UNCHANGED: &str = "UNCHANGED";
+
+/// The format a polished text has to keep
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TextConstraints {
+    /// Commit message rules: the subject length limit and the body wrap column
+    pub commit_message: Option<(usize, usize)>,
+    /// Keep Markdown headings, lists, checkboxes and code blocks
+    pub markdown: bool,
+}
+
+impl TextConstraints {
+    pub fn commit_message(subject_len: usize, body_wrap: usize) -> Self {
+        Self { commit_message: Some((subject_len, body_wrap)), markdown: false }
+    }
+
+    pub fn markdown() -> Self {
+        Self { commit_message: None, markdown: true }
+    }
+
+    /// Rules to append to `POLISH_PROMPT`
+    pub fn instructions(&self) -> String {
+        let mut rules = String::new();
+        if let Some((subject_len, body_wrap)) = self.commit_message {
+            rules.push_str(&format!(
+                "\nThe text is a git commit message: keep the first line at most {} characters, in the imperative mood and \
+                 without a trailing period, keep any blank line after it, and wrap the remaining lines at {} characters.",
+                subject_len, body_wrap
+            ));
+        }
+        if self.markdown {
+            rules.push_str("\nThe text is Markdown: keep every heading, list item, checkbox, link and code block.");
+        }
+        rules
+    }
+
+    /// The text to use given the editor's reply: the original when the reply says it
+    /// needs no edits, is empty, or breaks more commit message rules than the original
+    pub fn accept(&self, original: &str, reply: &str) -> String {
+        let reply = reply.trim();
+        let reply = reply.strip_prefix("```")
+            .and_then(|rest| rest.strip_suffix("```"))
+            .map(|body| body.trim_start_matches(|c: char| c.is_ascii_alphanumeric()).trim())
+            .unwrap_or(reply);
+        if reply.is_empty() || reply == UNCHANGED {
+            return original.to_string();
+        }
+        if let Some((subject_len, body_wrap)) = self.commit_message {
+            let broken = |text: &str| validate_commit_message(text, subject_len, body_wrap).len();
+            if broken(reply) > broken(original) {
+                return original.to_string();
+            }
+        }
+        reply.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_polish() {
+        let commit = TextConstraints::commit_message(50, 72);
+        let original = "Add retry for the uploads\n\nRetries uploads what fail with a 503.";
+        assert_eq!(commit.accept(original, "UNCHANGED"), original);
+        assert_eq!(commit.accept(original, "  \n"), original);
+
+        let polished = "Add retries for uploads\n\nRetry uploads that fail with a 503.";
+        assert_eq!(commit.accept(original, polished), polished);
+        assert_eq!(commit.accept(original, &format!("```text\n{}\n```", polished)), polished);
+        // A longer subject breaks the length rule the original kept
+        assert_eq!(commit.accept(original, "Add automatic retries for every upload that fails on the server\n\nRetry them."), original);
+
+        assert!(commit.instructions().contains("at most 50 characters"));
+        assert!(TextConstraints::markdown().instructions().contains("checkbox"));
+        assert_eq!(TextConstraints::markdown().accept("## Summary\nFixes it", "## Summary\nFix it."), "## Summary\nFix it.");
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Rate-limited (429) responses retried before the request fails over or gives up
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// First wait of the backoff used when a 429 comes without `Retry-After`; it doubles
+/// with each retry
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest `Retry-After` worth waiting for, even without a timeout. Beyond it, or
+/// beyond the time left before the timeout, the request fails as rate limited,
+/// which lets the other provider take it.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// A POST to a provider that failed, before it's turned into that client library's error
+#[derive(Debug)]
+pub enum PostError {
+    Http(reqwest::Error),
+    /// An error status, with the body holding the provider's error object
+    Status(StatusCode, Vec<u8>),
+    Decode(serde_json::Error),
+}
+
+/// The `{"error": {...}}` body both APIs answer failures with
+#[derive(Deserialize)]
+struct WrappedError<E> {
+    error: E,
+}
+
+impl PostError {
+    /// Whether the provider was overloaded or rate limited, even when the error body
+    /// isn't one of its error objects (e.g. a gateway's HTML error page)
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, PostError::Status(status, _) if status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS)
+    }
+
+    pub fn into_anthropic(self) -> anthropic::error::AnthropicError {
+        use anthropic::error::AnthropicError;
+        match self {
+            PostError::Http(e) => AnthropicError::Reqwest(e),
+            PostError::Status(_, body) => match serde_json::from_slice::<WrappedError<anthropic::error::ApiError>>(&body) {
+                Ok(wrapped) => AnthropicError::ApiError(wrapped.error),
+                Err(e) => AnthropicError::JSONDeserialize(e),
+            },
+            PostError::Decode(e) => AnthropicError::JSONDeserialize(e),
+        }
+    }
+
+    pub fn into_openai(self) -> async_openai::error::OpenAIError {
+        use async_openai::error::OpenAIError;
+        match self {
+            PostError::Http(e) => OpenAIError::Reqwest(e),
+            PostError::Status(_, body) => match serde_json::from_slice::<WrappedError<async_openai::error::ApiError>>(&body) {
+                Ok(wrapped) => OpenAIError::ApiError(wrapped.error),
+                Err(e) => OpenAIError::JSONDeserialize(e),
+            },
+            PostError::Decode(e) => OpenAIError::JSONDeserialize(e),
+        }
+    }
+}
+
+/// Send the request `build` makes and decode the JSON response. Rate-limited requests
+/// are sent again after the wait the provider's `Retry-After` asks for, or on an
+/// exponential schedule when it doesn't say; exhausted quotas fail straight away, and
+/// so does a wait that would run past `deadline` (the request's timeout).
+pub async fn post_json<O, F>(provider: &str, deadline: Option<Instant>, build: F) -> Result<O, PostError>
+where
+    O: DeserializeOwned,
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        let response = build().send().await.map_err(PostError::Http)?;
+        let status = response.status();
+        let delay = retry_after(response.headers(), Utc::now());
+        let body = response.bytes().await.map_err(PostError::Http)?;
+        if status.is_success() {
+            return serde_json::from_slice(&body).map_err(PostError::Decode);
+        }
+        if status != StatusCode::TOO_MANY_REQUESTS || attempt == MAX_RATE_LIMIT_RETRIES || is_quota_exhausted(&body) {
+            return Err(PostError::Status(status, body.to_vec()));
+        }
+
+        let (delay, asked) = match delay {
+            Some(delay) => (delay, true),
+            None => (INITIAL_BACKOFF * 2u32.pow(attempt), false),
+        };
+        let budget = deadline.map_or(MAX_RETRY_AFTER, |deadline| deadline.saturating_duration_since(Instant::now()).min(MAX_RETRY_AFTER));
+        if delay > budget {
+            debug!("{} rate limited the request for {:.1}s, longer than the {:.1}s left to wait", provider, delay.as_secs_f64(), budget.as_secs_f64());
+            return Err(PostError::Status(status, body.to_vec()));
+        }
+        match asked {
+            true => debug!("{} rate limited the request; retrying in {:.1}s as its Retry-After asks", provider, delay.as_secs_f64()),
+            false => debug!("{} rate limited the request; retrying in {:.1}s", provider, delay.as_secs_f64()),
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// How long a response asks the client to wait before retrying: OpenAI's
+/// `retry-after-ms`, or `Retry-After` in seconds or as an HTTP date
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+    if let Some(millis) = header("retry-after-ms").and_then(|value| value.parse::<f64>().ok()).filter(|ms| ms.is_finite() && *ms >= 0.0) {
+        return Some(Duration::from_secs_f64(millis / 1000.0));
+    }
+    let value = header("retry-after")?;
+    if let Some(seconds) = value.parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0) {
+        return Some(Duration::from_secs_f64(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+/// Whether a 429 means the account is out of quota, which waiting won't fix
+fn is_quota_exhausted(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body).is_ok_and(|body| {
+        ["/error/type", "/error/code"].iter().any(|field| body.pointer(field).and_then(|value| value.as_str()) == Some("insufficient_quota"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_retry_after() {
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+
+        assert_eq!(retry_after(&headers(&[("retry-after", "7")]), now), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&headers(&[("retry-after", "1.5")]), now), Some(Duration::from_millis(1500)));
+        assert_eq!(retry_after(&headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:30 GMT")]), now), Some(Duration::from_secs(30)));
+        assert_eq!(retry_after(&headers(&[("retry-after", "Wed, 21 Oct 2015 07:27:00 GMT")]), now), Some(Duration::ZERO));
+        assert_eq!(retry_after(&headers(&[("retry-after", "20"), ("retry-after-ms", "250")]), now), Some(Duration::from_millis(250)));
+        assert_eq!(retry_after(&headers(&[("retry-after", "soon")]), now), None);
+        assert_eq!(retry_after(&headers(&[("retry-after", "-3")]), now), None);
+        assert_eq!(retry_after(&headers(&[]), now), None);
+
+        assert!(is_quota_exhausted(br#"{"error": {"type": "insufficient_quota", "message": "You exceeded your quota"}}"#));
+        assert!(!is_quota_exhausted(br#"{"error": {"type": "rate_limit_error", "message": "Slow down"}}"#));
+        assert!(!is_quota_exhausted(b"Too Many Requests"));
+    }
+
+    #[tokio::test]
+    async fn test_post_json_waits_out_rate_limits() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Answers with a 429 asking for a short wait, then with the JSON result
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let responses = [
+                "HTTP/1.1 429 Too Many Requests\r\nretry-after-ms: 50\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+                "HTTP/1.1 200 OK\r\ncontent-length: 11\r\nconnection: close\r\n\r\n{\"ok\":true}",
+            ];
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let http = reqwest::Client::new();
+        let started = std::time::Instant::now();
+        let body: serde_json::Value = post_json("Test", None, || http.post(&url)).await.unwrap();
+        assert_eq!(body, serde_json::json!({ "ok": true }));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        server.await.unwrap();
+
+        // A wait longer than the time left before the timeout fails straight away
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            socket.write_all(b"HTTP/1.1 429 Too Many Requests\r\nretry-after: 90\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}").await.unwrap();
+        });
+        let started = std::time::Instant::now();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let error = post_json::<serde_json::Value, _>("Test", Some(deadline), || http.post(&url)).await.unwrap_err();
+        assert!(matches!(error, PostError::Status(StatusCode::TOO_MANY_REQUESTS, _)));
+        assert!(error.is_unavailable());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,7 +1,11 @@
-use anyhow::Result;
-use git2::Diff;
+use crate::error::Result;
+use git2::{Delta, Diff, Patch};
 
-pub fn summarize_diff(diff: &Diff) -> Result<String> {
+/// Files listed individually in `file_list`; the rest are collapsed into one line
+const MAX_LISTED_FILES: usize = 25;
+
+/// Summarize a diff from its line statistics alone, without calling an AI provider
+pub fn diff_stats(diff: &Diff) -> Result<String> {
     let stats = diff.stats()?;
     Ok(format!(
         "Changes: {} files changed, {} insertions(+), {} deletions(-)",
@@ -10,3 +14,182 @@ pub fn summarize_diff(diff: &Diff) -> Result<String> {
         stats.deletions()
     ))
 }
+
+/// Changed files of one type, as counted by `file_types`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileTypeStats {
+    /// Extension such as `.rs`, a well-known name such as `Makefile`, or `(no extension)`
+    pub kind: String,
+    pub files: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// The type of a file for `file_types`: its lower-cased extension, or its name for
+/// extensionless build files such as `Makefile` and `Dockerfile.dev`
+fn file_kind(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let lower = name.to_lowercase();
+    if lower == "makefile" || lower == "gnumakefile" {
+        return "Makefile".to_string();
+    }
+    if lower == "dockerfile" || lower.starts_with("dockerfile.") || lower.ends_with(".dockerfile") {
+        return "Dockerfile".to_string();
+    }
+    // Dotfiles like `.gitignore` are named, not extended
+    match lower.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => format!(".{}", extension),
+        _ if lower.starts_with('.') => lower,
+        _ => "(no extension)".to_string(),
+    }
+}
+
+/// Changed files grouped by type, most changed lines first
+pub fn file_types(diff: &Diff) -> Result<Vec<FileTypeStats>> {
+    let mut types: Vec<FileTypeStats> = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        let (insertions, deletions) = match Patch::from_diff(diff, i)? {
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats()?;
+                (insertions, deletions)
+            }
+            None => (0, 0),
+        };
+        let kind = file_kind(&super::delta_path(&delta));
+        match types.iter_mut().find(|stats| stats.kind == kind) {
+            Some(stats) => {
+                stats.files += 1;
+                stats.insertions += insertions;
+                stats.deletions += deletions;
+            }
+            None => types.push(FileTypeStats { kind, files: 1, insertions, deletions }),
+        }
+    }
+    types.sort_by_key(|stats| std::cmp::Reverse(stats.insertions + stats.deletions));
+    Ok(types)
+}
+
+/// One-line "File types:" breakdown for prompts, e.g. `.rs 3 files (+40 -2), .md 1 file (+5 -0)`
+pub fn file_type_line(types: &[FileTypeStats]) -> String {
+    let parts: Vec<String> = types.iter()
+        .map(|stats| format!(
+            "{} {} {} (+{} -{})",
+            stats.kind, stats.files, if stats.files == 1 { "file" } else { "files" }, stats.insertions, stats.deletions
+        ))
+        .collect();
+    format!("File types: {}", parts.join(", "))
+}
+
+/// A compact "Files changed:" list with per-file line counts and the status of
+/// added, deleted, renamed and binary files
+pub fn file_list(diff: &Diff) -> Result<String> {
+    let mut lines = Vec::new();
+    let (mut rest, mut rest_insertions, mut rest_deletions) = (0, 0, 0);
+
+    for (i, delta) in diff.deltas().enumerate() {
+        let (insertions, deletions) = match Patch::from_diff(diff, i)? {
+            Some(patch) => {
+                let (_, insertions, deletions) = patch.line_stats()?;
+                (insertions, deletions)
+            }
+            None => (0, 0),
+        };
+        if lines.len() >= MAX_LISTED_FILES {
+            rest += 1;
+            rest_insertions += insertions;
+            rest_deletions += deletions;
+            continue;
+        }
+
+        let path = super::delta_path(&delta);
+        let mut notes = Vec::new();
+        match delta.status() {
+            Delta::Added => notes.push("new".to_string()),
+            Delta::Deleted => notes.push("deleted".to_string()),
+            Delta::Renamed | Delta::Copied => {
+                let verb = if delta.status() == Delta::Renamed { "renamed" } else { "copied" };
+                let old = delta.old_file().path().map(|path| path.display().to_string()).unwrap_or_default();
+                notes.push(format!("{} from {}", verb, old));
+            }
+            _ => {}
+        }
+        if super::is_binary(&delta) {
+            notes.push("binary".to_string());
+        } else {
+            notes.push(format!("+{} -{}", insertions, deletions));
+        }
+        lines.push(format!("- {} ({})", path, notes.join(", ")));
+    }
+
+    if rest > 0 {
+        lines.push(format!("- ... and {} more files (+{} -{})", rest, rest_insertions, rest_deletions));
+    }
+    Ok(format!("Files changed:\n{}", lines.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Repository, Signature};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_file_types() {
+        assert_eq!(file_kind("src/main.RS"), ".rs");
+        assert_eq!(file_kind("build/Makefile"), "Makefile");
+        assert_eq!(file_kind("docker/Dockerfile.dev"), "Dockerfile");
+        assert_eq!(file_kind("LICENSE"), "(no extension)");
+        assert_eq!(file_kind(".gitignore"), ".gitignore");
+
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (path, contents) in [("a.rs", "1\n2\n"), ("b.rs", "1\n"), ("README.md", "1\n"), ("Makefile", "1\n2\n3\n4\n")] {
+            builder.insert(path, repo.blob(contents.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let diff = repo.diff_tree_to_tree(None, Some(&tree), None).unwrap();
+
+        let types = file_types(&diff).unwrap();
+        assert_eq!(file_type_line(&types), "File types: Makefile 1 file (+4 -0), .rs 2 files (+3 -0), .md 1 file (+1 -0)");
+    }
+
+    #[test]
+    fn test_file_list() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let write = |path: &str, contents: &str| fs::write(temp_dir.path().join(path), contents).unwrap();
+
+        write("old.txt", "one\ntwo\n");
+        write("moved.txt", "a\nb\nc\nd\n");
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        fs::remove_file(temp_dir.path().join("old.txt")).unwrap();
+        fs::rename(temp_dir.path().join("moved.txt"), temp_dir.path().join("renamed.txt")).unwrap();
+        for i in 0..MAX_LISTED_FILES + 2 {
+            write(&format!("zz{:02}.txt", i), "x\n");
+        }
+        index.remove_path(Path::new("old.txt")).unwrap();
+        index.remove_path(Path::new("moved.txt")).unwrap();
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None).unwrap();
+
+        let mut diff = repo.diff_tree_to_index(Some(&tree), Some(&index), None).unwrap();
+        crate::git::find_renames(&mut diff).unwrap();
+        let list = file_list(&diff).unwrap();
+        let lines: Vec<&str> = list.lines().collect();
+
+        assert_eq!(lines[0], "Files changed:");
+        assert!(lines.contains(&"- zz00.txt (new, +1 -0)"));
+        assert!(lines.contains(&"- old.txt (deleted, +0 -2)"));
+        assert!(lines.contains(&"- renamed.txt (renamed from moved.txt, +0 -0)"));
+        assert_eq!(lines.len(), MAX_LISTED_FILES + 2);
+        assert_eq!(lines.last().unwrap(), &"- ... and 4 more files (+4 -0)");
+    }
+}
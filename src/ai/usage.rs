@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// USD per million (prompt, completion) tokens, matched by model-name prefix.
+/// More specific prefixes come first.
+const PRICING: &[(&str, f64, f64)] = &[
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-sonnet", 3.0, 15.0),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 5.0, 15.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+];
+
+/// Token counts accumulated across AI requests
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Estimated cost in USD of the requests whose model has known pricing
+    pub cost: Option<f64>,
+}
+
+impl Usage {
+    /// Add one request's token counts, pricing them for `model` when possible
+    pub fn add(&mut self, model: &str, prompt_tokens: u64, completion_tokens: u64) {
+        self.prompt_tokens += prompt_tokens;
+        self.completion_tokens += completion_tokens;
+
+        if let Some((_, prompt_price, completion_price)) = PRICING.iter().find(|(prefix, _, _)| model.starts_with(prefix)) {
+            let cost = (prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price) / 1_000_000.0;
+            self.cost = Some(self.cost.unwrap_or(0.0) + cost);
+        }
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Used {} tokens", group_thousands(self.total_tokens()))?;
+        if let Some(cost) = self.cost {
+            write!(f, " (~${:.3})", cost)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format a number with comma thousands separators, e.g. 1234 -> "1,234"
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_summary() {
+        let mut usage = Usage::default();
+        usage.add("gpt-4o-mini-2024-07-18", 1000, 234);
+        usage.add("some-local-model", 0, 0);
+
+        assert_eq!(usage.total_tokens(), 1234);
+        assert_eq!(usage.to_string(), "Used 1,234 tokens (~$0.000)");
+
+        usage.add("claude-3-opus-20240229", 1_000_000, 0);
+        assert_eq!(usage.to_string(), "Used 1,001,234 tokens (~$15.000)");
+    }
+}
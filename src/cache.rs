@@ -0,0 +1,98 @@
+use anyhow::Result;
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tracing::debug;
+
+use gitwise::utils::fnv1a;
+
+/// A cached AI summary for a single commit
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    commit: String,
+    key: String,
+    summary: String,
+}
+
+/// On-disk cache of commit summaries under `~/.cache/gitwise/`
+pub struct SummaryCache {
+    dir: Option<PathBuf>,
+}
+
+impl SummaryCache {
+    /// Open the cache; a disabled cache never hits and never stores
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            dir: if enabled { cache_dir() } else { None },
+        }
+    }
+
+    /// Look up a summary for `commit` computed under `key` (prompt, provider, ...)
+    pub fn get(&self, commit: Oid, key: &str) -> Option<String> {
+        let path = self.entry_path(commit, key)?;
+        let contents = fs::read_to_string(&path).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+
+        // Guard against hash collisions between keys
+        if entry.commit != commit.to_string() || entry.key != key {
+            return None;
+        }
+
+        debug!("Using cached summary for {}", commit);
+        Some(entry.summary)
+    }
+
+    /// Store a summary; failures are logged and otherwise ignored
+    pub fn put(&self, commit: Oid, key: &str, summary: &str) {
+        let Some(path) = self.entry_path(commit, key) else {
+            return;
+        };
+
+        let entry = CacheEntry {
+            commit: commit.to_string(),
+            key: key.to_string(),
+            summary: summary.to_string(),
+        };
+
+        let result = path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(fs::write(&path, serde_json::to_string(&entry)?)?));
+
+        if let Err(e) = result {
+            debug!("Failed to write cache entry {}: {}", path.display(), e);
+        }
+    }
+
+    /// Remove every cached summary, returning how many entries were deleted
+    pub fn clear() -> Result<usize> {
+        let Some(dir) = cache_dir() else {
+            return Ok(0);
+        };
+        if !dir.exists() {
+            return Ok(0);
+        }
+
+        let count = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .count();
+        fs::remove_dir_all(&dir)?;
+
+        Ok(count)
+    }
+
+    fn entry_path(&self, commit: Oid, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref()
+            .map(|dir| dir.join(format!("{}-{:016x}.json", commit, fnv1a(key.as_bytes()))))
+    }
+}
+
+/// Resolve the cache directory from `XDG_CACHE_HOME` or `HOME`
+fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .map(|dir| dir.join("gitwise"))
+}
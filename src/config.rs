@@ -0,0 +1,220 @@
+use anyhow::{Result, Context};
+use git2::Repository;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ai::ModelProvider;
+use crate::git::ticket::TicketPlacement;
+
+/// Name of the per-repository config file, read from the repository root
+pub const CONFIG_FILE: &str = ".gitwise.toml";
+
+/// Defaults read from `.gitwise.toml` and `~/.config/gitwise/config.toml`.
+/// Command-line flags and environment variables take precedence.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub provider: Option<ModelProvider>,
+    pub anthropic_model: Option<String>,
+    pub openai_model: Option<String>,
+    pub language: Option<String>,
+    pub temperature: Option<f32>,
+    /// Maximum commit subject length
+    pub subject_len: Option<usize>,
+    /// Column to wrap commit message bodies at
+    pub body_wrap: Option<usize>,
+    /// Maximum pull request title length
+    pub pr_title_len: Option<usize>,
+    /// Most feature groups `add` suggests
+    pub max_groups: Option<usize>,
+    /// Extra `.gitwiseignore`-style patterns
+    pub ignore: Vec<String>,
+    /// Replacements for the built-in system prompts
+    pub prompts: Prompts,
+    /// Imitating the repository's commit style with `--learn-style`
+    pub style: Style,
+    /// Models `--model auto` chooses between
+    pub auto: Auto,
+    /// Ticket ids taken from the branch name into commit messages
+    pub ticket: Ticket,
+    /// Where pull requests are opened
+    pub github: GitHub,
+}
+
+/// The `[auto]` table: `--model auto` sends requests estimated below `threshold`
+/// tokens to `small` and the rest to `large`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Auto {
+    pub threshold: Option<usize>,
+    pub small: AutoModel,
+    pub large: AutoModel,
+}
+
+/// `[auto.small]` or `[auto.large]`; unset values fall back to the usual provider and model
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoModel {
+    pub provider: Option<ModelProvider>,
+    pub model: Option<String>,
+}
+
+impl AutoModel {
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            provider: self.provider.or(fallback.provider),
+            model: self.model.or(fallback.model),
+        }
+    }
+}
+
+/// The `[ticket]` table: how `commit` finds a ticket id in the branch name and
+/// where it puts it in the message
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ticket {
+    pub pattern: Option<String>,
+    /// Trailer key, e.g. `Refs`
+    pub trailer: Option<String>,
+    pub placement: Option<TicketPlacement>,
+}
+
+/// The `[github]` table
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHub {
+    /// GitHub Enterprise Server hosts, besides those `gh` is logged in to
+    pub hosts: Vec<String>,
+}
+
+/// The `[style]` table
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Style {
+    /// Number of recent commit messages shown to the model as examples
+    pub sample_count: Option<usize>,
+}
+
+/// The `[prompts]` table: system prompts replacing gitwise's own for each kind of request
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prompts {
+    /// Commit messages; `{subject_len}` and `{body_wrap}` are replaced with the limits
+    pub commit: Option<String>,
+    /// Diff and history summaries
+    pub diff: Option<String>,
+    /// Grouping changes for `add`; the JSON response format is still appended
+    pub analyze: Option<String>,
+}
+
+impl Prompts {
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            commit: self.commit.or(fallback.commit),
+            diff: self.diff.or(fallback.diff),
+            analyze: self.analyze.or(fallback.analyze),
+        }
+    }
+}
+
+impl Config {
+    /// Load the repository config, falling back to the user config for unset values
+    pub fn load() -> Result<Self> {
+        let repo_config = Repository::open_from_env()
+            .ok()
+            .and_then(|repo| repo.workdir().map(|dir| dir.join(CONFIG_FILE)))
+            .map(|path| Self::read(&path))
+            .transpose()?
+            .flatten()
+            .unwrap_or_default();
+
+        let user_config = user_config_path()
+            .map(|path| Self::read(&path))
+            .transpose()?
+            .flatten()
+            .unwrap_or_default();
+
+        Ok(repo_config.or(user_config))
+    }
+
+    /// Parse a config file, returning `None` if it doesn't exist
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        toml::from_str(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Fill values unset in `self` from `fallback`; ignore patterns from both apply
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            provider: self.provider.or(fallback.provider),
+            anthropic_model: self.anthropic_model.or(fallback.anthropic_model),
+            openai_model: self.openai_model.or(fallback.openai_model),
+            language: self.language.or(fallback.language),
+            temperature: self.temperature.or(fallback.temperature),
+            subject_len: self.subject_len.or(fallback.subject_len),
+            body_wrap: self.body_wrap.or(fallback.body_wrap),
+            pr_title_len: self.pr_title_len.or(fallback.pr_title_len),
+            max_groups: self.max_groups.or(fallback.max_groups),
+            ignore: fallback.ignore.into_iter().chain(self.ignore).collect(),
+            prompts: self.prompts.or(fallback.prompts),
+            style: Style { sample_count: self.style.sample_count.or(fallback.style.sample_count) },
+            auto: Auto {
+                threshold: self.auto.threshold.or(fallback.auto.threshold),
+                small: self.auto.small.or(fallback.auto.small),
+                large: self.auto.large.or(fallback.auto.large),
+            },
+            ticket: Ticket {
+                pattern: self.ticket.pattern.or(fallback.ticket.pattern),
+                trailer: self.ticket.trailer.or(fallback.ticket.trailer),
+                placement: self.ticket.placement.or(fallback.ticket.placement),
+            },
+            github: GitHub { hosts: fallback.github.hosts.into_iter().chain(self.github.hosts).collect() },
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/gitwise/config.toml`, or `~/.config/gitwise/config.toml`
+fn user_config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("gitwise").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_merge() {
+        let repo: Config = toml::from_str("provider = \"openai\"\nmax_groups = 3\nlanguage = \"German\"\nignore = [\"*.lock\"]\n[prompts]\ncommit = \"Write a haiku\"\n[auto.small]\nmodel = \"o1-mini\"\n[ticket]\nplacement = \"subject\"\n").unwrap();
+        let user: Config = toml::from_str("language = \"French\"\ntemperature = 0.5\nignore = [\"dist/\"]\n[prompts]\ncommit = \"Be terse\"\ndiff = \"Summarize\"\n[style]\nsample_count = 5\n[auto]\nthreshold = 1000\n[auto.small]\nprovider = \"openai\"\nmodel = \"gpt-4o-mini\"\n[ticket]\ntrailer = \"Issue\"\nplacement = \"trailer\"\n[github]\nhosts = [\"github.mycorp.com\"]\n").unwrap();
+
+        let config = repo.or(user);
+        assert_eq!(config.provider, Some(ModelProvider::OpenAI));
+        assert_eq!(config.language.as_deref(), Some("German"));
+        assert_eq!(config.temperature, Some(0.5));
+        assert_eq!(config.max_groups, Some(3));
+        assert_eq!(config.ignore, vec!["dist/", "*.lock"]);
+        assert_eq!(config.prompts.commit.as_deref(), Some("Write a haiku"));
+        assert_eq!(config.prompts.diff.as_deref(), Some("Summarize"));
+        assert_eq!(config.prompts.analyze, None);
+        assert_eq!(config.style.sample_count, Some(5));
+        assert_eq!(config.auto.threshold, Some(1000));
+        assert_eq!(config.auto.small.provider, Some(ModelProvider::OpenAI));
+        assert_eq!(config.auto.small.model.as_deref(), Some("o1-mini"));
+        assert_eq!(config.ticket.trailer.as_deref(), Some("Issue"));
+        assert_eq!(config.ticket.placement, Some(TicketPlacement::Subject));
+        assert_eq!(config.github.hosts, vec!["github.mycorp.com"]);
+
+        assert!(toml::from_str::<Config>("modle = \"gpt-4o\"\n").is_err());
+    }
+}
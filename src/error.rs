@@ -0,0 +1,91 @@
+use thiserror::Error;
+
+use crate::ai::ApiFailure;
+
+/// Errors returned by the `ai` and `git` modules, so callers can match on the kind
+/// of failure; the binary turns them into `anyhow` errors for reporting
+#[derive(Debug, Error)]
+pub enum GitwiseError {
+    /// No AI provider has an API key, or the enforced one doesn't
+    #[error("No AI provider available: {0}")]
+    NoProvider(String),
+    /// An AI provider rejected the request, failed, or timed out
+    #[error(transparent)]
+    ApiError(#[from] ApiFailure),
+    /// A reply from the AI couldn't be parsed into the expected structure
+    #[error("{0}")]
+    ParseFailure(String),
+    /// A libgit2 operation failed
+    #[error(transparent)]
+    GitError(#[from] git2::Error),
+    /// A bad argument, reference or repository state, such as an unknown branch
+    #[error("{0}")]
+    InvalidInput(String),
+    /// An external program or service (gh, gpg, Bitbucket) failed
+    #[error("{0}")]
+    External(String),
+    /// The user declined to go ahead
+    #[error("{0}")]
+    Aborted(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = GitwiseError> = std::result::Result<T, E>;
+
+impl GitwiseError {
+    /// Stable name of the variant, for `--json-errors`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GitwiseError::NoProvider(_) => "no_provider",
+            GitwiseError::ApiError(_) => "api_error",
+            GitwiseError::ParseFailure(_) => "parse_failure",
+            GitwiseError::GitError(_) => "git_error",
+            GitwiseError::InvalidInput(_) => "invalid_input",
+            GitwiseError::External(_) => "external",
+            GitwiseError::Aborted(_) => "aborted",
+            GitwiseError::Io(_) => "io",
+        }
+    }
+}
+
+/// An error as `{"error", "kind", "context"}` for `--json-errors`: the outermost message,
+/// the kind of the first `GitwiseError` in the chain (or of a bare git or I/O error),
+/// and the messages of the causes below the outermost one
+pub fn to_json(error: &anyhow::Error) -> serde_json::Value {
+    let kind = error.chain()
+        .find_map(|cause| {
+            cause.downcast_ref::<GitwiseError>().map(GitwiseError::kind)
+                .or_else(|| cause.is::<git2::Error>().then_some("git_error"))
+                .or_else(|| cause.is::<std::io::Error>().then_some("io"))
+        })
+        .unwrap_or("other");
+    let context: Vec<String> = error.chain().skip(1).map(|cause| cause.to_string()).collect();
+    serde_json::json!({
+        "error": error.to_string(),
+        "kind": kind,
+        "context": context,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_to_json() {
+        let error = Err::<(), _>(GitwiseError::InvalidInput("Could not resolve git reference: nope".to_string()))
+            .context("Failed to summarize history")
+            .unwrap_err();
+        assert_eq!(to_json(&error), serde_json::json!({
+            "error": "Failed to summarize history",
+            "kind": "invalid_input",
+            "context": ["Could not resolve git reference: nope"],
+        }));
+
+        let error = anyhow::Error::from(std::io::Error::new(std::io::ErrorKind::NotFound, "missing.txt"));
+        assert_eq!(to_json(&error)["kind"], "io");
+        assert_eq!(to_json(&anyhow::anyhow!("boom"))["context"], serde_json::json!([]));
+    }
+}
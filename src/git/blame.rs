@@ -0,0 +1,102 @@
+use crate::error::{GitwiseError, Result};
+use git2::{Oid, Repository};
+use std::fs;
+use std::path::Path;
+
+/// Where a line of a file came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineOrigin {
+    /// The line was last changed by this commit
+    Commit(Oid),
+    /// The line only exists in the working tree
+    Uncommitted,
+}
+
+/// A blamed line along with its current text
+#[derive(Debug, Clone)]
+pub struct BlamedLine {
+    /// Path relative to the repository root
+    pub path: String,
+    pub text: String,
+    pub origin: LineOrigin,
+}
+
+/// Find the commit that last changed `line` (1-based) of `path`, taking
+/// uncommitted edits in the working tree into account
+pub fn blame_line(repo: &Repository, path: &Path, line: usize) -> Result<BlamedLine> {
+    let path = repo_relative_path(repo, path)?;
+    let workdir = repo.workdir().ok_or_else(bare_repository)?;
+    let contents = fs::read_to_string(workdir.join(&path))
+        .map_err(|e| GitwiseError::InvalidInput(format!("Failed to read {}: {}", path, e)))?;
+
+    let text = line.checked_sub(1)
+        .and_then(|i| contents.lines().nth(i))
+        .ok_or_else(|| GitwiseError::InvalidInput(format!("{} has {} lines; line {} does not exist", path, contents.lines().count(), line)))?
+        .to_string();
+
+    let blame = match repo.blame_file(Path::new(&path), None) {
+        Ok(blame) => blame,
+        // Files that were never committed have no history to blame
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            return Ok(BlamedLine { path, text, origin: LineOrigin::Uncommitted });
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let blame = blame.blame_buffer(contents.as_bytes())?;
+
+    let hunk = blame.get_line(line)
+        .ok_or_else(|| GitwiseError::InvalidInput(format!("No blame information for {}:{}", path, line)))?;
+    let origin = match hunk.final_commit_id() {
+        oid if oid.is_zero() => LineOrigin::Uncommitted,
+        oid => LineOrigin::Commit(oid),
+    };
+
+    Ok(BlamedLine { path, text, origin })
+}
+
+/// Convert a path given on the command line into one relative to the repository root
+fn repo_relative_path(repo: &Repository, path: &Path) -> Result<String> {
+    let workdir = repo.workdir().ok_or_else(bare_repository)?;
+    let absolute = fs::canonicalize(path)
+        .map_err(|_| GitwiseError::InvalidInput(format!("File not found: {}", path.display())))?;
+    let relative = absolute.strip_prefix(fs::canonicalize(workdir)?)
+        .map_err(|_| GitwiseError::InvalidInput(format!("{} is outside the repository", path.display())))?;
+
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
+
+fn bare_repository() -> GitwiseError {
+    GitwiseError::InvalidInput("Cannot blame in a bare repository".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_blame_line() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let file = temp_dir.path().join("notes.txt");
+
+        fs::write(&file, "first\nsecond\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("notes.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, "Add notes", &tree, &[]).unwrap();
+
+        fs::write(&file, "first\nsecond\nthird\n").unwrap();
+
+        let committed = blame_line(&repo, &file, 2).unwrap();
+        assert_eq!(committed.path, "notes.txt");
+        assert_eq!(committed.text, "second");
+        assert_eq!(committed.origin, LineOrigin::Commit(oid));
+
+        assert_eq!(blame_line(&repo, &file, 3).unwrap().origin, LineOrigin::Uncommitted);
+        assert!(blame_line(&repo, &file, 4).is_err());
+    }
+}
@@ -1,5 +1,105 @@
-use anyhow::Result;
-use git2::{Diff, Repository};
+use crate::error::{GitwiseError, Result};
+use git2::{Commit, Diff, DiffFindOptions, DiffLineType, DiffOptions, Repository};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::utils;
+
+/// Environment variable holding the rename similarity threshold (0-100)
+pub const RENAME_THRESHOLD_ENV: &str = "GITWISE_RENAME_THRESHOLD";
+/// Similarity percentage at which a delete+add pair counts as a rename, matching git's default
+const DEFAULT_RENAME_THRESHOLD: u16 = 50;
+
+/// Unchanged lines shown around each change, matching git's default
+const DEFAULT_CONTEXT_LINES: u32 = 3;
+static CONTEXT_LINES: AtomicU32 = AtomicU32::new(DEFAULT_CONTEXT_LINES);
+
+/// Set the context lines used by every diff gitwise generates (`--context-lines`)
+pub fn set_context_lines(lines: u32) {
+    CONTEXT_LINES.store(lines, Ordering::Relaxed);
+}
+
+/// The configured number of context lines
+pub fn context_lines() -> u32 {
+    CONTEXT_LINES.load(Ordering::Relaxed)
+}
+
+/// Diff options with the configured context lines
+pub fn diff_options() -> DiffOptions {
+    let mut opts = DiffOptions::new();
+    opts.context_lines(context_lines());
+    opts
+}
+
+/// Diff options limited to paths matching `patterns` (gitignore-style globs;
+/// a leading `!` excludes). With only exclusions, every other path is included.
+pub fn pathspec_options(patterns: &[String]) -> DiffOptions {
+    // libgit2 stops at the first pattern that matches, so exclusions must come first
+    let (excludes, includes): (Vec<&String>, Vec<&String>) = patterns.iter().partition(|pattern| pattern.starts_with('!'));
+    let mut opts = diff_options();
+    for pattern in excludes.iter().chain(&includes) {
+        opts.pathspec(pattern.as_str());
+    }
+    if includes.is_empty() && !excludes.is_empty() {
+        opts.pathspec("*");
+    }
+    opts
+}
+
+/// Detect renamed and copied files so they aren't reported as a delete plus an add
+pub fn find_renames(diff: &mut Diff<'_>) -> Result<()> {
+    let threshold = std::env::var(RENAME_THRESHOLD_ENV)
+        .ok()
+        .and_then(|value| value.parse::<u16>().ok())
+        .map(|value| value.min(100))
+        .unwrap_or(DEFAULT_RENAME_THRESHOLD);
+
+    let mut opts = DiffFindOptions::new();
+    opts.renames(true)
+        .copies(true)
+        .rename_threshold(threshold)
+        .copy_threshold(threshold);
+
+    diff.find_similar(Some(&mut opts))?;
+    Ok(())
+}
+
+/// Render a diff as a unified patch, colored like `git diff` unless color is off
+pub fn format_patch(diff: &Diff<'_>) -> Result<String> {
+    let mut patch = String::new();
+    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+        let content = String::from_utf8_lossy(line.content());
+        let (style, prefix) = match line.origin_value() {
+            DiffLineType::FileHeader => (Some(utils::BOLD), ""),
+            DiffLineType::HunkHeader => (Some(utils::CYAN), ""),
+            DiffLineType::Addition => (Some(utils::GREEN), "+"),
+            DiffLineType::Deletion => (Some(utils::RED), "-"),
+            DiffLineType::Context => (None, " "),
+            _ => (None, ""),
+        };
+        // File headers span several lines; color each of them
+        for text in content.lines() {
+            let text = format!("{}{}", prefix, text);
+            match style {
+                Some(style) => patch.push_str(&utils::paint(style, text)),
+                None => patch.push_str(&text),
+            }
+            patch.push('\n');
+        }
+        true
+    })?;
+    Ok(patch)
+}
+
+/// Look up a base branch locally, then on `remote`
+pub fn find_base_commit<'a>(repo: &'a Repository, base_branch: &str, remote: &str) -> Result<Commit<'a>> {
+    if let Ok(branch) = repo.find_branch(base_branch, git2::BranchType::Local) {
+        Ok(branch.get().peel_to_commit()?)
+    } else if let Ok(branch) = repo.find_branch(&format!("{}/{}", remote, base_branch), git2::BranchType::Remote) {
+        Ok(branch.get().peel_to_commit()?)
+    } else {
+        Err(GitwiseError::InvalidInput(format!("Base branch '{}' not found", base_branch)))
+    }
+}
 
 pub fn get_branch_diff<'a>(repo: &'a Repository, source: &str, target: &str) -> Result<Diff<'a>> {
     let source_branch = repo.find_branch(source, git2::BranchType::Local)?;
@@ -11,8 +111,42 @@ pub fn get_branch_diff<'a>(repo: &'a Repository, source: &str, target: &str) ->
     let diff = repo.diff_tree_to_tree(
         Some(&source_tree),
         Some(&target_tree),
-        None,
+        Some(&mut diff_options()),
     )?;
     
     Ok(diff)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn changed_paths(repo: &Repository, patterns: &[&str]) -> Vec<String> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let diff = repo.diff_tree_to_index(None, None, Some(&mut pathspec_options(&patterns))).unwrap();
+        diff.deltas().map(|delta| delta.new_file().path().unwrap().display().to_string()).collect()
+    }
+
+    #[test]
+    fn test_pathspec_options() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let mut index = repo.index().unwrap();
+        for path in ["Cargo.lock", "README.md", "src/main.rs", "src/git/log.rs"] {
+            let file = temp_dir.path().join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, "content\n").unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+
+        assert_eq!(changed_paths(&repo, &[]).len(), 4);
+        assert_eq!(changed_paths(&repo, &["src/**"]), vec!["src/git/log.rs", "src/main.rs"]);
+        assert_eq!(changed_paths(&repo, &["src/**", "!src/git/**"]), vec!["src/main.rs"]);
+        assert_eq!(changed_paths(&repo, &["*.md", "*.lock"]), vec!["Cargo.lock", "README.md"]);
+        assert_eq!(changed_paths(&repo, &["!*.lock"]), vec!["README.md", "src/git/log.rs", "src/main.rs"]);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+
+use crate::utils::display_width;
+
+/// Words ending in -ed, -ing or -s that are still fine as an imperative first word
+const IMPERATIVE_EXCEPTIONS: &[&str] = &[
+    // -ing
+    "bring", "fling", "ping", "ring", "sing", "sling", "spring", "sting", "string", "swing", "wring",
+    // -ed
+    "bleed", "breed", "embed", "exceed", "feed", "heed", "need", "proceed", "seed", "shed", "shred", "speed", "succeed", "weed",
+    // -s
+    "alias", "bias",
+];
+
+/// A way a commit message breaks the usual git conventions
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    /// 1-based line of the message the warning is about
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Check a commit message against the subject length, imperative mood, blank
+/// second line and body wrapping rules
+pub fn validate_commit_message(message: &str, subject_len: usize, body_wrap: usize) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+    let mut warn = |line: usize, message: String| warnings.push(LintWarning { line, message });
+    let lines: Vec<&str> = message.trim_end().lines().collect();
+
+    let subject = lines.first().copied().unwrap_or("").trim_end();
+    if subject.trim().is_empty() {
+        warn(1, "subject line is empty".to_string());
+        return warnings;
+    }
+    let width = display_width(subject);
+    if width > subject_len {
+        warn(1, format!("subject is {} characters, more than {}", width, subject_len));
+    }
+    if subject.ends_with('.') {
+        warn(1, "subject ends with a period".to_string());
+    }
+    if let Some(word) = first_word(subject).filter(|word| !is_imperative(word)) {
+        warn(1, format!("subject should use the imperative mood ('{}' reads as past tense or third person)", word));
+    }
+
+    if lines.get(1).is_some_and(|line| !line.trim().is_empty()) {
+        warn(2, "second line should be blank".to_string());
+    }
+
+    // Long unbreakable tokens such as URLs, indented code and trailers can't be wrapped
+    for (i, line) in lines.iter().enumerate().skip(2) {
+        let width = display_width(line);
+        if width > body_wrap && line.trim().contains(' ') && !line.starts_with("    ") && !line.starts_with('\t') {
+            warn(i + 1, format!("body line is {} characters, wrap at {}", width, body_wrap));
+        }
+    }
+
+    warnings
+}
+
+/// First word of the subject, after any gitmoji, `[TICKET]` or `type(scope):` prefix
+fn first_word(subject: &str) -> Option<&str> {
+    subject.split_whitespace()
+        .find(|word| {
+            word.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+                && !word.ends_with(':')
+                && !(word.starts_with('[') && word.ends_with(']'))
+        })
+        .map(|word| word.trim_end_matches(|c: char| !c.is_ascii_alphabetic()))
+}
+
+fn is_imperative(word: &str) -> bool {
+    let word = word.to_ascii_lowercase();
+    if IMPERATIVE_EXCEPTIONS.contains(&word.as_str()) {
+        return true;
+    }
+    let third_person = word.ends_with('s') && !["ss", "us", "is"].iter().any(|ending| word.ends_with(ending));
+    !(word.ends_with("ed") || word.ends_with("ing") || third_person)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_commit_message() {
+        let good = "Add retry to fetch\n\nRequests to the server fail now and then, so retry\nthem once before giving up.\n\n    let url = \"https://example.com/a/very/long/path/that/is/not/wrapped/because/it/is/code\";\n";
+        assert_eq!(validate_commit_message(good, 50, 72), vec![]);
+        assert_eq!(validate_commit_message("feat(api): Process queued jobs", 50, 72), vec![]);
+        assert_eq!(validate_commit_message("✨ Embed fonts", 50, 72), vec![]);
+        for subject in ["Ping the server before syncing", "Bring back the old parser", "Shed unused features", "[JIRA-7] Alias ls to list"] {
+            assert_eq!(validate_commit_message(subject, 50, 72), vec![], "{}", subject);
+        }
+        for subject in ["Pinged the server", "Embedding fonts", "Brings back the old parser"] {
+            assert_eq!(validate_commit_message(subject, 50, 72).len(), 1, "{}", subject);
+        }
+
+        let bad = format!("Added retries to the fetch helper in the network layer.\nIt fails sometimes.\n{}", "word ".repeat(20));
+        let lines: Vec<usize> = validate_commit_message(&bad, 50, 72).iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, vec![1, 1, 1, 2, 3]);
+
+        let warnings = validate_commit_message("Fixes login", 50, 72);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].to_string().starts_with("line 1: subject should use the imperative mood ('Fixes'"));
+    }
+}
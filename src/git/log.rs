@@ -1,48 +1,469 @@
-use anyhow::Result;
-use git2::{Repository, Commit, DiffOptions};
+use crate::error::{GitwiseError, Result};
+use git2::{Repository, Commit, Oid};
 
-/// Get commits in a branch with their diffs
+/// Resolve a branch, tag, revision expression (`HEAD~1`) or abbreviated hash to a commit id
+pub fn resolve_reference(repo: &Repository, reference: &str) -> Result<Oid> {
+    // Try as a direct reference first (branch or tag)
+    if let Ok(reference) = repo.find_reference(reference) {
+        return Ok(reference.peel_to_commit()?.id());
+    }
+
+    // Try as a revision (commit hash, HEAD~1, etc)
+    if let Ok(revspec) = repo.revparse_single(reference) {
+        return Ok(revspec.peel_to_commit()?.id());
+    }
+
+    // Try as a short commit hash, using the object database's prefix index
+    if reference.len() >= 4 && reference.chars().all(|c| c.is_ascii_hexdigit()) {
+        match repo.find_object_by_prefix(reference, None) {
+            Ok(object) => return Ok(object.peel_to_commit()?.id()),
+            Err(e) if e.code() == git2::ErrorCode::Ambiguous => {
+                return Err(GitwiseError::InvalidInput(format!("Short hash {} is ambiguous; use more characters", reference)));
+            }
+            Err(_) => {}
+        }
+    }
+
+    Err(GitwiseError::InvalidInput(format!("Could not resolve git reference: {}", reference)))
+}
+
+/// Resolve a `from..to` range to its two commits, or `from...to` to the merge base
+/// of both and `to`, as `git diff` does. An empty side means HEAD. Returns `None`
+/// when `spec` is a single reference rather than a range.
+pub fn resolve_range(repo: &Repository, spec: &str) -> Result<Option<(Oid, Oid)>> {
+    let (from, to, three_dot) = match spec.split_once("...") {
+        Some((from, to)) => (from, to, true),
+        None => match spec.split_once("..") {
+            Some((from, to)) => (from, to, false),
+            None => return Ok(None),
+        },
+    };
+    let invalid = |reason: &str| GitwiseError::InvalidInput(format!("Invalid range '{}': {}", spec, reason));
+    if to.contains("..") || to.starts_with('.') {
+        return Err(invalid("use a single '..' or '...' between two references"));
+    }
+    if from.is_empty() && to.is_empty() {
+        return Err(invalid("give a reference on at least one side, e.g. HEAD~3..HEAD"));
+    }
+
+    let resolve = |reference: &str| resolve_reference(repo, if reference.is_empty() { "HEAD" } else { reference });
+    let (from, to) = (resolve(from)?, resolve(to)?);
+    if !three_dot {
+        return Ok(Some((from, to)));
+    }
+    let base = repo.merge_base(from, to)
+        .map_err(|_| invalid("the two sides share no history, so there is no merge base"))?;
+    Ok(Some((base, to)))
+}
+
+/// Get commits in a branch with their diffs; a limit of 0 walks every reachable commit
 pub fn get_log<'a>(repo: &'a Repository, branch_name: Option<&str>, limit: Option<u32>) -> Result<Vec<Commit<'a>>> {
+    get_filtered_log(repo, branch_name, limit, |_| true)
+}
+
+/// Which end of history a log starts from and which way it is listed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogOrder {
+    /// The newest commits, newest first
+    NewestFirst,
+    /// The newest commits, oldest first
+    Reverse,
+    /// The oldest commits, oldest first
+    FromStart,
+}
+
+impl LogOrder {
+    pub fn new(reverse: bool, from_start: bool) -> Self {
+        match (reverse, from_start) {
+            (_, true) => LogOrder::FromStart,
+            (true, false) => LogOrder::Reverse,
+            (false, false) => LogOrder::NewestFirst,
+        }
+    }
+}
+
+/// Like `get_log`, but only commits accepted by `filter` count towards the limit
+pub fn get_filtered_log<'a, F>(repo: &'a Repository, branch_name: Option<&str>, limit: Option<u32>, filter: F) -> Result<Vec<Commit<'a>>>
+where
+    F: Fn(&Commit<'a>) -> bool,
+{
+    get_ordered_log(repo, branch_name, limit, LogOrder::NewestFirst, filter)
+}
+
+/// Like `get_filtered_log`, listing commits in `order`. The limit always counts
+/// from the end the walk starts at, so `Reverse` shows the same commits as
+/// `NewestFirst` and only `FromStart` walks from the root.
+pub fn get_ordered_log<'a, F>(repo: &'a Repository, branch_name: Option<&str>, limit: Option<u32>, order: LogOrder, filter: F) -> Result<Vec<Commit<'a>>>
+where
+    F: Fn(&Commit<'a>) -> bool,
+{
     let mut revwalk = repo.revwalk()?;
+    if order == LogOrder::FromStart {
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    }
     
     // Start from HEAD or specified branch
     if let Some(branch) = branch_name {
         let branch_id = repo.find_branch(branch, git2::BranchType::Local)?.get().peel_to_commit()?.id();
         revwalk.push(branch_id)?;
     } else {
-        revwalk.push_head()?;
+        match super::staging::head_commit(repo)? {
+            Some(head) => revwalk.push(head.id())?,
+            // Nothing to walk before the first commit
+            None => return Ok(Vec::new()),
+        }
     }
 
-    // Limit number of commits if specified
-    let limit = limit.unwrap_or(10);
+    // Limit number of commits if specified; 0 means no limit
+    let limit = match limit.unwrap_or(10) {
+        0 => usize::MAX,
+        limit => limit as usize,
+    };
     
     let mut commits = Vec::new();
-    for (i, oid) in revwalk.enumerate() {
-        if i >= limit as usize {
+    for oid in revwalk {
+        if commits.len() >= limit {
             break;
         }
         let commit = repo.find_commit(oid?)?;
-        commits.push(commit);
+        if filter(&commit) {
+            commits.push(commit);
+        }
+    }
+    if order == LogOrder::Reverse {
+        commits.reverse();
     }
     
     Ok(commits)
 }
 
+/// Messages of the last `count` commits on HEAD, leaving out merges and fixup/squash commits
+pub fn recent_commit_messages(repo: &Repository, count: usize) -> Result<Vec<String>> {
+    let commits = get_filtered_log(repo, None, Some(count as u32), |commit| {
+        let summary = commit_summary(commit);
+        commit.parent_count() <= 1 && !summary.starts_with("fixup!") && !summary.starts_with("squash!")
+    })?;
+    Ok(commits.iter().map(commit_message).filter(|message| message != NO_COMMIT_MESSAGE).collect())
+}
+
+/// Check whether a commit's author name or email contains `pattern`, ignoring case
+pub fn author_matches(commit: &Commit<'_>, pattern: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let author = commit.author();
+    author.name().into_iter()
+        .chain(author.email())
+        .any(|field| field.to_lowercase().contains(&pattern))
+}
+
+/// When a commit was made, in the committer's own timezone
+pub fn commit_time(commit: &Commit<'_>) -> chrono::DateTime<chrono::FixedOffset> {
+    let time = commit.time();
+    let offset = chrono::FixedOffset::east_opt(time.offset_minutes() * 60)
+        .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap());
+    chrono::DateTime::from_timestamp(time.seconds(), 0)
+        .unwrap_or_default()
+        .with_timezone(&offset)
+}
+
+/// Shown in place of an empty commit message
+pub const NO_COMMIT_MESSAGE: &str = "(no commit message)";
+
+/// A commit's whole message for display, trimmed. Bytes that aren't valid UTF-8 are
+/// replaced instead of losing the message, which `Commit::message` would.
+pub fn commit_message(commit: &Commit<'_>) -> String {
+    let message = String::from_utf8_lossy(commit.message_bytes()).trim().to_string();
+    if message.is_empty() {
+        NO_COMMIT_MESSAGE.to_string()
+    } else {
+        message
+    }
+}
+
+/// The first line of `commit_message`
+pub fn commit_summary(commit: &Commit<'_>) -> String {
+    commit_message(commit).lines().next().unwrap_or(NO_COMMIT_MESSAGE).trim_end().to_string()
+}
+
+/// A commit's date as `git log` shows it, e.g. `Tue Mar 5 14:02:11 2024 +0100`
+pub fn format_commit_date(commit: &Commit<'_>) -> String {
+    commit_time(commit).format("%a %b %-d %H:%M:%S %Y %z").to_string()
+}
+
+/// Commits reachable from `head` but not from `base`, oldest first
+pub fn get_commits_between<'a>(repo: &'a Repository, base: &Commit<'a>, head: &Commit<'a>) -> Result<Vec<Commit<'a>>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(head.id())?;
+    revwalk.hide(base.id())?;
+
+    revwalk
+        .map(|oid| Ok(repo.find_commit(oid?)?))
+        .collect()
+}
+
+/// Name of the checked-out branch, or `None` when HEAD is detached (as during a
+/// rebase or bisect) or there is no commit yet
+pub fn current_branch_name(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    head.shorthand().filter(|_| head.is_branch()).map(str::to_string)
+}
+
+/// If HEAD is a branch whose upstream already contains the HEAD commit,
+/// return the upstream's name
+pub fn pushed_upstream(repo: &Repository) -> Result<Option<String>> {
+    match repo.head()?.target() {
+        Some(head_id) => upstream_containing(repo, head_id),
+        None => Ok(None),
+    }
+}
+
+/// If HEAD is a branch whose upstream contains `commit`, return the upstream's name
+pub fn upstream_containing(repo: &Repository, commit: Oid) -> Result<Option<String>> {
+    let Some(name) = current_branch_name(repo) else {
+        return Ok(None);
+    };
+
+    let upstream = match repo.find_branch(&name, git2::BranchType::Local)?.upstream() {
+        Ok(upstream) => upstream,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Some(upstream_id) = upstream.get().target() else {
+        return Ok(None);
+    };
+
+    if upstream_id == commit || repo.graph_descendant_of(upstream_id, commit)? {
+        Ok(Some(upstream.name()?.unwrap_or(&name).to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
 /// Get the diff for a commit
 pub fn get_commit_diff<'a>(repo: &'a Repository, commit: &Commit<'a>) -> Result<git2::Diff<'a>> {
-    let parent = commit.parent(0).ok();
+    get_commit_diff_with_context(repo, commit, super::context_lines())
+}
+
+/// Get the diff for a commit with `context` unchanged lines around each change
+pub fn get_commit_diff_with_context<'a>(repo: &'a Repository, commit: &Commit<'a>, context: u32) -> Result<git2::Diff<'a>> {
+    commit_diff(repo, commit, super::diff_options(), context)
+}
+
+/// Like `get_commit_diff`, keeping only paths matched by `patterns`, as for `--files`
+pub fn get_commit_diff_matching<'a>(repo: &'a Repository, commit: &Commit<'a>, patterns: &[String]) -> Result<git2::Diff<'a>> {
+    commit_diff(repo, commit, super::pathspec_options(patterns), super::context_lines())
+}
+
+/// Whether a commit changed any path matched by `patterns`
+pub fn touches_paths(repo: &Repository, commit: &Commit<'_>, patterns: &[String]) -> Result<bool> {
+    Ok(get_commit_diff_matching(repo, commit, patterns)?.deltas().len() > 0)
+}
+
+fn commit_diff<'a>(repo: &'a Repository, commit: &Commit<'a>, mut opts: git2::DiffOptions, context: u32) -> Result<git2::Diff<'a>> {
     let tree = commit.tree()?;
-    let parent_tree = parent.and_then(|p| p.tree().ok());
+    // Root commits are diffed against the empty tree
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(parent.tree()?),
+        Err(_) => None,
+    };
 
-    let mut opts = DiffOptions::new();
-    opts.context_lines(3)
+    opts.context_lines(context)
         .patience(true)
         .minimal(true);
 
-    let diff = match parent_tree {
-        Some(parent_tree) => repo.diff_tree_to_tree(Some(&parent_tree), Some(&tree), Some(&mut opts))?,
-        None => repo.diff_tree_to_tree(None, Some(&tree), Some(&mut opts))?,
-    };
+    let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut opts))?;
+    super::find_renames(&mut diff)?;
 
     Ok(diff)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Signature, Tree};
+    use tempfile::TempDir;
+
+    /// Commit `tree` on top of HEAD (or as the root commit on an unborn branch)
+    fn commit_on_head(repo: &Repository, signature: &Signature<'_>, message: &str, tree: &Tree<'_>) -> Oid {
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&Commit<'_>> = parent.iter().collect();
+        repo.commit(Some("HEAD"), signature, signature, message, tree, &parents).unwrap()
+    }
+
+    /// Commit `messages` one after another on HEAD, each with an empty tree
+    fn commit_chain(repo: &Repository, messages: &[&str]) -> Vec<Oid> {
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        messages.iter().map(|message| commit_on_head(repo, &signature, message, &tree)).collect()
+    }
+
+    #[test]
+    fn test_resolve_short_hash() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        let start = std::time::Instant::now();
+        assert_eq!(resolve_reference(&repo, &oid.to_string()[..7]).unwrap(), oid);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        assert_eq!(resolve_reference(&repo, "HEAD").unwrap(), oid);
+        assert!(resolve_reference(&repo, "no-such-branch").is_err());
+    }
+
+    #[test]
+    fn test_resolve_range() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let root = repo.commit(Some("HEAD"), &signature, &signature, "root", &tree, &[]).unwrap();
+        let root_commit = repo.find_commit(root).unwrap();
+        let main = repo.commit(Some("HEAD"), &signature, &signature, "main", &tree, &[&root_commit]).unwrap();
+        let side = repo.commit(None, &signature, &signature, "side", &tree, &[&root_commit]).unwrap();
+        repo.branch("side", &repo.find_commit(side).unwrap(), false).unwrap();
+
+        assert_eq!(resolve_range(&repo, "HEAD").unwrap(), None);
+        assert_eq!(resolve_range(&repo, "HEAD~1..HEAD").unwrap(), Some((root, main)));
+        assert_eq!(resolve_range(&repo, "HEAD~1..").unwrap(), Some((root, main)));
+        assert_eq!(resolve_range(&repo, "side..HEAD").unwrap(), Some((side, main)));
+        assert_eq!(resolve_range(&repo, "HEAD...side").unwrap(), Some((root, side)));
+
+        for malformed in ["..", "...", "HEAD~1..HEAD..side", "HEAD....side"] {
+            let error = resolve_range(&repo, malformed).unwrap_err().to_string();
+            assert!(error.starts_with(&format!("Invalid range '{}'", malformed)), "{}", error);
+        }
+        assert!(resolve_range(&repo, "nope..HEAD").is_err());
+    }
+
+    #[test]
+    fn test_commit_summary_of_odd_messages() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = repo.index().unwrap().write_tree().unwrap();
+        // libgit2 only takes UTF-8 messages, so write the commit object directly
+        let raw_commit = |message: &[u8]| {
+            let mut object = format!("tree {}\nauthor Test <test@example.com> 0 +0000\ncommitter Test <test@example.com> 0 +0000\n\n", tree).into_bytes();
+            object.extend_from_slice(message);
+            let oid = repo.odb().unwrap().write(git2::ObjectType::Commit, &object).unwrap();
+            repo.find_commit(oid).unwrap()
+        };
+
+        let latin1 = raw_commit(b"Fix caf\xe9 menu\n\nBody\n");
+        assert_eq!(latin1.summary(), None);
+        assert_eq!(commit_summary(&latin1), "Fix caf\u{fffd} menu");
+        assert_eq!(commit_message(&latin1), "Fix caf\u{fffd} menu\n\nBody");
+
+        let empty = raw_commit(b"");
+        assert_eq!(commit_summary(&empty), NO_COMMIT_MESSAGE);
+        assert_eq!(commit_summary(&raw_commit(b"\n  \n")), NO_COMMIT_MESSAGE);
+        assert_eq!(commit_summary(&raw_commit(b"\nLate subject  \nmore\n")), "Late subject");
+    }
+
+    #[test]
+    fn test_commit_diff_matching_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+
+        let mut parent: Option<Oid> = None;
+        for files in [&[("src/ai/mod.rs", "a"), ("README.md", "a")][..], &[("README.md", "b")], &[("src/ai/mod.rs", "b"), ("src/main.rs", "b")]] {
+            // An in-memory index builds the nested trees
+            let mut index = git2::Index::new().unwrap();
+            if let Some(oid) = parent {
+                index.read_tree(&repo.find_commit(oid).unwrap().tree().unwrap()).unwrap();
+            }
+            for (path, content) in files {
+                let blob = repo.blob(content.as_bytes()).unwrap();
+                let entry = git2::IndexEntry {
+                    ctime: git2::IndexTime::new(0, 0), mtime: git2::IndexTime::new(0, 0), dev: 0, ino: 0, mode: 0o100644,
+                    uid: 0, gid: 0, file_size: 0, id: blob, flags: 0, flags_extended: 0, path: path.as_bytes().to_vec(),
+                };
+                index.add(&entry).unwrap();
+            }
+            let tree = repo.find_tree(index.write_tree_to(&repo).unwrap()).unwrap();
+            parent = Some(commit_on_head(&repo, &signature, "change", &tree));
+        }
+
+        let paths = vec!["src/ai/".to_string()];
+        let commits = get_filtered_log(&repo, None, None, |commit| touches_paths(&repo, commit, &paths).unwrap_or(false)).unwrap();
+        assert_eq!(commits.len(), 2);
+        let diff = get_commit_diff_matching(&repo, &commits[0], &paths).unwrap();
+        let changed: Vec<String> = diff.deltas().map(|delta| delta.new_file().path().unwrap().display().to_string()).collect();
+        assert_eq!(changed, vec!["src/ai/mod.rs"]);
+        assert_eq!(get_commit_diff(&repo, &commits[0]).unwrap().deltas().len(), 2);
+    }
+
+    #[test]
+    fn test_commit_date_uses_commit_timezone() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        // 2024-03-05 12:32:11 UTC, committed at UTC-05:30
+        let signature = Signature::new("Test", "test@example.com", &git2::Time::new(1_709_641_931, -330)).unwrap();
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+        let commit = repo.find_commit(oid).unwrap();
+
+        assert_eq!(format_commit_date(&commit), "Tue Mar 5 07:02:11 2024 -0530");
+        assert_eq!(commit_time(&commit).to_rfc3339(), "2024-03-05T07:02:11-05:30");
+    }
+
+    #[test]
+    fn test_recent_commit_messages_skip_fixups() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        commit_chain(&repo, &["api: Add login\n\nWith tokens.\n", "fixup! api: Add login", "ui: Show errors", "api: Rate limit"]);
+
+        assert_eq!(recent_commit_messages(&repo, 3).unwrap(), vec!["api: Rate limit", "ui: Show errors", "api: Add login\n\nWith tokens."]);
+    }
+
+    #[test]
+    fn test_current_branch_name() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(current_branch_name(&repo), None);
+
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+        repo.branch("feature", &repo.find_commit(oid).unwrap(), false).unwrap();
+        repo.set_head("refs/heads/feature").unwrap();
+        assert_eq!(current_branch_name(&repo).as_deref(), Some("feature"));
+
+        repo.set_head_detached(oid).unwrap();
+        assert_eq!(current_branch_name(&repo), None);
+    }
+
+    #[test]
+    fn test_author_filter_applies_before_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+
+        for (name, email) in [("Alice", "alice@example.com"), ("Bob", "bob@example.com"), ("Bob", "bob@example.com"), ("Alice", "alice@example.com")] {
+            commit_on_head(&repo, &Signature::now(name, email).unwrap(), name, &tree);
+        }
+
+        let commits = get_filtered_log(&repo, None, Some(2), |commit| author_matches(commit, "ALICE@")).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert!(commits.iter().all(|commit| commit.author().name() == Some("Alice")));
+    }
+
+    #[test]
+    fn test_log_order_keeps_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        commit_chain(&repo, &["one", "two", "three", "four"]);
+
+        let summaries = |order| -> Vec<String> {
+            get_ordered_log(&repo, None, Some(2), order, |_| true).unwrap()
+                .iter().map(|commit| commit.summary().unwrap().to_string()).collect()
+        };
+        assert_eq!(summaries(LogOrder::NewestFirst), vec!["four", "three"]);
+        assert_eq!(summaries(LogOrder::Reverse), vec!["three", "four"]);
+        assert_eq!(summaries(LogOrder::FromStart), vec!["one", "two"]);
+        assert_eq!(get_log(&repo, None, Some(0)).unwrap().len(), 4);
+    }
+}
@@ -2,6 +2,14 @@ mod diff;
 mod log;
 pub mod staging;
 pub mod pr;
+pub mod sign;
+pub mod blame;
+pub mod stats;
+pub mod trailers;
+pub mod lint;
+pub mod stash;
+pub mod ticket;
+pub mod submodule;
 
 // Re-export commonly used items
 pub use diff::*;
@@ -1,21 +1,227 @@
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use anyhow::{Result, anyhow};
-use git2::Repository;
-use crate::ai::AiEngine;
+use crate::error::{GitwiseError, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use git2::{Commit, Repository};
+use tracing::warn;
+use crate::ai::{self, AiEngine};
 
+/// Where GitHub looks for a pull request template, relative to the repository root
+const TEMPLATE_PATHS: &[&str] = &[
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "pull_request_template.md",
+    "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+    "docs/PULL_REQUEST_TEMPLATE.md",
+];
+
+/// Number of commit summaries requested at once for `--from-commits`
+const COMMIT_SUMMARY_CONCURRENCY: usize = 4;
+
+/// Bitbucket Cloud REST API root
+const BITBUCKET_API: &str = "https://api.bitbucket.org/2.0";
+
+/// Host `gh` talks to unless GH_HOST says otherwise
+const GITHUB_HOST: &str = "github.com";
+
+/// Other names of github.com in remote URLs
+const GITHUB_ALIASES: &[&str] = &["ssh.github.com", "www.github.com"];
+
+/// Remote used when nothing else is configured
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Remote a fork conventionally uses for the repository it was forked from
+const UPSTREAM_REMOTE: &str = "upstream";
+
+/// A remote URL split into host and repository path, e.g. `github.com` and `owner/repo`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteUrl {
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
+}
+
+/// Parse `https://host/owner/repo.git`, `ssh://git@host/owner/repo` or `git@host:owner/repo.git`
+pub fn parse_remote_url(url: &str) -> Option<RemoteUrl> {
+    let url = url.trim().trim_end_matches('/');
+    let (host, path) = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?,
+        // scp-like syntax
+        None => url.split_once(':')?,
+    };
+    // Drop any user and port: `git@host:22` becomes `host`
+    let host = host.rsplit('@').next()?.split(':').next()?;
+    let (owner, repo) = path.trim_end_matches(".git").rsplit_once('/')?;
+    if host.is_empty() || owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+    Some(RemoteUrl { host: host.to_lowercase(), owner: owner.to_string(), repo: repo.to_string() })
+}
+
+/// The parsed URL of a remote, if it has one in a recognized form
+fn remote_url(repo: &Repository, name: &str) -> Option<RemoteUrl> {
+    repo.find_remote(name).ok().and_then(|remote| remote.url().and_then(parse_remote_url))
+}
+
+/// The remotes a pull request goes between: `base` has the branch it merges into and
+/// decides where it is opened, `head` has the branch it merges from. They differ when
+/// working from a fork.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrRemotes {
+    pub base: String,
+    pub head: String,
+}
+
+impl PrRemotes {
+    /// Pick the remotes for a pull request from `branch`. The base is `remote` when
+    /// given, else `upstream`, `origin` or the only remote there is. The head is where
+    /// git pushes the branch: `branch.<name>.pushRemote`, `remote.pushDefault`, then the
+    /// remote of its upstream branch. Without remotes, both are `origin`.
+    pub fn detect(repo: &Repository, branch: Option<&str>, remote: Option<&str>) -> Result<Self> {
+        let names: Vec<String> = repo.remotes()?.iter().flatten().map(str::to_string).collect();
+        let exists = |name: &str| names.iter().any(|existing| existing == name);
+
+        let base = match remote {
+            Some(remote) if exists(remote) => remote.to_string(),
+            Some(remote) => return Err(GitwiseError::InvalidInput(match names.is_empty() {
+                true => format!("No remote named '{}'; the repository has no remotes", remote),
+                false => format!("No remote named '{}'; the remotes are {}", remote, names.join(", ")),
+            })),
+            None => [UPSTREAM_REMOTE, DEFAULT_REMOTE].into_iter()
+                .find(|name| exists(name))
+                .map(str::to_string)
+                .or_else(|| (names.len() == 1).then(|| names[0].clone()))
+                .unwrap_or_else(|| DEFAULT_REMOTE.to_string()),
+        };
+
+        let config = repo.config()?;
+        let push_remote = branch.and_then(|branch| config.get_string(&format!("branch.{}.pushRemote", branch)).ok())
+            .or_else(|| config.get_string("remote.pushDefault").ok())
+            .or_else(|| branch.and_then(|branch| {
+                let upstream = repo.branch_upstream_remote(&format!("refs/heads/{}", branch)).ok()?;
+                upstream.as_str().map(str::to_string)
+            }));
+        let head = push_remote.filter(|name| exists(name))
+            .or_else(|| exists(DEFAULT_REMOTE).then(|| DEFAULT_REMOTE.to_string()))
+            .unwrap_or_else(|| base.clone());
+
+        Ok(Self { base, head })
+    }
+
+    fn is_fork(&self) -> bool {
+        self.base != self.head
+    }
+}
+
+/// Hosts `gh` is logged in to: the top-level keys of its `hosts.yml`
+fn gh_hosts() -> Vec<String> {
+    let dir = std::env::var_os("GH_CONFIG_DIR").map(PathBuf::from)
+        .or_else(|| std::env::var_os("XDG_CONFIG_HOME").map(|dir| PathBuf::from(dir).join("gh")))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("gh")))
+        .or_else(|| std::env::var_os("AppData").map(|dir| PathBuf::from(dir).join("GitHub CLI")));
+    let Some(contents) = dir.and_then(|dir| fs::read_to_string(dir.join("hosts.yml")).ok()) else {
+        return Vec::new();
+    };
+    contents.lines()
+        .filter(|line| !line.starts_with(char::is_whitespace) && !line.starts_with('#'))
+        .filter_map(|line| line.trim_end().strip_suffix(':'))
+        .map(|host| host.trim_matches(|c| c == '"' || c == '\'').to_lowercase())
+        .collect()
+}
+
+/// Where pull requests are opened, chosen from the base remote's host
+#[derive(Debug, Clone, PartialEq)]
+enum Backend {
+    /// GitHub, through the `gh` CLI; `host` is set for GitHub Enterprise Server
+    GitHub { host: Option<String> },
+    /// Bitbucket Cloud, through its REST API
+    Bitbucket { workspace: String, repo_slug: String },
+}
+
+impl Backend {
+    /// The backend for `remote`. `enterprise_hosts` are the GitHub Enterprise Server
+    /// hosts gh is pointed at; any other host, such as an SSH alias from `~/.ssh/config`,
+    /// is left for gh to resolve from the remotes itself.
+    fn detect(repo: &Repository, remote: &str, enterprise_hosts: &[String]) -> Self {
+        match remote_url(repo, remote) {
+            Some(remote) if remote.host == "bitbucket.org" => Backend::Bitbucket { workspace: remote.owner, repo_slug: remote.repo },
+            Some(remote) if remote.host != GITHUB_HOST
+                && !GITHUB_ALIASES.contains(&remote.host.as_str())
+                && enterprise_hosts.iter().any(|host| host.eq_ignore_ascii_case(&remote.host)) => Backend::GitHub { host: Some(remote.host) },
+            _ => Backend::GitHub { host: None },
+        }
+    }
+}
+
+/// A `gh` command aimed at `host`, for GitHub Enterprise Server. Without one, gh
+/// uses GH_HOST from the environment or github.com.
+fn gh_command(host: Option<&str>) -> Command {
+    let mut command = Command::new("gh");
+    if let Some(host) = host {
+        command.env("GH_HOST", host);
+    }
+    command
+}
+
+/// Credentials for the Bitbucket API: `BITBUCKET_USERNAME` and an app password
+fn bitbucket_credentials() -> Result<(String, String)> {
+    match (std::env::var("BITBUCKET_USERNAME"), std::env::var("BITBUCKET_APP_PASSWORD")) {
+        (Ok(username), Ok(password)) if !username.is_empty() && !password.is_empty() => Ok((username, password)),
+        _ => Err(GitwiseError::InvalidInput(
+            "Creating Bitbucket pull requests needs BITBUCKET_USERNAME and BITBUCKET_APP_PASSWORD (an app password with the pullrequest:write scope)".to_string()
+        )),
+    }
+}
+
+/// Check that pull requests can be opened for `repo` the way `create` would open them:
+/// `gh` is installed and logged in, or the Bitbucket credentials are set. Returns
+/// where they would be opened. `github_hosts` are Enterprise hosts from the config file.
+pub fn check_backend(repo: &Repository, github_hosts: &[String]) -> Result<String> {
+    let remotes = PrRemotes::detect(repo, super::current_branch_name(repo).as_deref(), None)?;
+    let enterprise_hosts: Vec<String> = github_hosts.iter().cloned().chain(gh_hosts()).collect();
+    match Backend::detect(repo, &remotes.base, &enterprise_hosts) {
+        Backend::GitHub { host } => {
+            let host = host.filter(|_| std::env::var_os("GH_HOST").is_none());
+            check_gh(host.as_deref())?;
+            let host = host.or_else(|| std::env::var("GH_HOST").ok()).unwrap_or_else(|| GITHUB_HOST.to_string());
+            Ok(format!("GitHub ({}) through gh", host))
+        }
+        Backend::Bitbucket { workspace, repo_slug } => {
+            bitbucket_credentials()?;
+            Ok(format!("Bitbucket Cloud ({}/{})", workspace, repo_slug))
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct PullRequest {
     pub title: Option<String>,
     pub body: Option<String>,
     pub base: Option<String>,
+    /// Source branch, instead of the checked-out one
+    pub head: Option<String>,
+    pub draft: bool,
+    pub reviewers: Vec<String>,
+    pub labels: Vec<String>,
+    pub from_commits: bool,
+    pub template: Option<PathBuf>,
+    /// GitHub host for `gh`, instead of the one in the base remote's URL
+    pub host: Option<String>,
+    /// Remote with the base branch, instead of the detected one
+    pub remote: Option<String>,
+    /// GitHub Enterprise Server hosts besides those `gh` is logged in to
+    pub github_hosts: Vec<String>,
+    /// Open the new PR in the browser
+    pub web: bool,
+    /// Tidy the wording of the generated body
+    pub polish: bool,
 }
 
 impl PullRequest {
     pub fn new() -> Self {
-        Self {
-            title: None,
-            body: None,
-            base: None,
-        }
+        Self::default()
     }
 
     pub fn with_title(mut self, title: String) -> Self {
@@ -33,66 +239,558 @@ impl PullRequest {
         self
     }
 
-    pub async fn create(&self) -> Result<()> {
+    /// Open the PR from this local branch instead of the current one
+    pub fn with_head(mut self, head: String) -> Self {
+        self.head = Some(head);
+        self
+    }
+
+    pub fn with_draft(mut self, draft: bool) -> Self {
+        self.draft = draft;
+        self
+    }
+
+    pub fn with_reviewers(mut self, reviewers: Vec<String>) -> Self {
+        self.reviewers = reviewers;
+        self
+    }
+
+    pub fn with_labels(mut self, labels: Vec<String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Describe the PR from its individual commits instead of the combined diff
+    pub fn with_from_commits(mut self, from_commits: bool) -> Self {
+        self.from_commits = from_commits;
+        self
+    }
+
+    /// Use this body template instead of the repository's pull request template
+    pub fn with_template(mut self, template: PathBuf) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    /// Open the PR on this GitHub Enterprise Server host instead of the base remote's
+    pub fn with_host(mut self, host: String) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// Treat remotes on these hosts as GitHub Enterprise Server, like those `gh` is logged in to
+    pub fn with_github_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.github_hosts = hosts;
+        self
+    }
+
+    /// Merge into a branch of this remote instead of the detected one
+    pub fn with_remote(mut self, remote: String) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    pub fn with_web(mut self, web: bool) -> Self {
+        self.web = web;
+        self
+    }
+
+    /// Fix grammar and tighten the wording of a generated body with a second AI pass
+    pub fn with_polish(mut self, polish: bool) -> Self {
+        self.polish = polish;
+        self
+    }
+
+    /// Open the pull request, returning its URL when the backend reports one
+    pub async fn create(&self, ai: &AiEngine) -> Result<Option<String>> {
         let repo = Repository::open_from_env()?;
-        let ai = AiEngine::new()?;
 
-        // Get the diff between the current branch and the base branch
-        let head = repo.head()?.peel_to_commit()?;
-        let base_branch = self.base.as_deref().unwrap_or("main");
-        
-        let base_commit = if let Ok(branch) = repo.find_branch(base_branch, git2::BranchType::Local) {
-            branch.get().peel_to_commit()?
-        } else if let Ok(branch) = repo.find_branch(&format!("origin/{}", base_branch), git2::BranchType::Remote) {
-            branch.get().peel_to_commit()?
-        } else {
-            return Err(anyhow!("Base branch '{}' not found", base_branch));
+        // A pull request needs a branch to merge from; HEAD is often detached mid-rebase or bisect
+        let head_branch = match &self.head {
+            Some(name) => name.clone(),
+            None => super::current_branch_name(&repo).ok_or_else(|| GitwiseError::InvalidInput(
+                "Cannot open a pull request from a detached HEAD; check out a branch or pass --head".to_string()
+            ))?,
         };
 
-        let diff = repo.diff_tree_to_tree(
+        let remotes = PrRemotes::detect(&repo, Some(&head_branch), self.remote.as_deref())?;
+        let backend = match &self.host {
+            Some(host) => Backend::GitHub { host: Some(host.clone()) },
+            None => {
+                let enterprise_hosts: Vec<String> = self.github_hosts.iter().cloned().chain(gh_hosts()).collect();
+                Backend::detect(&repo, &remotes.base, &enterprise_hosts)
+            }
+        };
+        // An explicit GH_HOST wins over the host guessed from the base remote
+        let gh_host = match &backend {
+            Backend::GitHub { host } if self.host.is_some() || std::env::var_os("GH_HOST").is_none() => host.as_deref(),
+            _ => None,
+        };
+        // The fork the branch is pushed to, for naming the head across repositories
+        let fork = remotes.is_fork()
+            .then(|| remote_url(&repo, &remotes.head))
+            .flatten();
+
+        // Fail before spending any tokens if the PR can't be opened
+        let credentials = match &backend {
+            Backend::GitHub { .. } => {
+                check_gh(gh_host)?;
+                None
+            }
+            Backend::Bitbucket { .. } => Some(bitbucket_credentials()?),
+        };
+
+        // Get the diff between the head branch and the base branch
+        let head = match &self.head {
+            Some(name) => {
+                let branch = repo.find_branch(name, git2::BranchType::Local)
+                    .map_err(|_| GitwiseError::InvalidInput(format!("Head branch '{}' not found locally", name)))?;
+                warn_if_behind(&repo, &branch);
+                branch.get().peel_to_commit()?
+            }
+            None => repo.head()?.peel_to_commit()?,
+        };
+        let base_branch = match &self.base {
+            Some(base) => base.clone(),
+            None => detect_default_branch(&repo, &remotes.base),
+        };
+        
+        let base_commit = super::find_base_commit(&repo, &base_branch, &remotes.base)?;
+
+        let mut diff = repo.diff_tree_to_tree(
             Some(&base_commit.tree()?),
             Some(&head.tree()?),
-            None,
+            Some(&mut super::diff_options()),
         )?;
+        super::find_renames(&mut diff)?;
 
         // Generate PR title and description using AI if not provided
         let title = match &self.title {
             Some(t) => t.clone(),
-            None => {
-                let commit_msg = ai.generate_commit_message(&diff).await?;
-                // Extract first line as title
-                commit_msg.lines().next()
-                    .ok_or_else(|| anyhow!("Failed to generate PR title"))?
-                    .to_string()
-            }
+            None => ai.generate_pr_title(&diff).await?,
         };
 
         let body = match &self.body {
             Some(b) => b.clone(),
             None => {
-                ai.summarize_diff(&diff, Some("Generate a detailed pull request description that explains the changes, their purpose, and any important implementation details. Include a high-level summary at the start.")).await?
+                let template = load_template(&repo, self.template.as_deref())?;
+                if self.from_commits {
+                    describe_commits(&repo, ai, &base_commit, &head, template.as_deref()).await?
+                } else {
+                    let mut prompt = "Generate a detailed pull request description that explains the changes, their purpose, and any important implementation details. Include a high-level summary at the start.".to_string();
+                    if let Some(template) = &template {
+                        prompt.push(' ');
+                        prompt.push_str(&ai::template_instruction(template));
+                    }
+                    ai.summarize_diff(&diff, Some(&prompt)).await?
+                }
             }
         };
 
-        let mut command = Command::new("gh");
+        let body = strip_comments(&body, comment_char(&repo));
+        let body = match self.polish && self.body.is_none() {
+            true => ai.polish_text(&body, &ai::TextConstraints::markdown()).await.unwrap_or_else(|e| {
+                warn!("Could not polish the PR description: {}", e);
+                body
+            }),
+            false => body,
+        };
+
+        if let (Backend::Bitbucket { workspace, repo_slug }, Some((username, password))) = (&backend, credentials) {
+            if !self.reviewers.is_empty() || !self.labels.is_empty() {
+                warn!("Bitbucket pull requests are created without --reviewer and --label");
+            }
+            let source_repo = fork.as_ref().map(|fork| format!("{}/{}", fork.owner, fork.repo));
+            let request = BitbucketRequest { workspace, repo_slug, source_repo: source_repo.as_deref(), username: &username, password: &password };
+            let url = request.create(&title, &body, &head_branch, &base_branch, self.draft).await?;
+            if let (true, Some(url)) = (self.web, &url) {
+                open_url(url);
+            }
+            return Ok(url);
+        }
+
+        let mut command = gh_command(gh_host);
         command.arg("pr").arg("create");
         
         command.arg("--title").arg(&title);
         command.arg("--body").arg(&body);
         
-        if let Some(base) = &self.base {
-            command.arg("--base").arg(base);
+        command.arg("--base").arg(&base_branch);
+        // gh picks the repository from the remotes itself; name it when ours may differ
+        if self.remote.is_some() || remotes.is_fork() {
+            if let Some(url) = remote_url(&repo, &remotes.base) {
+                command.arg("--repo").arg(format!("{}/{}/{}", url.host, url.owner, url.repo));
+            }
+        }
+        // Left to gh for the current branch, so it can offer to push it first
+        if let Some(fork) = &fork {
+            command.arg("--head").arg(format!("{}:{}", fork.owner, head_branch));
+        } else if self.head.is_some() {
+            command.arg("--head").arg(&head_branch);
         }
 
-        let output = command.output()?;
+        if self.draft {
+            command.arg("--draft");
+        }
+        for reviewer in &self.reviewers {
+            command.arg("--reviewer").arg(reviewer);
+        }
+        for label in &self.labels {
+            command.arg("--label").arg(label);
+        }
+
+        let output = command.output().map_err(gh_error)?;
         
         if !output.status.success() {
-            return Err(anyhow!(
+            return Err(GitwiseError::External(format!(
                 "Failed to create PR: {}",
                 String::from_utf8_lossy(&output.stderr)
-            ));
+            )));
+        }
+
+        let url = pr_url(&String::from_utf8_lossy(&output.stdout));
+        if let (true, Some(url)) = (self.web, &url) {
+            let opened = gh_command(gh_host).args(["pr", "view", "--web", url]).status();
+            if !opened.is_ok_and(|status| status.success()) {
+                warn!("Could not open {} in the browser", url);
+            }
+        }
+        Ok(url)
+    }
+}
+
+/// The PR URL `gh pr create` prints as the last line of its output
+fn pr_url(stdout: &str) -> Option<String> {
+    stdout.lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| line.starts_with("https://") || line.starts_with("http://"))
+        .map(str::to_string)
+}
+
+/// Open a URL with the platform's default handler, warning if that fails
+fn open_url(url: &str) {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    if !Command::new(program).arg(url).status().is_ok_and(|status| status.success()) {
+        warn!("Could not open {} in the browser", url);
+    }
+}
+
+/// Warn when a branch is missing commits that its upstream has
+fn warn_if_behind(repo: &Repository, branch: &git2::Branch<'_>) {
+    let Ok(upstream) = branch.upstream() else {
+        return;
+    };
+    let (Some(local), Some(remote)) = (branch.get().target(), upstream.get().target()) else {
+        return;
+    };
+    if let Ok((_, behind)) = repo.graph_ahead_behind(local, remote) {
+        if behind > 0 {
+            let name = branch.name().ok().flatten().unwrap_or("head branch");
+            let upstream_name = upstream.name().ok().flatten().unwrap_or("its upstream");
+            warn!("{} is {} commit(s) behind {}; the PR may not include the latest changes", name, behind, upstream_name);
+        }
+    }
+}
+
+/// A pull request to open through the Bitbucket Cloud API
+struct BitbucketRequest<'a> {
+    workspace: &'a str,
+    repo_slug: &'a str,
+    /// `workspace/repo` of the fork the head branch is in, if it isn't the target
+    source_repo: Option<&'a str>,
+    username: &'a str,
+    password: &'a str,
+}
+
+impl BitbucketRequest<'_> {
+    /// Open the pull request, returning the URL of its page
+    async fn create(&self, title: &str, body: &str, head: &str, base: &str, draft: bool) -> Result<Option<String>> {
+        let url = format!("{}/repositories/{}/{}/pullrequests", BITBUCKET_API, self.workspace, self.repo_slug);
+        let mut source = serde_json::json!({ "branch": { "name": head } });
+        if let Some(full_name) = self.source_repo {
+            source["repository"] = serde_json::json!({ "full_name": full_name });
+        }
+        let payload = serde_json::json!({
+            "title": title,
+            "description": body,
+            "source": source,
+            "destination": { "branch": { "name": base } },
+            "draft": draft,
+        });
+
+        let response = reqwest::Client::new()
+            .post(&url)
+            .basic_auth(self.username, Some(self.password))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(payload.to_string())
+            .send()
+            .await
+            .map_err(|e| GitwiseError::External(format!("Failed to reach the Bitbucket API: {}", e)))?;
+
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            // Errors come back as {"error": {"message": ...}}
+            let message = serde_json::from_str::<serde_json::Value>(&text)
+                .ok()
+                .and_then(|value| value["error"]["message"].as_str().map(str::to_string))
+                .unwrap_or(text);
+            return Err(GitwiseError::External(format!("Failed to create PR on Bitbucket ({}): {}", status, message)));
+        }
+        Ok(serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|value| value["links"]["html"]["href"].as_str().map(str::to_string)))
+    }
+}
+
+/// Make sure the GitHub CLI is installed and logged in
+fn check_gh(host: Option<&str>) -> Result<()> {
+    let mut command = gh_command(host);
+    command.args(["auth", "status"]);
+    if let Some(host) = host {
+        command.args(["--hostname", host]);
+    }
+    let output = command.output().map_err(gh_error)?;
+    if !output.status.success() {
+        let login = match host {
+            Some(host) => format!("gh auth login --hostname {}", host),
+            None => "gh auth login".to_string(),
+        };
+        return Err(GitwiseError::External(format!("GitHub CLI is not authenticated; run `{}` and try again", login)));
+    }
+    Ok(())
+}
+
+/// Explain a failure to start `gh`
+fn gh_error(error: std::io::Error) -> GitwiseError {
+    if error.kind() == std::io::ErrorKind::NotFound {
+        GitwiseError::External("GitHub CLI (gh) was not found on PATH; install it from https://cli.github.com to create pull requests".to_string())
+    } else {
+        GitwiseError::External(format!("Failed to run GitHub CLI (gh): {}", error))
+    }
+}
+
+/// The branch `<remote>/HEAD` points at, falling back to `main` then `master`
+pub fn detect_default_branch(repo: &Repository, remote: &str) -> String {
+    let prefix = format!("refs/remotes/{}/", remote);
+    let remote_head = repo.find_reference(&format!("{}HEAD", prefix))
+        .ok()
+        .and_then(|reference| reference.symbolic_target().map(str::to_string))
+        .and_then(|target| target.strip_prefix(&prefix).map(str::to_string));
+    if let Some(branch) = remote_head {
+        return branch;
+    }
+
+    ["main", "master"].into_iter()
+        .find(|name| {
+            repo.find_branch(name, git2::BranchType::Local).is_ok()
+                || repo.find_branch(&format!("{}/{}", remote, name), git2::BranchType::Remote).is_ok()
+        })
+        .unwrap_or("main")
+        .to_string()
+}
+
+/// The repository's `core.commentChar`, defaulting to `#`
+fn comment_char(repo: &Repository) -> char {
+    repo.config()
+        .and_then(|config| config.get_string("core.commentChar"))
+        .ok()
+        .and_then(|value| value.chars().next().filter(|_| value != "auto"))
+        .unwrap_or('#')
+}
+
+/// Remove comments from a PR body: `<!-- ... -->` blocks, which templates use for
+/// instructions, and lines starting with `comment_char` (alone or followed by a
+/// space).
+///
+/// Limitation: `#` comment lines can't be told apart from Markdown headings, so
+/// with the default `#` no lines are stripped, only `<!-- ... -->` blocks. Set
+/// `core.commentChar` to another character to have comment lines removed.
+fn strip_comments(body: &str, comment_char: char) -> String {
+    let mut text = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.find("<!--") {
+        text.push_str(rest[..start].trim_end_matches([' ', '\t']));
+        rest = rest[start..].find("-->").map_or("", |end| &rest[start + end + 3..]);
+    }
+    text.push_str(rest);
+
+    let kept: Vec<&str> = text.lines()
+        .filter(|line| {
+            let Some(rest) = line.strip_prefix(comment_char).filter(|_| comment_char != '#') else {
+                return true;
+            };
+            !(rest.is_empty() || rest.starts_with(' '))
+        })
+        .collect();
+    kept.join("\n").trim().to_string()
+}
+
+/// Generate a PR body from the messages and summaries of the commits on the branch
+async fn describe_commits(repo: &Repository, ai: &AiEngine, base: &Commit<'_>, head: &Commit<'_>, template: Option<&str>) -> Result<String> {
+    let commits = super::get_commits_between(repo, base, head)?;
+    if commits.is_empty() {
+        return Err(GitwiseError::InvalidInput("No commits between the base branch and HEAD".to_string()));
+    }
+
+    let diffs = commits.iter()
+        .map(|commit| super::get_commit_diff(repo, commit))
+        .collect::<Result<Vec<_>>>()?;
+
+    let summaries: Vec<String> = stream::iter(&diffs)
+        .map(|diff| ai.summarize_diff(diff, None))
+        .buffered(COMMIT_SUMMARY_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    let entries: Vec<(String, String)> = commits.iter()
+        .map(super::commit_message)
+        .zip(summaries)
+        .collect();
+
+    ai.generate_pr_body_from_commits(&entries, template).await
+}
+
+/// Read the PR body template: the given path, which must exist, or the first
+/// of GitHub's standard locations present in the repository
+fn load_template(repo: &Repository, path: Option<&Path>) -> Result<Option<String>> {
+    if let Some(path) = path {
+        return fs::read_to_string(path)
+            .map(Some)
+            .map_err(|e| GitwiseError::InvalidInput(format!("Failed to read PR template '{}': {}", path.display(), e)));
+    }
+
+    let Some(workdir) = repo.workdir() else {
+        return Ok(None);
+    };
+
+    Ok(TEMPLATE_PATHS.iter()
+        .map(|candidate| workdir.join(candidate))
+        .find_map(|candidate| fs::read_to_string(candidate).ok())
+        .filter(|template| !template.trim().is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_default_branch() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(detect_default_branch(&repo, "origin"), "main");
+
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let oid = repo.commit(None, &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        repo.reference("refs/heads/master", oid, true, "test").unwrap();
+        assert_eq!(detect_default_branch(&repo, "origin"), "master");
+
+        repo.reference("refs/remotes/origin/develop", oid, true, "test").unwrap();
+        repo.reference_symbolic("refs/remotes/origin/HEAD", "refs/remotes/origin/develop", true, "test").unwrap();
+        assert_eq!(detect_default_branch(&repo, "origin"), "develop");
+        assert_eq!(detect_default_branch(&repo, "upstream"), "master");
+    }
+
+    #[test]
+    fn test_detect_remotes() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let detect = |branch: Option<&str>, remote: Option<&str>| {
+            PrRemotes::detect(&repo, branch, remote).map(|remotes| (remotes.base, remotes.head))
+        };
+        let pair = |base: &str, head: &str| (base.to_string(), head.to_string());
+        assert_eq!(detect(Some("feature"), None).unwrap(), pair("origin", "origin"));
+        assert!(detect(None, Some("origin")).unwrap_err().to_string().contains("has no remotes"));
+
+        repo.remote("fork", "git@github.com:alice/gitwise.git").unwrap();
+        assert_eq!(detect(Some("feature"), None).unwrap(), pair("fork", "fork"));
+
+        repo.remote("origin", "git@github.com:alice/gitwise.git").unwrap();
+        repo.remote("upstream", "git@github.com:NethermindEth/gitwise.git").unwrap();
+        assert_eq!(detect(Some("feature"), None).unwrap(), pair("upstream", "origin"));
+        assert_eq!(detect(Some("feature"), Some("fork")).unwrap(), pair("fork", "origin"));
+        assert!(detect(None, Some("github")).unwrap_err().to_string().contains("the remotes are fork, origin, upstream"));
+
+        let mut config = repo.config().unwrap();
+        config.set_str("remote.pushDefault", "fork").unwrap();
+        assert_eq!(detect(Some("feature"), None).unwrap(), pair("upstream", "fork"));
+        config.set_str("branch.feature.pushRemote", "origin").unwrap();
+        assert_eq!(detect(Some("feature"), None).unwrap(), pair("upstream", "origin"));
+        assert_eq!(detect(None, None).unwrap(), pair("upstream", "fork"));
+    }
+
+    #[test]
+    fn test_load_template() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(load_template(&repo, None).unwrap(), None);
+
+        fs::create_dir(temp_dir.path().join(".github")).unwrap();
+        fs::write(temp_dir.path().join(".github/pull_request_template.md"), "## Testing\n- [ ] Tests pass\n").unwrap();
+        assert_eq!(load_template(&repo, None).unwrap().as_deref(), Some("## Testing\n- [ ] Tests pass\n"));
+
+        let custom = temp_dir.path().join("custom.md");
+        fs::write(&custom, "## Related issues\n").unwrap();
+        assert_eq!(load_template(&repo, Some(&custom)).unwrap().as_deref(), Some("## Related issues\n"));
+        assert!(load_template(&repo, Some(&temp_dir.path().join("missing.md"))).is_err());
+    }
+
+    #[test]
+    fn test_parse_remote_url() {
+        let github = RemoteUrl { host: "github.com".into(), owner: "NethermindEth".into(), repo: "gitwise".into() };
+        assert_eq!(parse_remote_url("https://github.com/NethermindEth/gitwise.git"), Some(github.clone()));
+        assert_eq!(parse_remote_url("git@github.com:NethermindEth/gitwise.git"), Some(github.clone()));
+        assert_eq!(parse_remote_url("ssh://git@github.com:22/NethermindEth/gitwise/"), Some(github));
+
+        let bitbucket = parse_remote_url("https://alice@bitbucket.org/acme/web-app.git").unwrap();
+        assert_eq!((bitbucket.host.as_str(), bitbucket.owner.as_str(), bitbucket.repo.as_str()), ("bitbucket.org", "acme", "web-app"));
+        assert_eq!(parse_remote_url("/srv/git/project.git"), None);
+
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let enterprise = vec!["github.mycorp.com".to_string()];
+        assert_eq!(Backend::detect(&repo, "origin", &enterprise), Backend::GitHub { host: None });
+        repo.remote("origin", "git@bitbucket.org:acme/web-app.git").unwrap();
+        assert_eq!(Backend::detect(&repo, "origin", &enterprise), Backend::Bitbucket { workspace: "acme".into(), repo_slug: "web-app".into() });
+        repo.remote_set_url("origin", "https://GitHub.MyCorp.com/platform/api.git").unwrap();
+        assert_eq!(Backend::detect(&repo, "origin", &enterprise), Backend::GitHub { host: Some("github.mycorp.com".into()) });
+        assert_eq!(Backend::detect(&repo, "origin", &[]), Backend::GitHub { host: None });
+
+        // SSH host aliases and github.com's other names are left for gh to resolve
+        for url in ["git@github-work:acme/api.git", "ssh://git@ssh.github.com:443/acme/api.git"] {
+            repo.remote_set_url("origin", url).unwrap();
+            assert_eq!(Backend::detect(&repo, "origin", &enterprise), Backend::GitHub { host: None }, "{}", url);
         }
+    }
+
+    #[test]
+    fn test_pr_url() {
+        let stdout = "\nCreating pull request for feature into main in acme/api\n\nhttps://github.mycorp.com/acme/api/pull/42\n";
+        assert_eq!(pr_url(stdout).as_deref(), Some("https://github.mycorp.com/acme/api/pull/42"));
+        assert_eq!(pr_url(""), None);
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let body = "<!-- Describe your change below -->\n# Login rework\n## Summary\nFixes #123 <!-- link the issue -->\n<!--\nList the tests\n-->\n#42 is related\n; not a comment\n";
+        assert_eq!(strip_comments(body, '#'), "# Login rework\n## Summary\nFixes #123\n\n#42 is related\n; not a comment");
+        assert_eq!(strip_comments("; hidden\n;\n# Title\n;kept\nText\n", ';'), "# Title\n;kept\nText");
+        assert_eq!(strip_comments("Text <!-- never closed", '#'), "Text");
 
-        Ok(())
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        assert_eq!(comment_char(&repo), '#');
+        repo.config().unwrap().set_str("core.commentChar", ";").unwrap();
+        assert_eq!(comment_char(&repo), ';');
     }
 }
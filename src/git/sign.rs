@@ -0,0 +1,207 @@
+use crate::error::{GitwiseError, Result};
+use git2::{Commit, Oid, Repository, Signature, Tree};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Signature formats supported by git's `gpg.format`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SigningFormat {
+    Gpg,
+    Ssh,
+}
+
+/// Signs commit content with the key configured for this repository
+#[derive(Debug, Clone)]
+pub struct Signer {
+    format: SigningFormat,
+    program: String,
+    key: String,
+}
+
+impl Signer {
+    /// Build a signer when `commit.gpgsign` is set or `force` is true.
+    /// Fails if signing is wanted but no `user.signingkey` is configured.
+    pub fn from_config(repo: &Repository, force: bool) -> Result<Option<Self>> {
+        let config = repo.config()?;
+
+        let enabled = force || config.get_bool("commit.gpgsign").unwrap_or(false);
+        if !enabled {
+            return Ok(None);
+        }
+
+        let format = match config.get_string("gpg.format").ok().as_deref() {
+            None | Some("openpgp") => SigningFormat::Gpg,
+            Some("ssh") => SigningFormat::Ssh,
+            Some(other) => return Err(GitwiseError::InvalidInput(format!("Unsupported gpg.format '{}' (expected 'openpgp' or 'ssh')", other))),
+        };
+
+        let key = config.get_string("user.signingkey")
+            .ok()
+            .filter(|key| !key.trim().is_empty())
+            .ok_or_else(|| GitwiseError::InvalidInput("Commit signing is enabled but no signing key is configured; set one with `git config user.signingkey <key>`".to_string()))?;
+
+        let program = match format {
+            SigningFormat::Gpg => config.get_string("gpg.program").unwrap_or_else(|_| "gpg".to_string()),
+            SigningFormat::Ssh => config.get_string("gpg.ssh.program").unwrap_or_else(|_| "ssh-keygen".to_string()),
+        };
+
+        Ok(Some(Self { format, program, key }))
+    }
+
+    /// Produce an armored detached signature over `content`
+    pub fn sign(&self, content: &str) -> Result<String> {
+        let mut command = Command::new(&self.program);
+        match self.format {
+            SigningFormat::Gpg => command.args(["--status-fd=2", "-bsau", &self.key]),
+            SigningFormat::Ssh => command.args(["-Y", "sign", "-n", "git", "-f", &self.key]),
+        };
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| GitwiseError::External(format!("Failed to run signing program '{}': {}", self.program, e)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(content.as_bytes())?;
+        }
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(GitwiseError::External(format!(
+                "Failed to sign commit with '{}': {}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        String::from_utf8(output.stdout)
+            .map_err(|_| GitwiseError::External(format!("Signing program '{}' produced a signature that is not valid UTF-8", self.program)))
+    }
+}
+
+/// Create a commit and advance HEAD to it, signing it when a signer is given
+pub fn commit_to_head(
+    repo: &Repository,
+    signature: &Signature<'_>,
+    message: &str,
+    tree: &Tree<'_>,
+    parents: &[&Commit<'_>],
+    signer: Option<&Signer>,
+) -> Result<Oid> {
+    match signer {
+        Some(signer) => create_signed(repo, signature, signature, message, tree, parents, signer),
+        None => Ok(repo.commit(Some("HEAD"), signature, signature, message, tree, parents)?),
+    }
+}
+
+/// Replace the HEAD commit with one using `tree` and `message`, keeping its
+/// author and parents, and sign it when a signer is given
+pub fn amend_head(
+    repo: &Repository,
+    committer: &Signature<'_>,
+    message: &str,
+    tree: &Tree<'_>,
+    signer: Option<&Signer>,
+) -> Result<Oid> {
+    let head = repo.head()?.peel_to_commit()?;
+
+    let Some(signer) = signer else {
+        return Ok(head.amend(Some("HEAD"), None, Some(committer), None, Some(message), Some(tree))?);
+    };
+
+    let parents: Vec<Commit<'_>> = head.parents().collect();
+    let parents: Vec<&Commit<'_>> = parents.iter().collect();
+    let author = head.author();
+    create_signed(repo, &author, committer, message, tree, &parents, signer)
+}
+
+/// Write a signed commit object and point HEAD at it
+fn create_signed(
+    repo: &Repository,
+    author: &Signature<'_>,
+    committer: &Signature<'_>,
+    message: &str,
+    tree: &Tree<'_>,
+    parents: &[&Commit<'_>],
+    signer: &Signer,
+) -> Result<Oid> {
+    let buffer = repo.commit_create_buffer(author, committer, message, tree, parents)?;
+    let content = buffer.as_str().ok_or_else(|| GitwiseError::InvalidInput("Commit content is not valid UTF-8".to_string()))?;
+    let commit_signature = signer.sign(content)?;
+    let oid = repo.commit_signed(content, &commit_signature, None)?;
+
+    // commit_signed only writes the object, so move HEAD ourselves
+    let reflog = format!("commit: {}", message.lines().next().unwrap_or(""));
+    let head = repo.find_reference("HEAD")?;
+    match head.symbolic_target() {
+        Some(branch) => {
+            repo.reference(branch, oid, true, &reflog)?;
+        }
+        None => repo.set_head_detached(oid)?,
+    }
+
+    Ok(oid)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_signed_commit_moves_head() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        // Stand-in for ssh-keygen that emits a fixed signature
+        let program = temp_dir.path().join("fake-sign");
+        fs::write(&program, "#!/bin/sh\ncat > /dev/null\necho '-----BEGIN SSH SIGNATURE-----'\necho 'c2ln'\necho '-----END SSH SIGNATURE-----'\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // Pin local values so the user's global config can't leak in
+        let mut config = repo.config().unwrap();
+        config.set_bool("commit.gpgsign", false).unwrap();
+        config.set_str("user.signingkey", "").unwrap();
+        assert!(Signer::from_config(&repo, true).is_err(), "signing without a key must fail");
+        config.set_str("gpg.format", "ssh").unwrap();
+        config.set_str("gpg.ssh.program", program.to_str().unwrap()).unwrap();
+        config.set_str("user.signingkey", "/nonexistent/key").unwrap();
+
+        assert!(Signer::from_config(&repo, false).unwrap().is_none());
+        let signer = Signer::from_config(&repo, true).unwrap().unwrap();
+
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let oid = commit_to_head(&repo, &signature, "Initial commit", &tree, &[], Some(&signer)).unwrap();
+
+        assert_eq!(repo.head().unwrap().target(), Some(oid));
+        let (commit_signature, _) = repo.extract_signature(&oid, None).unwrap();
+        assert!(commit_signature.as_str().unwrap().contains("BEGIN SSH SIGNATURE"));
+    }
+
+    #[test]
+    fn test_amend_head_keeps_author() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+
+        let author = Signature::now("Author", "author@example.com").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let first = commit_to_head(&repo, &author, "First", &tree, &[], None).unwrap();
+        let parent = repo.find_commit(first).unwrap();
+        commit_to_head(&repo, &author, "Second", &tree, &[&parent], None).unwrap();
+
+        let committer = Signature::now("Committer", "committer@example.com").unwrap();
+        let oid = amend_head(&repo, &committer, "Second, amended", &tree, None).unwrap();
+
+        let amended = repo.find_commit(oid).unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(oid));
+        assert_eq!(amended.message(), Some("Second, amended"));
+        assert_eq!(amended.author().name(), Some("Author"));
+        assert_eq!(amended.committer().name(), Some("Committer"));
+        assert_eq!(amended.parent_id(0).unwrap(), first);
+    }
+}
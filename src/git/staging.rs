@@ -1,20 +1,68 @@
-use anyhow::Result;
-use git2::{Repository, Diff, Status, StatusOptions};
+use crate::error::{GitwiseError, Result};
+use git2::{Commit, Repository, Diff, ErrorCode, Index, Status, StatusOptions, Tree};
+use std::collections::HashSet;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
 
+use crate::ai::FileSelection;
+
+/// The commit HEAD points at, or `None` before the first commit
+pub fn head_commit(repo: &Repository) -> Result<Option<Commit<'_>>> {
+    match repo.head() {
+        Ok(head) => Ok(Some(head.peel_to_commit()?)),
+        Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The tree HEAD points at, or `None` before the first commit
+pub fn head_tree(repo: &Repository) -> Result<Option<Tree<'_>>> {
+    Ok(head_commit(repo)?.map(|commit| commit.tree()).transpose()?)
+}
+
+/// Changes staged relative to HEAD; in a fresh repository everything in the index counts
 pub fn get_staged_changes<'a>(repo: &'a Repository) -> Result<Diff<'a>> {
-    let head_tree = repo.head()?.peel_to_tree()?;
+    get_staged_changes_matching(repo, &[])
+}
+
+/// Staged changes limited to paths matching `patterns` (see `pathspec_options`)
+pub fn get_staged_changes_matching<'a>(repo: &'a Repository, patterns: &[String]) -> Result<Diff<'a>> {
+    let head_tree = head_tree(repo)?;
     
-    let diff = repo.diff_tree_to_index(
-        Some(&head_tree),
-        None,
+    let mut diff = repo.diff_tree_to_index(
+        head_tree.as_ref(),
         None,
+        Some(&mut super::pathspec_options(patterns)),
     )?;
+    super::find_renames(&mut diff)?;
     
     Ok(diff)
 }
 
+/// Get staged changes restricted to the given file paths
+pub fn get_staged_changes_for_paths<'a>(repo: &'a Repository, paths: &[String]) -> Result<Diff<'a>> {
+    let head_tree = head_tree(repo)?;
+
+    let mut opts = super::diff_options();
+    opts.disable_pathspec_match(true);
+    for path in paths {
+        opts.pathspec(path);
+    }
+
+    let mut diff = repo.diff_tree_to_index(
+        head_tree.as_ref(),
+        None,
+        Some(&mut opts),
+    )?;
+    super::find_renames(&mut diff)?;
+
+    Ok(diff)
+}
+
 pub fn get_unstaged_changes<'a>(repo: &'a Repository) -> Result<Diff<'a>> {
-    let mut opts = git2::DiffOptions::new();
+    let mut opts = super::diff_options();
     opts.include_untracked(true);
     
     let diff = repo.diff_index_to_workdir(
@@ -25,13 +73,93 @@ pub fn get_unstaged_changes<'a>(repo: &'a Repository) -> Result<Diff<'a>> {
     Ok(diff)
 }
 
-pub fn stage_file(repo: &Repository, path: &str) -> Result<()> {
-    let mut index = repo.index()?;
-    index.add_path(path.as_ref())?;
-    index.write()?;
+/// Attempts at writing the index while another process holds `index.lock`, and the pause between them
+const INDEX_LOCK_ATTEMPTS: u32 = 10;
+const INDEX_LOCK_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Run an index operation, retrying briefly while another git process holds the index lock
+fn retry_locked<T>(mut operation: impl FnMut() -> std::result::Result<T, git2::Error>) -> Result<T> {
+    for _ in 1..INDEX_LOCK_ATTEMPTS {
+        match operation() {
+            Err(e) if e.code() == ErrorCode::Locked => thread::sleep(INDEX_LOCK_RETRY_DELAY),
+            result => return Ok(result?),
+        }
+    }
+    operation().map_err(|e| match e.code() {
+        ErrorCode::Locked => GitwiseError::External(
+            "Another git process is using the index (.git/index.lock exists); wait for it to finish, or delete the lock file if no git process is running".to_string()
+        ),
+        _ => e.into(),
+    })
+}
+
+/// Write the in-memory index back to disk. libgit2 writes `index.lock` and renames it
+/// over the index, so the lock is only held for this call and never left behind by
+/// an error; changes made in memory before it are written all at once or not at all.
+fn write_index(index: &mut Index) -> Result<()> {
+    let result = retry_locked(|| index.write());
+    if result.is_err() {
+        // The repository shares this index; don't let a later write pick up the failed changes
+        let _ = index.read(true);
+    }
+    result
+}
+
+/// Remove a path from the index, resetting it to its HEAD version.
+/// Before the first commit the entry is simply dropped from the index.
+pub fn unstage_file(repo: &Repository, path: &str) -> Result<()> {
+    match head_commit(repo)? {
+        Some(commit) => repo.reset_default(Some(commit.as_object()), [path])?,
+        None => {
+            let mut index = repo.index()?;
+            index.remove_path(Path::new(path))?;
+            write_index(&mut index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Stage only the selected hunks of a file's unstaged changes in `index`, in memory.
+/// Hunks are numbered from 0 in the order they appear in `get_unstaged_changes`.
+fn add_hunks(repo: &Repository, index: &mut Index, path: &str, hunk_indices: &[usize]) -> Result<()> {
+    // Same context as `get_unstaged_changes`, so hunks are split the same way
+    let mut opts = super::diff_options();
+    opts.include_untracked(true)
+        .show_untracked_content(true)
+        .disable_pathspec_match(true)
+        .pathspec(path);
+    let diff = repo.diff_index_to_workdir(Some(index), Some(&mut opts))?;
+
+    let mut current = 0;
+    let mut apply_opts = git2::ApplyOptions::new();
+    apply_opts.hunk_callback(|_hunk| {
+        let selected = hunk_indices.contains(&current);
+        current += 1;
+        selected
+    });
+
+    // Patch the index's own tree, then carry the file's resulting entry over
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let patched = repo.apply_to_tree(&tree, &diff, Some(&mut apply_opts))?;
+    match patched.get_path(Path::new(path), 0) {
+        Some(entry) => index.add(&entry)?,
+        None => index.remove_path(Path::new(path))?,
+    }
     Ok(())
 }
 
+/// Stage every file or hunk in a group, writing the index once at the end
+pub fn stage_selections(repo: &Repository, group: &[FileSelection]) -> Result<()> {
+    let mut index = repo.index()?;
+    for file in group {
+        match &file.hunks {
+            Some(hunk_indices) => add_hunks(repo, &mut index, &file.path, hunk_indices)?,
+            None => index.add_path(file.path.as_ref())?,
+        }
+    }
+    write_index(&mut index)
+}
+
 pub fn get_status(repo: &Repository) -> Result<Vec<(String, Status)>> {
     let mut status_opts = StatusOptions::new();
     status_opts
@@ -67,3 +195,132 @@ pub fn get_change_groups(repo: &Repository) -> Result<(Vec<String>, Vec<String>)
     
     Ok((staged, unstaged))
 }
+
+/// Normalize a repository-relative path as written by the model,
+/// e.g. `./src\\main.rs` becomes `src/main.rs`
+pub fn normalize_path(path: &str) -> String {
+    let mut path = path.trim().replace('\\', "/");
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest.to_string();
+    }
+    path.trim_start_matches('/').to_string()
+}
+
+/// Keep only selections naming a file with unstaged changes, normalizing their paths.
+/// Unknown paths are dropped with a warning and groups left empty are removed.
+pub fn filter_selections(groups: Vec<Vec<FileSelection>>, unstaged_files: &[String]) -> Vec<Vec<FileSelection>> {
+    let known: HashSet<&str> = unstaged_files.iter().map(String::as_str).collect();
+
+    groups.into_iter()
+        .map(|group| {
+            group.into_iter()
+                .filter_map(|file| {
+                    let path = normalize_path(&file.path);
+                    if known.contains(path.as_str()) {
+                        Some(FileSelection { path, ..file })
+                    } else {
+                        warn!("Ignoring unknown path suggested by the model: {}", file.path);
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn stage_file(repo: &Repository, path: &str) -> Result<()> {
+        stage_selections(repo, &[FileSelection { path: path.to_string(), hunks: None }])
+    }
+
+    #[test]
+    fn test_stage_hunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let file = temp_dir.path().join("lib.txt");
+
+        let original: Vec<String> = (1..=30).map(|i| format!("line {}", i)).collect();
+        fs::write(&file, original.join("\n") + "\n").unwrap();
+        stage_file(&repo, "lib.txt").unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        // Two changes far enough apart to produce separate hunks
+        let mut modified = original.clone();
+        modified[1] = "changed 2".to_string();
+        modified[27] = "changed 28".to_string();
+        fs::write(&file, modified.join("\n") + "\n").unwrap();
+
+        stage_selections(&repo, &[FileSelection { path: "lib.txt".to_string(), hunks: Some(vec![1]) }]).unwrap();
+
+        let staged = get_staged_changes(&repo).unwrap();
+        let mut added = Vec::new();
+        staged.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+            if line.origin_value() == git2::DiffLineType::Addition {
+                added.push(String::from_utf8_lossy(line.content()).trim_end().to_string());
+            }
+            true
+        }).unwrap();
+        assert_eq!(added, vec!["changed 28"]);
+    }
+
+    #[test]
+    fn test_stage_waits_for_index_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "a\n").unwrap();
+        let lock = repo.path().join("index.lock");
+
+        fs::write(&lock, "").unwrap();
+        let error = stage_file(&repo, "a.txt").unwrap_err();
+        assert!(error.to_string().starts_with("Another git process is using the index"));
+        assert!(repo.index().unwrap().is_empty());
+
+        // A lock released while retrying doesn't fail the stage
+        let release = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                thread::sleep(INDEX_LOCK_RETRY_DELAY * 2);
+                fs::remove_file(lock).unwrap();
+            })
+        };
+        stage_file(&repo, "a.txt").unwrap();
+        release.join().unwrap();
+        assert_eq!(repo.index().unwrap().len(), 1);
+        assert!(!lock.exists());
+    }
+
+    #[test]
+    fn test_staged_changes_without_head() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        fs::write(temp_dir.path().join("first.txt"), "hello\n").unwrap();
+        stage_file(&repo, "first.txt").unwrap();
+
+        assert!(head_tree(&repo).unwrap().is_none());
+        let diff = get_staged_changes(&repo).unwrap();
+        assert_eq!(diff.deltas().len(), 1);
+        assert_eq!(diff.get_delta(0).unwrap().status(), git2::Delta::Added);
+    }
+
+    #[test]
+    fn test_filter_selections() {
+        let unstaged = vec!["src/main.rs".to_string(), "README.md".to_string()];
+        let selection = |path: &str| FileSelection { path: path.to_string(), hunks: None };
+
+        let groups = filter_selections(vec![
+            vec![selection("./src/main.rs"), selection("src/missing.rs")],
+            vec![selection("made/up.rs")],
+            vec![selection("README.md")],
+        ], &unstaged);
+
+        assert_eq!(groups, vec![vec![selection("src/main.rs")], vec![selection("README.md")]]);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::error::{GitwiseError, Result};
+use git2::{Diff, Oid, Repository};
+
+/// One entry of `git stash list`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StashEntry {
+    /// Position in the stash, 0 being the most recent (`stash@{0}`)
+    pub index: usize,
+    pub message: String,
+    /// The stash commit, whose tree is the working tree at stash time
+    pub oid: Oid,
+}
+
+/// Every stash entry, most recent first
+pub fn list_stashes(repo: &mut Repository) -> Result<Vec<StashEntry>> {
+    let mut entries = Vec::new();
+    repo.stash_foreach(|index, message, oid| {
+        entries.push(StashEntry { index, message: message.to_string(), oid: *oid });
+        true
+    })?;
+    Ok(entries)
+}
+
+/// The stash entry at `index`
+pub fn find_stash(repo: &mut Repository, index: usize) -> Result<StashEntry> {
+    list_stashes(repo)?
+        .into_iter()
+        .find(|entry| entry.index == index)
+        .ok_or_else(|| GitwiseError::InvalidInput(format!("No stash entry stash@{{{}}}", index)))
+}
+
+/// Everything a stash would bring back: its tracked changes against the commit it
+/// was made on, plus any untracked files it saved. A stash commit's first parent is
+/// that base, the second holds the index and the optional third the untracked files.
+pub fn stash_diff<'a>(repo: &'a Repository, entry: &StashEntry) -> Result<Diff<'a>> {
+    let stash = repo.find_commit(entry.oid)?;
+    let base_tree = stash.parent(0)?.tree()?;
+
+    let mut diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&stash.tree()?), Some(&mut super::diff_options()))?;
+    if let Ok(untracked) = stash.parent(2) {
+        let untracked = repo.diff_tree_to_tree(None, Some(&untracked.tree()?), Some(&mut super::diff_options()))?;
+        diff.merge(&untracked)?;
+    }
+    super::find_renames(&mut diff)?;
+    Ok(diff)
+}
+
+/// Paths that were staged when the stash was made, which `git stash apply --index`
+/// restores to the index
+pub fn stash_staged_paths(repo: &Repository, entry: &StashEntry) -> Result<Vec<String>> {
+    let stash = repo.find_commit(entry.oid)?;
+    let base_tree = stash.parent(0)?.tree()?;
+    let index_tree = stash.parent(1)?.tree()?;
+
+    let diff = repo.diff_tree_to_tree(Some(&base_tree), Some(&index_tree), None)?;
+    Ok(diff.deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(|path| path.display().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Signature, StashFlags};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stash_diff_includes_index_and_untracked() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        fs::write(temp_dir.path().join("tracked.txt"), "one\n").unwrap();
+        fs::write(temp_dir.path().join("staged.txt"), "one\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(std::path::Path::new("tracked.txt")).unwrap();
+        index.add_path(std::path::Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+        drop(tree);
+
+        fs::write(temp_dir.path().join("staged.txt"), "two\n").unwrap();
+        index.add_path(std::path::Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+        fs::write(temp_dir.path().join("tracked.txt"), "two\n").unwrap();
+        fs::write(temp_dir.path().join("new.txt"), "new\n").unwrap();
+        repo.stash_save(&signature, "work in progress", Some(StashFlags::INCLUDE_UNTRACKED)).unwrap();
+        fs::write(temp_dir.path().join("tracked.txt"), "three\n").unwrap();
+        repo.stash_save(&signature, "later", None).unwrap();
+
+        let entries = list_stashes(&mut repo).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[1].message.ends_with("work in progress"));
+
+        assert!(find_stash(&mut repo, 2).is_err());
+        let entry = find_stash(&mut repo, 1).unwrap();
+        let diff = stash_diff(&repo, &entry).unwrap();
+        let mut paths: Vec<String> = diff.deltas().map(|delta| delta.new_file().path().unwrap().display().to_string()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["new.txt", "staged.txt", "tracked.txt"]);
+        assert_eq!(stash_staged_paths(&repo, &entry).unwrap(), vec!["staged.txt"]);
+    }
+}
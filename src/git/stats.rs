@@ -0,0 +1,91 @@
+use crate::error::Result;
+use git2::{Commit, Repository};
+
+/// Line counts for one author over a set of commits
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthorStats {
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// Aggregate insertions and deletions per author, most lines changed first.
+/// Merge commits are skipped: their changes are already counted in the
+/// commits they merged.
+pub fn author_stats(repo: &Repository, commits: &[Commit<'_>]) -> Result<Vec<AuthorStats>> {
+    let mut authors: Vec<AuthorStats> = Vec::new();
+
+    for commit in commits.iter().filter(|commit| commit.parent_count() <= 1) {
+        let stats = super::get_commit_diff(repo, commit)?.stats()?;
+        let author = commit.author();
+        let name = author.name().unwrap_or("Unknown").to_string();
+        let email = author.email().unwrap_or("").to_string();
+
+        let entry = match authors.iter().position(|entry| entry.email == email && entry.name == name) {
+            Some(i) => &mut authors[i],
+            None => {
+                authors.push(AuthorStats { name, email, commits: 0, insertions: 0, deletions: 0 });
+                authors.last_mut().unwrap()
+            }
+        };
+        entry.commits += 1;
+        entry.insertions += stats.insertions();
+        entry.deletions += stats.deletions();
+    }
+
+    authors.sort_by_key(|author| std::cmp::Reverse(author.insertions + author.deletions));
+    Ok(authors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, Signature};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    fn commit_file(repo: &Repository, name: &str, contents: &str, author: &Signature<'_>, parents: &[Oid]) -> Oid {
+        fs::write(repo.workdir().unwrap().join(name), contents).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents: Vec<Commit<'_>> = parents.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+        let parents: Vec<&Commit<'_>> = parents.iter().collect();
+        repo.commit(None, author, author, name, &tree, &parents).unwrap()
+    }
+
+    #[test]
+    fn test_author_stats_skip_merges() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let alice = Signature::now("Alice", "alice@example.com").unwrap();
+        let bob = Signature::now("Bob", "bob@example.com").unwrap();
+
+        let base = commit_file(&repo, "a.txt", "one\n", &alice, &[]);
+        let feature = commit_file(&repo, "b.txt", "one\ntwo\nthree\n", &bob, &[base]);
+        repo.checkout_tree(repo.find_commit(base).unwrap().as_object(), Some(git2::build::CheckoutBuilder::new().force())).unwrap();
+        let mut index = repo.index().unwrap();
+        index.read_tree(&repo.find_commit(base).unwrap().tree().unwrap()).unwrap();
+        index.write().unwrap();
+        let main = commit_file(&repo, "a.txt", "one\nfour\n", &alice, &[base]);
+
+        // The merge brings in Bob's file again relative to its first parent
+        let mut index = repo.index().unwrap();
+        index.read_tree(&repo.find_commit(feature).unwrap().tree().unwrap()).unwrap();
+        index.add_path(Path::new("a.txt")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let merge = repo.commit(None, &alice, &alice, "Merge", &tree, &[&repo.find_commit(main).unwrap(), &repo.find_commit(feature).unwrap()]).unwrap();
+
+        let commits: Vec<Commit<'_>> = [merge, main, feature, base].iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+        let stats = author_stats(&repo, &commits).unwrap();
+
+        assert_eq!(stats, vec![
+            AuthorStats { name: "Bob".into(), email: "bob@example.com".into(), commits: 1, insertions: 3, deletions: 0 },
+            AuthorStats { name: "Alice".into(), email: "alice@example.com".into(), commits: 2, insertions: 2, deletions: 0 },
+        ]);
+    }
+}
@@ -0,0 +1,127 @@
+use git2::{DiffDelta, FileMode, Oid, Repository};
+
+/// Commits listed in each direction of a submodule pointer move; the rest are counted
+const MAX_LISTED_COMMITS: usize = 20;
+
+/// Whether a delta is a submodule pointer (a gitlink) rather than a file
+pub fn is_submodule(delta: &DiffDelta<'_>) -> bool {
+    delta.old_file().mode() == FileMode::Commit || delta.new_file().mode() == FileMode::Commit
+}
+
+/// Describe a submodule pointer change in words, e.g. "submodule lib updated from
+/// 1a2b3c4 to 5d6e7f8", followed by the subjects of the commits it moved across
+/// when the submodule is checked out in `repo`. Without a checkout only the label
+/// is given, which still keeps the model from reading the commit ids as content.
+pub fn describe_submodule_change(repo: Option<&Repository>, delta: &DiffDelta<'_>) -> String {
+    let path = delta.new_file().path()
+        .or_else(|| delta.old_file().path())
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let old = Some(delta.old_file().id()).filter(|id| !id.is_zero() && delta.old_file().mode() == FileMode::Commit);
+    let new = Some(delta.new_file().id()).filter(|id| !id.is_zero() && delta.new_file().mode() == FileMode::Commit);
+
+    let (old, new) = match (old, new) {
+        (None, Some(new)) => return format!("(submodule {} added at {})\n", path, short(new)),
+        (Some(old), None) => return format!("(submodule {} removed, was at {})\n", path, short(old)),
+        (Some(old), Some(new)) if old != new => (old, new),
+        // The same commit on both sides: only the submodule's working tree changed
+        _ => return format!("(submodule {} has uncommitted changes)\n", path),
+    };
+
+    let mut text = format!("(submodule {} updated from {} to {})\n", path, short(old), short(new));
+    let Some(submodule) = repo.and_then(|repo| repo.find_submodule(&path).ok()?.open().ok()) else {
+        return text;
+    };
+    let added = commits_between(&submodule, old, new);
+    let removed = commits_between(&submodule, new, old);
+    match (added, removed) {
+        (Some(added), Some(removed)) => {
+            push_commits(&mut text, "New commits", &added);
+            push_commits(&mut text, "Commits no longer included", &removed);
+        }
+        _ => text.push_str("(the submodule checkout doesn't have both commits)\n"),
+    }
+    text
+}
+
+/// Summaries of the commits reachable from `to` but not from `from`, newest first,
+/// or `None` when either commit is missing from the submodule
+fn commits_between(submodule: &Repository, from: Oid, to: Oid) -> Option<Vec<String>> {
+    let mut revwalk = submodule.revwalk().ok()?;
+    revwalk.push(to).ok()?;
+    revwalk.hide(from).ok()?;
+    revwalk
+        .map(|oid| {
+            let commit = submodule.find_commit(oid.ok()?).ok()?;
+            Some(format!("{} {}", short(commit.id()), super::commit_summary(&commit)))
+        })
+        .collect()
+}
+
+fn push_commits(text: &mut String, heading: &str, commits: &[String]) {
+    if commits.is_empty() {
+        return;
+    }
+    text.push_str(&format!("{}:\n", heading));
+    for commit in commits.iter().take(MAX_LISTED_COMMITS) {
+        text.push_str(&format!("  {}\n", commit));
+    }
+    if commits.len() > MAX_LISTED_COMMITS {
+        text.push_str(&format!("  … and {} more\n", commits.len() - MAX_LISTED_COMMITS));
+    }
+}
+
+fn short(oid: Oid) -> String {
+    oid.to_string()[..7].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_describe_submodule_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = Repository::init(temp_dir.path()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+
+        // A checked-out submodule with three commits
+        let submodule = Repository::init(temp_dir.path().join("lib")).unwrap();
+        let mut commits = Vec::new();
+        for subject in ["Initial import", "Fix parser crash", "Add streaming API"] {
+            let tree = submodule.find_tree(submodule.treebuilder(None).unwrap().write().unwrap()).unwrap();
+            let parents: Vec<git2::Commit> = commits.last().map(|oid| submodule.find_commit(*oid).unwrap()).into_iter().collect();
+            let parents: Vec<&git2::Commit> = parents.iter().collect();
+            commits.push(submodule.commit(Some("HEAD"), &signature, &signature, subject, &tree, &parents).unwrap());
+        }
+        fs::write(temp_dir.path().join(".gitmodules"), "[submodule \"lib\"]\n\tpath = lib\n\turl = ./lib\n").unwrap();
+
+        let tree_at = |commit: Option<Oid>| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            if let Some(commit) = commit {
+                builder.insert("lib", commit, FileMode::Commit.into()).unwrap();
+            }
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let describe = |old: Option<Oid>, new: Option<Oid>, checkout: Option<&Repository>| {
+            let diff = repo.diff_tree_to_tree(Some(&tree_at(old)), Some(&tree_at(new)), None).unwrap();
+            let delta = diff.deltas().next().unwrap();
+            assert!(is_submodule(&delta));
+            describe_submodule_change(checkout, &delta)
+        };
+        let (first, last) = (short(commits[0]), short(commits[2]));
+
+        let text = describe(Some(commits[0]), Some(commits[2]), Some(&repo));
+        assert_eq!(text, format!(
+            "(submodule lib updated from {} to {})\nNew commits:\n  {} Add streaming API\n  {} Fix parser crash\n",
+            first, last, last, short(commits[1]),
+        ));
+        assert!(describe(Some(commits[2]), Some(commits[0]), Some(&repo)).contains("Commits no longer included:\n"));
+        assert_eq!(describe(Some(commits[0]), Some(commits[2]), None), format!("(submodule lib updated from {} to {})\n", first, last));
+        assert_eq!(describe(None, Some(commits[0]), Some(&repo)), format!("(submodule lib added at {})\n", first));
+        assert_eq!(describe(Some(commits[2]), None, Some(&repo)), format!("(submodule lib removed, was at {})\n", last));
+    }
+}
@@ -0,0 +1,84 @@
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::error::{GitwiseError, Result};
+
+/// Pattern for ticket ids such as `JIRA-123` in branch names
+pub const DEFAULT_TICKET_PATTERN: &str = r"[A-Z]+-\d+";
+
+/// Trailer key for the ticket when it isn't set in the config
+pub const DEFAULT_TICKET_TRAILER: &str = "Refs";
+
+/// Where a ticket id found in the branch name goes in the commit message
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketPlacement {
+    /// A `Refs: JIRA-123` trailer
+    #[default]
+    Trailer,
+    /// A `[JIRA-123]` prefix on the subject line
+    Subject,
+}
+
+/// A regular expression for ticket ids, in the syntax of the `regex` crate
+#[derive(Debug, Clone)]
+pub struct TicketPattern {
+    regex: Regex,
+}
+
+impl TicketPattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| GitwiseError::InvalidInput(format!("Invalid ticket pattern '{}': {}", pattern, e)))?;
+        Ok(Self { regex })
+    }
+
+    /// The leftmost non-empty match in `text`
+    pub fn find<'a>(&self, text: &'a str) -> Option<&'a str> {
+        self.regex.find_iter(text).map(|m| m.as_str()).find(|m| !m.is_empty())
+    }
+}
+
+/// Add `ticket` to a commit message as `placement` says, unless the message already
+/// mentions it
+pub fn add_ticket(message: &str, ticket: &str, trailer: &str, placement: TicketPlacement) -> String {
+    if message.contains(ticket) {
+        return message.to_string();
+    }
+    match placement {
+        TicketPlacement::Trailer => super::trailers::append_trailers(message, trailer, &[ticket.to_string()]),
+        TicketPlacement::Subject => format!("[{}] {}", ticket, message.trim_start()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticket_pattern() {
+        let default = TicketPattern::new(DEFAULT_TICKET_PATTERN).unwrap();
+        assert_eq!(default.find("feature/JIRA-123-login"), Some("JIRA-123"));
+        assert_eq!(default.find("fix/ab-12-typo"), None);
+        assert_eq!(default.find("main"), None);
+
+        assert_eq!(TicketPattern::new(r"#?\d{2,4}").unwrap().find("bug/#4821-crash"), Some("#4821"));
+        assert_eq!(TicketPattern::new(r"^[a-z]+/").unwrap().find("feature/x"), Some("feature/"));
+        assert_eq!(TicketPattern::new(r"^\d+").unwrap().find("feature/12"), None);
+        assert_eq!(TicketPattern::new(r"[A-Z]+-\d+$").unwrap().find("PROJ-1-PROJ-22"), Some("PROJ-22"));
+        assert_eq!(TicketPattern::new(r"ö\w+").unwrap().find("wip/öx_1"), Some("öx_1"));
+        assert_eq!(TicketPattern::new(r"(JIRA|OPS)-\d+").unwrap().find("hotfix/OPS-7-disk"), Some("OPS-7"));
+        assert_eq!(TicketPattern::new(r"x*").unwrap().find("feature/x"), Some("x"));
+
+        for invalid in ["(JIRA|PROJ-\\d+", "[A-Z", "+1", "[z-a]"] {
+            assert!(TicketPattern::new(invalid).unwrap_err().to_string().starts_with("Invalid ticket pattern"), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_add_ticket() {
+        assert_eq!(add_ticket("Add login\n\nWith tokens.", "JIRA-123", "Refs", TicketPlacement::Trailer), "Add login\n\nWith tokens.\n\nRefs: JIRA-123");
+        assert_eq!(add_ticket("Add login", "JIRA-123", "Issue", TicketPlacement::Subject), "[JIRA-123] Add login");
+        assert_eq!(add_ticket("JIRA-123: Add login", "JIRA-123", "Refs", TicketPlacement::Trailer), "JIRA-123: Add login");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::error::{GitwiseError, Result};
+
+/// Trailer crediting another author of a commit
+pub const CO_AUTHORED_BY: &str = "Co-authored-by";
+
+/// Trailer certifying the committer wrote the change or may submit it (`git commit -s`)
+pub const SIGNED_OFF_BY: &str = "Signed-off-by";
+
+/// Validate a `Name <email>` identity, normalizing the spacing
+pub fn parse_identity(value: &str) -> Result<String> {
+    let invalid = || GitwiseError::InvalidInput(format!("Invalid identity '{}': expected \"Name <email>\"", value));
+
+    let (name, email) = value.trim()
+        .strip_suffix('>')
+        .and_then(|rest| rest.split_once('<'))
+        .ok_or_else(invalid)?;
+    let (name, email) = (name.trim(), email.trim());
+    if name.is_empty() || email.contains(['<', '>', ' ']) || !email.contains('@') {
+        return Err(invalid());
+    }
+
+    Ok(format!("{} <{}>", name, email))
+}
+
+/// Parse a `Key: value` (or `Key=value`) trailer
+pub fn parse_trailer(value: &str) -> Result<(String, String)> {
+    let invalid = || GitwiseError::InvalidInput(format!("Invalid trailer '{}': expected \"Key: value\"", value));
+
+    let (key, text) = value.split_once([':', '=']).ok_or_else(invalid)?;
+    let (key, text) = (key.trim(), text.trim());
+    if key.is_empty() || text.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(invalid());
+    }
+
+    Ok((key.to_string(), text.to_string()))
+}
+
+/// Append `key: value` trailers to a commit message, skipping any already present.
+/// They are added to the message's existing trailer block, or after a blank line.
+pub fn append_trailers(message: &str, key: &str, values: &[String]) -> String {
+    let message = message.trim_end();
+    let existing: Vec<String> = message.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(line_key, _)| line_key.trim().eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim().to_lowercase())
+        .collect();
+
+    let mut added: Vec<String> = Vec::new();
+    for value in values {
+        let normalized = value.trim().to_lowercase();
+        if !existing.contains(&normalized) && !added.iter().any(|v| v.to_lowercase() == normalized) {
+            added.push(value.trim().to_string());
+        }
+    }
+    if added.is_empty() {
+        return message.to_string();
+    }
+
+    let mut result = message.to_string();
+    result.push_str(if ends_with_trailers(message) { "\n" } else { "\n\n" });
+    let lines: Vec<String> = added.iter().map(|value| format!("{}: {}", key, value)).collect();
+    result.push_str(&lines.join("\n"));
+    result
+}
+
+/// Append trailers with differing keys in order, each collated into the trailer block
+pub fn append_all_trailers(message: &str, trailers: &[(String, String)]) -> String {
+    trailers.iter().fold(message.trim_end().to_string(), |message, (key, value)| {
+        append_trailers(&message, key, std::slice::from_ref(value))
+    })
+}
+
+/// Whether the last paragraph of a message (other than its subject) consists of trailers
+fn ends_with_trailers(message: &str) -> bool {
+    let Some((_, last)) = message.rsplit_once("\n\n") else {
+        return false;
+    };
+    last.lines().all(|line| {
+        line.split_once(": ")
+            .is_some_and(|(key, _)| !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_co_author_trailers() {
+        assert_eq!(parse_identity(" Jane Doe  <jane@example.com> ").unwrap(), "Jane Doe <jane@example.com>");
+        assert!(parse_identity("jane@example.com").is_err());
+        assert!(parse_identity("<jane@example.com>").is_err());
+        assert!(parse_identity("Jane <not an email>").is_err());
+
+        let authors = vec!["Jane <jane@example.com>".to_string(), "jane <JANE@example.com>".to_string(), "Sam <sam@example.com>".to_string()];
+        let message = append_trailers("Fix login\n\nHandle expired tokens.\n", CO_AUTHORED_BY, &authors);
+        assert_eq!(message, "Fix login\n\nHandle expired tokens.\n\nCo-authored-by: Jane <jane@example.com>\nCo-authored-by: Sam <sam@example.com>");
+
+        // Re-applying to a message that already has them changes nothing
+        assert_eq!(append_trailers(&message, CO_AUTHORED_BY, &authors), message);
+
+        let signed = append_trailers("Fix login\n\nSigned-off-by: Me <me@example.com>", CO_AUTHORED_BY, &authors[2..]);
+        assert_eq!(signed, "Fix login\n\nSigned-off-by: Me <me@example.com>\nCo-authored-by: Sam <sam@example.com>");
+    }
+
+    #[test]
+    fn test_custom_trailers() {
+        assert_eq!(parse_trailer("Reviewed-by: Sam <sam@example.com>").unwrap(), ("Reviewed-by".to_string(), "Sam <sam@example.com>".to_string()));
+        assert_eq!(parse_trailer("Fixes=#12").unwrap(), ("Fixes".to_string(), "#12".to_string()));
+        assert!(parse_trailer("Reviewed by: Sam").is_err());
+        assert!(parse_trailer("Reviewed-by:").is_err());
+
+        let trailers = vec![
+            ("Reviewed-by".to_string(), "Sam <sam@example.com>".to_string()),
+            (SIGNED_OFF_BY.to_string(), "Me <me@example.com>".to_string()),
+        ];
+        let message = append_all_trailers("Fix login\n\nCo-authored-by: Jane <jane@example.com>\n", &trailers);
+        assert_eq!(message, "Fix login\n\nCo-authored-by: Jane <jane@example.com>\nReviewed-by: Sam <sam@example.com>\nSigned-off-by: Me <me@example.com>");
+    }
+}
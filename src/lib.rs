@@ -0,0 +1,16 @@
+//! gitwise as a library: the AI engine, git helpers and configuration behind the
+//! `gitwise` command, for tools that want to generate summaries or commit messages
+//! without shelling out to the binary.
+
+/// Provider clients, prompts and the `AiEngine` that drives them
+pub mod ai;
+/// Layered `.gitwise.toml` configuration
+pub mod config;
+/// The `GitwiseError` type shared by the library modules
+pub mod error;
+/// Diff, history, staging and pull request helpers built on git2
+pub mod git;
+/// Terminal UI: the interactive file picker and the progress spinner
+pub mod ui;
+/// Small terminal helpers shared by the modules above
+pub mod utils;
@@ -1,15 +1,26 @@
-use anyhow::{Result, anyhow};
-use clap::{Parser, Subcommand, ValueEnum};
+use anyhow::{Result, Context, anyhow};
+use clap::{Parser, Subcommand};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::io::{IsTerminal, Write};
 use git2::{Repository, Oid};
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::fmt;
 
-mod ai;
-mod utils;
-mod git;
+mod cache;
 
+use gitwise::{ai, config, error, git, ui, utils};
+use cache::SummaryCache;
 use git::staging;
 
+/// Default upper bound on commits summarized by a single `history` run
+const DEFAULT_HISTORY_CAP: u32 = 50;
+/// Environment variable overriding the history cap
+const HISTORY_CAP_ENV: &str = "GITWISE_HISTORY_CAP";
+/// Commit messages sampled by `commit --learn-style` unless `style.sample_count` is set
+const DEFAULT_STYLE_SAMPLES: usize = 10;
+/// Number of commit summaries requested from the AI provider at once
+const HISTORY_CONCURRENCY: usize = 4;
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
@@ -18,13 +29,81 @@ struct Cli {
     verbose: bool,
 
     /// Force a specific AI model provider
-    #[arg(long, value_enum, help = "Force a specific AI model provider (e.g., 'anthropic' or 'openai')")]
+    #[arg(long, value_enum, help = "Force a specific AI model provider ('anthropic', 'openai' or 'azure'), or 'auto' to pick one by request size")]
     model: Option<ModelProvider>,
 
+    /// Model name to use with the selected provider
+    #[arg(long, global = true, help = "Model name to use, overriding ANTHROPIC_MODEL and OPENAI_MODEL (e.g., 'claude-3-5-sonnet-20240620' or 'gpt-4o')")]
+    model_name: Option<String>,
+
+    /// Language for generated text (defaults to GITWISE_LANG, then English)
+    #[arg(long, global = true, help = "Language for generated commit messages and summaries (e.g., 'Spanish'); defaults to GITWISE_LANG or English")]
+    language: Option<String>,
+
+    /// Sampling temperature for the AI model (defaults to GITWISE_TEMPERATURE, then 0.2)
+    #[arg(long, global = true, help = "Sampling temperature between 0.0 and 2.0; lower is more deterministic (defaults to GITWISE_TEMPERATURE or 0.2)")]
+    temperature: Option<f32>,
+
+    /// Maximum commit subject length (defaults to 50)
+    #[arg(long, global = true, help = "Maximum length of generated commit subject lines (default 50)")]
+    subject_len: Option<usize>,
+
+    /// Column to wrap commit message bodies at (defaults to 72)
+    #[arg(long, global = true, help = "Wrap generated commit message bodies at this column (default 72)")]
+    body_wrap: Option<usize>,
+
+    /// Unchanged lines shown around each change in diffs sent to the AI
+    #[arg(long, global = true, value_name = "N", help = "Lines of context around each change in diffs (default 3); 0 keeps requests small")]
+    context_lines: Option<u32>,
+
+    /// Write the result to a file instead of stdout
+    #[arg(short, long, global = true, value_name = "FILE", help = "Write the output of diff, history, log and changelog to this file instead of stdout")]
+    output: Option<std::path::PathBuf>,
+
+    /// Append to the --output file instead of replacing it
+    #[arg(long, global = true, requires = "output", help = "Append to the --output file instead of overwriting it")]
+    append: bool,
+
+    /// Answer yes to confirmation prompts
+    #[arg(short, long, global = true, help = "Don't ask for confirmation (before staging, committing, rewriting history, or sending unusually large requests)")]
+    yes: bool,
+
+    /// Don't retry on the other provider when the preferred one is unavailable
+    #[arg(long, global = true, help = "Fail instead of retrying on the other AI provider when the preferred one is overloaded or unreachable")]
+    no_fallback: bool,
+
+    /// Print plain text without ANSI colors
+    #[arg(long, global = true, help = "Don't color output (also disabled by setting NO_COLOR, or when stdout is not a terminal)")]
+    no_color: bool,
+
+    /// Report failures as JSON on stderr
+    #[arg(long, global = true, help = "On failure, print {\"error\", \"kind\", \"context\"} as JSON on stderr instead of a plain message")]
+    json_errors: bool,
+
+    /// Send diffs without redacting likely secrets
+    #[arg(long, global = true, help = "Don't replace likely secrets (API keys, tokens, passwords, private keys) in diffs with <REDACTED> before sending them")]
+    no_redact: bool,
+
+    /// Never prompt, page, animate or color output
+    #[arg(long, global = true, visible_alias = "ci", help = "For cron jobs and CI: answer every confirmation with no (unless --yes), print without a pager, spinner or colors, and refuse the terminal UIs")]
+    non_interactive: bool,
+
+    /// Print each prompt sent to the AI on stderr
+    #[arg(long, global = true, value_name = "CHARS", num_args = 0..=1, require_equals = true, default_missing_value = "2000", help = "Print the system prompt and user message sent to the AI on stderr, each cut to CHARS characters (default 2000, 0 for no limit)")]
+    show_prompt: Option<usize>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+impl Cli {
+    /// Whether someone is at the terminal to answer prompts and read paged, colored
+    /// output; `--non-interactive` says no regardless of the terminal
+    fn is_interactive(&self) -> bool {
+        !self.non_interactive && std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Intelligently stage changes by feature
@@ -32,23 +111,85 @@ enum Commands {
         /// Custom prompt for feature analysis
         #[arg(long, help = "Custom prompt for feature analysis (e.g., 'Focus on UI changes' or 'Look for security-related changes')")]
         prompt: Option<String>,
+        /// Read the custom prompt from a file
+        #[arg(long, conflicts_with = "prompt", help = "Read the custom prompt from a file instead of passing it with --prompt")]
+        prompt_file: Option<std::path::PathBuf>,
+        /// Feature group to stage (as numbered in the suggestions)
+        #[arg(long, help = "Feature group to stage without prompting (1-based, as numbered in the suggestions)")]
+        group: Option<usize>,
+        /// Stage individual hunks instead of whole files
+        #[arg(long, help = "Let the AI group individual hunks, staging only the parts of each file that belong to the chosen feature")]
+        hunks: bool,
+        /// Prefer separating unrelated changes into their own groups
+        #[arg(long, help = "Ask the AI to split unrelated changes into separate groups instead of merging them")]
+        split: bool,
+        /// Commit every group separately
+        #[arg(long, requires = "split", help = "Stage and commit each group in turn, with its own generated message")]
+        commit_each: bool,
+        /// Pick files from the suggested groups in a terminal UI
+        #[arg(short, long, conflicts_with_all = ["group", "yes", "commit_each"], help = "Choose files from the suggested groups in a terminal UI, then stage them and show a commit message")]
+        interactive: bool,
+        /// Print the changes that would be sent to the AI and exit
+        #[arg(long, conflicts_with_all = ["group", "commit_each", "interactive"], help = "Print the exact change listing that would be sent to the AI, after ignore rules, and exit without calling it")]
+        preview: bool,
+        /// Most feature groups to suggest
+        #[arg(long, help = "Suggest at most this many feature groups, merging the smallest if the AI returns more (defaults to max_groups in the config file, then no limit)")]
+        max_groups: Option<usize>,
+    },
+    /// Remove files from the staging area
+    Unstage {
+        /// Files to unstage (defaults to everything currently staged)
+        files: Vec<String>,
     },
     /// Create a pull request with AI-generated title and description
     Pr {
         /// Base branch for the PR
-        #[arg(long, help = "Base branch for the PR (e.g., 'main' or 'develop')")]
+        #[arg(long, help = "Base branch for the PR (defaults to the branch <remote>/HEAD points at, then 'main' or 'master')")]
         base: Option<String>,
+        /// Source branch for the PR
+        #[arg(long, help = "Local branch to open the PR from (defaults to the current branch)")]
+        head: Option<String>,
         /// Custom PR title
         #[arg(long, help = "Custom PR title (if not provided, will be AI-generated)")]
         title: Option<String>,
+        /// Maximum length of a generated title
+        #[arg(long, conflicts_with = "title", help = "Maximum length of the generated PR title (defaults to pr_title_len in the config file, then 72)")]
+        title_len: Option<usize>,
         /// Custom PR description
         #[arg(long, help = "Custom PR description (if not provided, will be AI-generated)")]
         body: Option<String>,
+        /// Open the PR as a draft
+        #[arg(long)]
+        draft: bool,
+        /// Request a review from these users
+        #[arg(long, value_delimiter = ',', help = "Request a review from a user (repeatable or comma-separated)")]
+        reviewer: Vec<String>,
+        /// Apply these labels to the PR
+        #[arg(long, value_delimiter = ',', help = "Add a label to the PR (repeatable or comma-separated)")]
+        label: Vec<String>,
+        /// Describe the PR commit by commit
+        #[arg(long, help = "Generate the description from the branch's individual commits, with a Commits section")]
+        from_commits: bool,
+        /// Body template to fill in
+        #[arg(long, help = "PR body template for the AI to fill in (defaults to .github/pull_request_template.md if present)")]
+        template: Option<std::path::PathBuf>,
+        /// Open the new PR in the browser
+        #[arg(long, help = "Open the pull request in the default browser once it is created")]
+        web: bool,
+        /// GitHub Enterprise Server host to open the PR on
+        #[arg(long, help = "GitHub host to open the PR on, e.g. github.mycorp.com (defaults to GH_HOST, then the base remote's host when gh is logged in to it or [github] hosts lists it)")]
+        host: Option<String>,
+        /// Tidy the wording of the generated description
+        #[arg(long, conflicts_with = "body", help = "Run a second AI pass (on the [auto.small] model if configured) that fixes grammar and tightens the wording of the generated description, keeping its Markdown")]
+        polish: bool,
+        /// Remote with the branch the PR merges into
+        #[arg(long, help = "Remote with the base branch, which also decides between GitHub and Bitbucket (defaults to 'upstream', then 'origin'); the head branch is taken from the remote it is pushed to")]
+        remote: Option<String>,
     },
     /// Summarize changes between git references
     Diff {
         /// First git reference (branch, commit, or tag)
-        #[arg(default_value = "HEAD")]
+        #[arg(default_value = "HEAD", help = "First git reference (branch, commit, or tag), or a range: A..B diffs A against B, A...B diffs B against its merge base with A")]
         from: String,
         /// Second git reference (branch, commit, or tag)
         #[arg()]
@@ -59,20 +200,144 @@ enum Commands {
         /// Custom prompt for AI summarization
         #[arg(long, help = "Custom prompt for AI summarization (e.g., 'Focus on security changes' or 'List only modified functions')")]
         prompt: Option<String>,
+        /// Read the custom prompt from a file
+        #[arg(long, conflicts_with = "prompt", help = "Read the custom prompt from a file instead of passing it with --prompt")]
+        prompt_file: Option<std::path::PathBuf>,
+        /// Show change statistics only, without calling an AI provider
+        #[arg(long)]
+        no_ai: bool,
+        /// Show the summary in a scrollable terminal UI
+        #[arg(long, conflicts_with = "output")]
+        tui: bool,
+        /// Wait for the whole summary instead of printing it as it is generated
+        #[arg(long)]
+        no_stream: bool,
+        /// Only include paths matching this glob (repeatable; prefix with ! to exclude)
+        #[arg(long = "files", value_name = "PATTERN", help = "Only include paths matching this glob, e.g. 'src/**' (repeatable; prefix with ! to exclude, e.g. '!*.lock')")]
+        files: Vec<String>,
+        /// Summarize only changes to public items, to spot breaking changes
+        #[arg(long, conflicts_with = "no_ai", help = "Summarize only added, removed and changed public declarations (pub fn, pub struct, trait items, ...)")]
+        api_only: bool,
+        /// Language whose public declarations --api-only looks for
+        #[arg(long, value_enum, default_value = "rust", requires = "api_only")]
+        lang: Lang,
+        /// Print changed files per file type before the summary
+        #[arg(long, help = "Print a table of changed files and lines per file type (.rs, .md, Makefile, ...) before the summary")]
+        file_types: bool,
     },
     /// Generate a commit message for staged changes
-    Commit,
+    Commit {
+        /// Sign the commit even if commit.gpgsign is not set
+        #[arg(short = 'S', long, help = "Sign the commit with the configured GPG or SSH key (also enabled by commit.gpgsign)")]
+        sign: bool,
+        /// Replace the last commit instead of creating a new one
+        #[arg(long, help = "Replace HEAD with a commit of the current staged tree and a regenerated message")]
+        amend: bool,
+        /// Keep the existing message when amending
+        #[arg(long, requires = "amend", help = "Keep HEAD's commit message instead of generating a new one")]
+        no_edit: bool,
+        /// Amend even if HEAD has already been pushed
+        #[arg(long, requires = "amend", help = "Amend even if HEAD is already on its upstream branch")]
+        force: bool,
+        /// Credit another author with a Co-authored-by trailer (repeatable)
+        #[arg(long = "co-author", value_name = "NAME <EMAIL>", value_parser = git::trailers::parse_identity, help = "Add a Co-authored-by trailer for \"Name <email>\"; may be repeated")]
+        co_authors: Vec<String>,
+        /// Append a custom trailer (repeatable)
+        #[arg(long = "trailer", value_name = "KEY: VALUE", value_parser = git::trailers::parse_trailer, help = "Add a trailer such as \"Reviewed-by: Name <email>\" (or Key=value) at the end of the message; may be repeated")]
+        trailers: Vec<(String, String)>,
+        /// Add a Signed-off-by trailer for the committer
+        #[arg(short = 's', long, help = "Add a Signed-off-by trailer with the committer's name and email, like git commit -s")]
+        signoff: bool,
+        /// Prefix the subject with a gitmoji
+        #[arg(long, help = "Start the subject with a gitmoji matching the change (e.g. ✨ feature, 🐛 fix, ♻️ refactor)")]
+        emoji: bool,
+        /// Write only a subject line
+        #[arg(long, conflicts_with = "detailed", help = "Write only a subject line, without a body (the default for diffs of up to 3 changed lines)")]
+        oneline: bool,
+        /// Always write a body under the subject
+        #[arg(long, help = "Always write a body under the subject line, even for tiny diffs")]
+        detailed: bool,
+        /// Generate a message with every configured provider and choose one
+        #[arg(long, conflicts_with = "no_edit", help = "Ask every configured provider concurrently, show their messages side by side and pick one (the first with --yes)")]
+        compare: bool,
+        /// Skip checking the generated message against commit conventions
+        #[arg(long, help = "Don't check the generated message against the subject length, imperative mood, blank line and body wrap rules")]
+        no_lint: bool,
+        /// Imitate the style of the repository's recent commit messages
+        #[arg(long, help = "Show the model the last few commit messages (style.sample_count, default 10) so the new one matches the repository's style")]
+        learn_style: bool,
+        /// Keep regenerating the message as the staged changes change, without committing
+        #[arg(long, conflicts_with_all = ["amend", "compare"], help = "Print a new suggested message whenever the staged changes change; never commits. Stop with Ctrl-C")]
+        watch: bool,
+        /// Leave out the ticket id found in the branch name
+        #[arg(long, help = "Don't add the ticket id found in the branch name (e.g. JIRA-123 in feature/JIRA-123-login) to the message")]
+        no_ticket: bool,
+        /// Tidy the wording of the generated message
+        #[arg(long, conflicts_with_all = ["no_edit", "watch"], help = "Run a second AI pass (on the [auto.small] model if configured) that fixes grammar and tightens the wording of the generated message, keeping its subject length and wrapping")]
+        polish: bool,
+        /// Print the new commit as JSON
+        #[arg(long, conflicts_with = "watch", help = "Print {\"sha\", \"message\"} as JSON instead of text, for scripts")]
+        json: bool,
+    },
+    /// Squash the last N commits into one with a generated message
+    Squash {
+        /// Number of commits to squash
+        #[arg(value_parser = clap::value_parser!(u32).range(2..))]
+        count: u32,
+        /// Sign the new commit even if commit.gpgsign is not set
+        #[arg(short = 'S', long)]
+        sign: bool,
+        /// Squash even if some of the commits have already been pushed
+        #[arg(long, help = "Squash even if some of the commits are already on the upstream branch")]
+        force: bool,
+    },
     /// Summarize git history
     History {
         /// Git reference to start from (branch, commit, or tag)
         #[arg(default_value = "HEAD")]
         reference: String,
-        /// Number of commits to summarize
+        /// Number of commits to summarize, 0 for all (capped by GITWISE_HISTORY_CAP, default 50)
         #[arg(short, long, default_value_t = 5)]
         count: u32,
+        /// Summarize every reachable commit, up to the cap; same as --count 0
+        #[arg(long, conflicts_with = "count")]
+        all: bool,
         /// Custom prompt for AI summarization
         #[arg(long, help = "Custom prompt for AI summarization (e.g., 'Focus on API changes' or 'Summarize in bullet points')")]
         prompt: Option<String>,
+        /// Read the custom prompt from a file
+        #[arg(long, conflicts_with = "prompt", help = "Read the custom prompt from a file instead of passing it with --prompt")]
+        prompt_file: Option<std::path::PathBuf>,
+        /// Always ask the AI instead of reusing cached summaries
+        #[arg(long)]
+        no_cache: bool,
+        /// Show change statistics only, without calling an AI provider
+        #[arg(long)]
+        no_ai: bool,
+        /// Skip merge commits
+        #[arg(long, help = "Skip merge commits; the count applies to the remaining commits")]
+        no_merges: bool,
+        /// List the oldest commits first
+        #[arg(long, help = "List the oldest commits first; the count still picks the newest commits unless --from-start is given")]
+        reverse: bool,
+        /// Take the oldest commits instead of the newest
+        #[arg(long, requires = "reverse", help = "With --reverse, apply the count from the first commit instead of the newest")]
+        from_start: bool,
+        /// Only summarize commits that touched this path (repeatable)
+        #[arg(long = "path", value_name = "PATH", help = "Only include commits that changed this path, e.g. 'src/ai/', and summarize only their changes to it (repeatable; globs and ! exclusions work as for diff --files)")]
+        paths: Vec<String>,
+    },
+    /// Summarize a single commit
+    Show {
+        /// Commit to show (branch, tag, hash or revision such as HEAD~2)
+        #[arg(default_value = "HEAD")]
+        reference: String,
+        /// Always ask the AI instead of reusing a cached summary
+        #[arg(long)]
+        no_cache: bool,
+        /// Show change statistics only, without calling an AI provider
+        #[arg(long)]
+        no_ai: bool,
     },
     /// Show commit history with AI-generated summaries
     Log {
@@ -80,9 +345,149 @@ enum Commands {
         #[arg(short, long)]
         branch: Option<String>,
         /// Limit the number of commits shown
-        #[arg(short, long, default_value = "10")]
+        #[arg(short, long, default_value = "10", help = "Limit the number of commits shown; 0 shows every commit that matches the other filters")]
         limit: u32,
+        /// Only show commits newer than this date
+        #[arg(long, help = "Only show commits more recent than a date (e.g., '2024-01-01' or '2.weeks')")]
+        since: Option<String>,
+        /// Only show commits by this author
+        #[arg(long, help = "Only show commits whose author name or email contains this text (case-insensitive)")]
+        author: Option<String>,
+        /// Show each commit's patch under its summary
+        #[arg(long)]
+        show_diff: bool,
+        /// Context lines around changes in --show-diff output
+        #[arg(long, requires = "show_diff", help = "Context lines around changes in --show-diff output (defaults to --context-lines, then 3)")]
+        diff_context: Option<u32>,
+        /// Print directly instead of opening a pager
+        #[arg(long)]
+        no_pager: bool,
+        /// Always ask the AI instead of reusing cached summaries
+        #[arg(long)]
+        no_cache: bool,
+        /// Skip merge commits
+        #[arg(long, help = "Skip merge commits; the limit applies to the remaining commits")]
+        no_merges: bool,
+        /// List the oldest commits first
+        #[arg(long, help = "List the oldest commits first; the limit still picks the newest commits unless --from-start is given")]
+        reverse: bool,
+        /// Take the oldest commits instead of the newest
+        #[arg(long, requires = "reverse", help = "With --reverse, apply the limit from the first commit instead of the newest")]
+        from_start: bool,
+        /// Output format
+        #[arg(long, value_enum, default_value = "text", help = "Output format; jsonl prints one JSON object per commit and never uses the pager")]
+        format: LogFormat,
+        /// List commits without AI summaries
+        #[arg(long)]
+        no_ai: bool,
+    },
+    /// Review the current branch and flag potential issues
+    Review {
+        /// Base branch to review against
+        #[arg(long, help = "Base branch to review against (defaults to the remote's default branch)")]
+        base: Option<String>,
+    },
+    /// Explain why a line of a file exists
+    Explain {
+        /// File containing the line
+        file: std::path::PathBuf,
+        /// Line number (1-based)
+        line: usize,
+    },
+    /// Write a Markdown changelog section for the commits between two references
+    Changelog {
+        /// Previous release (tag, branch or commit), excluded from the changelog
+        from: String,
+        /// New release, defaults to HEAD
+        to: Option<String>,
+        /// Always ask the AI instead of reusing cached summaries
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Summarize recent contribution activity per author
+    Stats {
+        /// Count commits from this branch
+        #[arg(short, long)]
+        branch: Option<String>,
+        /// Number of recent commits to include
+        #[arg(short, long, default_value_t = 50)]
+        count: u32,
+        /// Print the table only, without an AI narrative
+        #[arg(long)]
+        no_ai: bool,
     },
+    /// Summarize stash entries without applying them
+    Stash {
+        #[command(subcommand)]
+        action: StashAction,
+    },
+    /// Check the repository, API keys, pull request tooling and provider connections
+    Doctor {
+        /// Skip the test request to each configured provider
+        #[arg(long)]
+        no_ping: bool,
+    },
+    /// Manage the on-disk summary cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Delete all cached summaries
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum StashAction {
+    /// List stash entries with a one-line summary of each
+    List {
+        /// Always ask the AI instead of reusing cached summaries
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Summarize one stash entry in detail
+    Show {
+        /// Stash entry to summarize, 0 being the most recent (stash@{0})
+        #[arg(default_value_t = 0)]
+        index: usize,
+        /// Always ask the AI instead of reusing a cached summary
+        #[arg(long)]
+        no_cache: bool,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    /// Colored, git log style output
+    Text,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Lang {
+    /// pub items and public trait members
+    Rust,
+    /// def and class without a leading underscore
+    Python,
+    /// Capitalized func, type, const and var names
+    Go,
+    /// export declarations
+    Typescript,
+}
+
+impl From<Lang> for ai::Language {
+    fn from(lang: Lang) -> Self {
+        match lang {
+            Lang::Rust => ai::Language::Rust,
+            Lang::Python => ai::Language::Python,
+            Lang::Go => ai::Language::Go,
+            Lang::Typescript => ai::Language::TypeScript,
+        }
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -91,72 +496,536 @@ enum ModelProvider {
     Anthropic,
     /// Use OpenAI's GPT model
     OpenAI,
+    /// Use an Azure OpenAI deployment
+    Azure,
+    /// Pick the provider and model by request size, as set in the [auto] config table
+    Auto,
+}
+
+/// Read the history cap from the environment, falling back to the default
+fn history_cap() -> u32 {
+    std::env::var(HISTORY_CAP_ENV)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_CAP)
+}
+
+/// Whether running `command` will call an AI provider
+fn needs_ai(command: &Commands) -> bool {
+    match command {
+        Commands::Unstage { .. } | Commands::Cache { .. } | Commands::Doctor { .. } => false,
+        Commands::Commit { amend: true, no_edit: true, .. } => false,
+        Commands::Pr { title: Some(_), body: Some(_), .. } => false,
+        Commands::Add { preview: true, .. } => false,
+        Commands::Diff { no_ai, .. }
+        | Commands::History { no_ai, .. }
+        | Commands::Show { no_ai, .. }
+        | Commands::Log { no_ai, .. }
+        | Commands::Stats { no_ai, .. } => !*no_ai,
+        _ => true,
+    }
+}
+
+/// Print a command's result, or write it to the `--output` file
+fn emit(cli: &Cli, output: &str) -> Result<()> {
+    match &cli.output {
+        Some(path) => utils::write_output(path, output, cli.append),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+/// `commit <sha>` line that starts each commit in history output
+fn commit_header(id: impl std::fmt::Display) -> String {
+    utils::paint(utils::YELLOW, format!("commit {}", id))
+}
+
+/// Heading above an AI-written section, such as `AI Summary:`
+fn section_heading(title: &str) -> String {
+    utils::paint(utils::CYAN, format!("{}:", title))
+}
+
+/// Table of changed files and lines per file type, followed by a blank line
+fn file_type_table(types: &[ai::FileTypeStats]) -> String {
+    let width = types.iter().map(|stats| stats.kind.len()).max().unwrap_or(0).max("Type".len());
+    let mut table = format!("{}\n", utils::paint(utils::BOLD, format!("{:<width$}  {:>5}  {:>10}  {:>10}", "Type", "Files", "Insertions", "Deletions", width = width)));
+    for stats in types {
+        table.push_str(&format!(
+            "{:<width$}  {:>5}  {}  {}\n",
+            stats.kind,
+            stats.files,
+            utils::paint(utils::GREEN, format!("{:>10}", stats.insertions)),
+            utils::paint(utils::RED, format!("{:>10}", stats.deletions)),
+            width = width
+        ));
+    }
+    table.push('\n');
+    table
+}
+
+/// Commit the current index on top of HEAD
+fn commit_index(repo: &Repository, message: &str, signer: Option<&git::sign::Signer>) -> Result<Oid> {
+    let signature = repo.signature()?;
+    let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+    // The first commit in a fresh repository has no parent
+    let parent = staging::head_commit(repo)?;
+    let parents: Vec<&git2::Commit<'_>> = parent.iter().collect();
+
+    Ok(git::sign::commit_to_head(repo, &signature, message, &tree, &parents, signer)?)
+}
+
+/// Report a new commit as "<verb> commit <short sha>:" and its message, or as
+/// `{"sha", "message"}` with `--json`
+fn print_commit(verb: &str, oid: Oid, message: &str, json: bool) {
+    if json {
+        println!("{}", serde_json::json!({ "sha": oid.to_string(), "message": message }));
+    } else {
+        println!("{} commit {}:\n{}", verb, &oid.to_string()[..7], message);
+    }
+}
+
+/// Point out a commit made on a detached HEAD outside a rebase, bisect or similar,
+/// since it's easily lost when switching branches
+fn warn_if_detached(repo: &Repository) {
+    if repo.head_detached().unwrap_or(false) && repo.state() == git2::RepositoryState::Clean {
+        warn!("HEAD is detached, so the commit is not on any branch; keep it with `git switch -c <branch>`");
+    }
+}
+
+/// Append co-author and custom trailers, then `Signed-off-by` for the committer last
+fn add_trailers(repo: &Repository, message: &str, co_authors: &[String], trailers: &[(String, String)], signoff: bool) -> Result<String> {
+    let message = git::trailers::append_trailers(message, git::trailers::CO_AUTHORED_BY, co_authors);
+    let mut message = git::trailers::append_all_trailers(&message, trailers);
+    if signoff {
+        let committer = repo.signature()?;
+        let identity = format!("{} <{}>", committer.name().unwrap_or(""), committer.email().unwrap_or(""));
+        message = git::trailers::append_trailers(&message, git::trailers::SIGNED_OFF_BY, &[identity]);
+    }
+    Ok(message)
 }
 
-/// Resolve a git reference (branch, tag, or commit hash) to a commit
-fn resolve_reference(repo: &Repository, reference: &str) -> Result<Oid> {
-    // Try as a direct reference first (branch or tag)
-    if let Ok(reference) = repo.find_reference(reference) {
-        return Ok(reference.peel_to_commit()?.id());
+/// Add the ticket id found in the current branch's name, as the `[ticket]` config says.
+/// Messages are left alone on a detached HEAD or when the branch names no ticket.
+/// This runs before the lint, so a `[JIRA-123]` subject prefix counts towards the
+/// subject length it checks.
+fn add_branch_ticket(repo: &Repository, message: &str, ticket: &config::Ticket) -> Result<String> {
+    let Some(branch) = git::current_branch_name(repo) else {
+        return Ok(message.to_string());
+    };
+    let pattern = git::ticket::TicketPattern::new(ticket.pattern.as_deref().unwrap_or(git::ticket::DEFAULT_TICKET_PATTERN))?;
+    Ok(match pattern.find(&branch) {
+        Some(id) => {
+            let trailer = ticket.trailer.as_deref().unwrap_or(git::ticket::DEFAULT_TICKET_TRAILER);
+            git::ticket::add_ticket(message, id, trailer, ticket.placement.unwrap_or_default())
+        }
+        None => message.to_string(),
+    })
+}
+
+/// Put the branch's ticket back when a lint revision or the polish dropped it,
+/// warning about any rule the message breaks once it's back
+fn restore_branch_ticket(engine: &ai::AiEngine, repo: &Repository, message: String, ticket: &config::Ticket) -> Result<String> {
+    let restored = add_branch_ticket(repo, &message, ticket)?;
+    if restored != message {
+        for warning in engine.validate_commit_message(&restored) {
+            warn!("Commit message {}", warning);
+        }
     }
+    Ok(restored)
+}
 
-    // Try as a revision (commit hash, HEAD~1, etc)
-    if let Ok(revspec) = repo.revparse_single(reference) {
-        return Ok(revspec.peel_to_commit()?.id());
+/// Recent commit messages for `--learn-style`, cached per repository and HEAD so the
+/// log is only walked again once HEAD moves
+fn style_examples(repo: &Repository, count: usize) -> Result<Vec<String>> {
+    // A repository without commits has no style to learn yet
+    let Ok(head) = repo.head().and_then(|head| head.peel_to_commit()) else {
+        return Ok(Vec::new());
+    };
+    let cache = SummaryCache::new(true);
+    let key = format!("style:{}:{}", repo.path().display(), count);
+    if let Some(messages) = cache.get(head.id(), &key).and_then(|cached| serde_json::from_str(&cached).ok()) {
+        return Ok(messages);
     }
 
-    // Try as a short commit hash
-    let partial_hash = reference.to_string();
-    if partial_hash.len() >= 4 {
-        let mut found_oid = None;
-        repo.odb()?.foreach(|oid| {
-            if oid.to_string().starts_with(&partial_hash) {
-                found_oid = Some(*oid);
-                false // Stop iteration
+    let messages = git::recent_commit_messages(repo, count)?;
+    cache.put(head.id(), &key, &serde_json::to_string(&messages)?);
+    Ok(messages)
+}
+
+/// How often `commit --watch` looks at the index
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Reprint a suggested commit message each time the staged changes settle on new
+/// content, until Ctrl-C
+async fn watch_commit_message(engine: &ai::AiEngine, repo: &Repository) -> Result<()> {
+    println!("Watching staged changes; press Ctrl-C to stop.");
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    // Content last shown, and last seen; a change is only acted on once two polls
+    // agree, so a burst of `git add`s produces one message
+    let mut shown: Option<Oid> = None;
+    let mut seen: Option<Oid> = None;
+    loop {
+        repo.index()?.read(false)?;
+        let diff = staging::get_staged_changes(repo)?;
+        let fingerprint = diff.patchid(None)?;
+
+        if seen == Some(fingerprint) && shown != Some(fingerprint) {
+            shown = Some(fingerprint);
+            println!("\n{}", utils::paint(utils::GRAY, format!("── {} ──", chrono::Local::now().format("%H:%M:%S"))));
+            if diff.deltas().len() == 0 {
+                println!("No staged changes");
             } else {
-                true // Continue iteration
+                tokio::select! {
+                    result = engine.generate_commit_message(&diff) => match result {
+                        Ok(message) => println!("{}", message),
+                        // Keep watching; the next change may well succeed
+                        Err(error) => warn!("Failed to generate a commit message: {}", error),
+                    },
+                    _ = &mut ctrl_c => return Ok(()),
+                }
+            }
+        }
+        seen = Some(fingerprint);
+
+        tokio::select! {
+            _ = tokio::time::sleep(WATCH_INTERVAL) => {}
+            _ = &mut ctrl_c => return Ok(()),
+        }
+    }
+}
+
+/// Warn about a generated commit message that breaks the subject/body rules and, when
+/// `regenerate` is set, ask once for a corrected message
+async fn lint_commit_message(engine: &ai::AiEngine, diff: &git2::Diff<'_>, message: String, regenerate: bool) -> Result<String> {
+    let warnings = engine.validate_commit_message(&message);
+    if warnings.is_empty() || !regenerate {
+        for warning in &warnings {
+            warn!("Commit message {}", warning);
+        }
+        return Ok(message);
+    }
+
+    info!("Generated commit message breaks {} rule(s); asking for a revision", warnings.len());
+    let revised = engine.revise_commit_message(diff, &message, &warnings).await?;
+    for warning in engine.validate_commit_message(&revised) {
+        warn!("Commit message {}", warning);
+    }
+    Ok(revised)
+}
+
+/// Tidy the wording of a generated commit message, keeping it as it is if that fails
+async fn polish_commit_message(engine: &ai::AiEngine, message: String) -> String {
+    match engine.polish_text(&message, &engine.commit_message_constraints()).await {
+        Ok(polished) => polished,
+        Err(e) => {
+            warn!("Could not polish the commit message: {}", e);
+            message
+        }
+    }
+}
+
+/// Generate a commit message from every configured provider at once, print them
+/// side by side and let the user pick one (the first when not interactive). With
+/// `json`, the choice is offered on stderr so stdout holds only the JSON result.
+async fn compare_commit_messages(engine: &ai::AiEngine, diff: &git2::Diff<'_>, assume_yes: bool, json: bool) -> Result<String> {
+    let show = |text: String| if json { eprintln!("{}", text) } else { println!("{}", text) };
+    let providers = engine.configured_providers();
+    if providers.len() < 2 {
+        return Err(anyhow!("--compare needs at least two providers configured (ANTHROPIC_API_KEY, OPENAI_API_KEY or AZURE_OPENAI_*)"));
+    }
+
+    let results = futures::future::join_all(
+        providers.iter().map(|provider| engine.generate_commit_message_with(diff, Some(provider)))
+    ).await;
+
+    let mut messages = Vec::new();
+    for (provider, result) in providers.iter().zip(results) {
+        let label = format!("{:?} ({})", provider, engine.model_name(provider));
+        match result {
+            Ok(message) => {
+                messages.push(message);
+                show(format!("{}\n{}\n", utils::paint(utils::CYAN, format!("[{}] {}", messages.len(), label)), messages.last().unwrap()));
+            }
+            Err(error) => warn!("{} failed: {}", label, error),
+        }
+    }
+    if messages.is_empty() {
+        return Err(anyhow!("Every provider failed to generate a commit message"));
+    }
+    if assume_yes || messages.len() == 1 {
+        return Ok(messages.swap_remove(0));
+    }
+
+    let prompt = format!("Use which message? [1-{}, default 1]: ", messages.len());
+    loop {
+        let answer = if json {
+            eprint!("{}", prompt);
+            utils::prompt_line("")?
+        } else {
+            utils::prompt_line(&prompt)?
+        };
+        let Some(answer) = answer else {
+            return Ok(messages.swap_remove(0));
+        };
+        if answer.is_empty() {
+            return Ok(messages.swap_remove(0));
+        }
+        match answer.parse::<usize>() {
+            Ok(choice) if (1..=messages.len()).contains(&choice) => return Ok(messages.swap_remove(choice - 1)),
+            _ => show(format!("Please enter a number between 1 and {}", messages.len())),
+        }
+    }
+}
+
+/// Environment variables that configure each provider, for `doctor` hints
+fn provider_setup(provider: &ai::ModelProvider) -> &'static str {
+    match provider {
+        ai::ModelProvider::Anthropic => "set ANTHROPIC_API_KEY",
+        ai::ModelProvider::OpenAI => "set OPENAI_API_KEY, or OPENAI_BASE_URL for a local server",
+        ai::ModelProvider::Azure => "set AZURE_OPENAI_ENDPOINT, AZURE_OPENAI_API_KEY and AZURE_OPENAI_DEPLOYMENT",
+    }
+}
+
+/// Print a ✅/❌ checklist of what gitwise needs, with a hint for each failure,
+/// and fail if anything required is missing
+async fn run_doctor(engine: &ai::AiEngine, config: &config::Config, ping: bool) -> Result<()> {
+    let mut failures = 0;
+    let mut check = |ok: bool, text: String, hint: Option<String>| {
+        println!("{} {}", if ok { "✅" } else { "❌" }, text);
+        if let Some(hint) = hint.filter(|_| !ok) {
+            println!("   {}", utils::paint(utils::GRAY, format!("→ {}", hint)));
+        }
+        if !ok {
+            failures += 1;
+        }
+    };
+
+    let repo = Repository::open_from_env();
+    match &repo {
+        Ok(repo) => {
+            let location = repo.workdir().unwrap_or_else(|| repo.path()).display().to_string();
+            check(true, format!("Git repository at {}", location), None);
+        }
+        Err(error) => check(false, format!("No git repository: {}", error.message()), Some("run gitwise inside a repository, or create one with `git init`".to_string())),
+    }
+
+    let configured = engine.configured_providers();
+    for provider in [ai::ModelProvider::Anthropic, ai::ModelProvider::OpenAI, ai::ModelProvider::Azure] {
+        if configured.contains(&provider) {
+            check(true, format!("{:?} is configured (model {})", provider, engine.model_name(&provider)), None);
+        } else {
+            println!("➖ {:?} is not configured {}", provider, utils::paint(utils::GRAY, format!("(to use it, {})", provider_setup(&provider))));
+        }
+    }
+    if let Err(error) = engine.require_provider() {
+        check(false, error.to_string(), Some("add the key to your environment or a .env file in the repository".to_string()));
+    }
+
+    // Where `gitwise pr` would open pull requests
+    match &repo {
+        Ok(repo) => match git::pr::check_backend(repo, &config.github.hosts) {
+            Ok(backend) => check(true, format!("Pull requests can be opened on {}", backend), None),
+            Err(error) => check(false, format!("Pull requests cannot be opened: {}", error), None),
+        },
+        Err(_) => println!("➖ Pull request tooling not checked without a repository"),
+    }
+
+    if ping {
+        for provider in &configured {
+            let start = std::time::Instant::now();
+            match engine.ping(provider).await {
+                Ok(()) => check(true, format!("{:?} answered in {:.1}s", provider, start.elapsed().as_secs_f64()), None),
+                Err(error) => check(false, format!("{:?} did not answer: {}", provider, error), Some(format!("check the key and network access, or {}", provider_setup(provider)))),
             }
-        })?;
-        
-        if let Some(oid) = found_oid {
-            return Ok(oid);
         }
     }
 
-    Err(anyhow::anyhow!("Could not resolve git reference: {}", reference))
+    match failures {
+        0 => Ok(()),
+        1 => Err(anyhow!("1 check failed")),
+        n => Err(anyhow!("{} checks failed", n)),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
-    let cli = Cli::parse();
+    // Usage errors happen before the flag is parsed, so look for it directly
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) if e.use_stderr() && std::env::args().any(|arg| arg == "--json-errors") => {
+            let rendered = e.to_string();
+            let message = rendered.lines().next().unwrap_or("").trim_start_matches("error: ");
+            eprintln!("{}", serde_json::json!({ "error": message, "kind": "usage", "context": [] }));
+            std::process::exit(2);
+        }
+        Err(e) => e.exit(),
+    };
+
+    let json_errors = cli.json_errors;
+    match start(cli).await {
+        Err(e) if json_errors => {
+            eprintln!("{}", error::to_json(&e));
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
 
+/// Configure logging and the AI engine from the command line, then run the command
+async fn start(mut cli: Cli) -> Result<()> {
     // Initialize logging
+    let log_ansi = !cli.non_interactive && std::io::stderr().is_terminal();
     if cli.verbose {
         fmt()
             .with_max_level(tracing::Level::DEBUG)
+            .with_writer(std::io::stderr)
+            .with_ansi(log_ansi)
             .init();
     } else {
         fmt()
             .with_max_level(tracing::Level::INFO)
+            .with_writer(std::io::stderr)
+            .with_ansi(log_ansi)
             .init();
     }
 
-    let mut engine = ai::AiEngine::new()?;
+    if let Some(lines) = cli.context_lines {
+        git::set_context_lines(lines);
+    }
+
+    let config = config::Config::load()?;
+    let mut engine = ai::AiEngine::from_config(&config)?;
     
     // Apply model provider if specified
-    if let Some(provider) = cli.model {
-        info!("Using enforced model provider: {:?}", provider);
-        engine = engine.with_provider(match provider {
-            ModelProvider::Anthropic => ai::ModelProvider::Anthropic,
-            ModelProvider::OpenAI => ai::ModelProvider::OpenAI,
-        });
-    } else {
-        info!("Using default model provider selection");
+    let provider = cli.model.as_ref().map(|provider| match provider {
+        ModelProvider::Anthropic => Some(ai::ModelProvider::Anthropic),
+        ModelProvider::OpenAI => Some(ai::ModelProvider::OpenAI),
+        ModelProvider::Azure => Some(ai::ModelProvider::Azure),
+        ModelProvider::Auto => None,
+    });
+    match provider {
+        Some(Some(provider)) => {
+            info!("Using enforced model provider: {:?}", provider);
+            engine = engine.with_provider(provider);
+        }
+        Some(None) => {
+            info!("Choosing the model by request size");
+            engine = engine.with_auto_models(config.auto.clone());
+        }
+        None => info!("Using default model provider selection"),
+    }
+
+    if let Some(model_name) = &cli.model_name {
+        engine = engine.with_model_name(model_name.clone());
+    }
+
+    if let Some(language) = &cli.language {
+        engine = engine.with_language(language.clone());
+    }
+
+    if let Some(temperature) = cli.temperature {
+        engine = engine.with_temperature(temperature)?;
+    }
+
+    if let Some(subject_len) = cli.subject_len {
+        engine = engine.with_subject_len(subject_len);
+    }
+
+    if let Some(body_wrap) = cli.body_wrap {
+        engine = engine.with_body_wrap(body_wrap);
+    }
+
+    if cli.yes {
+        engine = engine.with_assume_yes(true);
+    }
+
+    if let Commands::Pr { title_len: Some(title_len), .. } = cli.command {
+        engine = engine.with_pr_title_len(title_len);
+    }
+
+    if let Commands::Add { max_groups: max_groups @ None, .. } = &mut cli.command {
+        *max_groups = config.max_groups;
+    }
+
+    if let Commands::Commit { emoji: true, .. } = cli.command {
+        engine = engine.with_emoji(true);
+    }
+
+    if let Commands::Commit { oneline: true, .. } = cli.command {
+        engine = engine.with_message_body(ai::MessageBody::Oneline);
+    }
+
+    if let Commands::Stash { action: StashAction::List { .. } } = cli.command {
+        engine = engine.with_message_body(ai::MessageBody::Oneline);
+    }
+
+    if let Commands::Commit { detailed: true, .. } = cli.command {
+        engine = engine.with_message_body(ai::MessageBody::Detailed);
+    }
+
+    if let Commands::Commit { learn_style: true, .. } = cli.command {
+        let repo = Repository::open_from_env()?;
+        let count = config.style.sample_count.unwrap_or(DEFAULT_STYLE_SAMPLES);
+        engine = engine.with_style_examples(style_examples(&repo, count)?);
+    }
+
+    if let Some(limit) = cli.show_prompt {
+        engine = engine.with_show_prompt(limit);
+    }
+
+    if cli.no_redact {
+        engine = engine.with_redact(false);
+    }
+
+    if cli.no_fallback {
+        engine = engine.with_fallback(false);
+    }
+
+    // Submodule commits are looked up in their checkouts under the working tree
+    if let Ok(repo) = Repository::open_from_env() {
+        engine = engine.with_repo(repo);
+    }
+
+    // Prompts, the pager, color and spinners are all for someone at the terminal
+    let interactive = cli.is_interactive();
+    if cli.non_interactive && matches!(cli.command, Commands::Add { interactive: true, .. } | Commands::Diff { tui: true, .. }) {
+        return Err(anyhow!("The terminal UI needs an interactive terminal; drop --non-interactive, or leave out --interactive/--tui"));
+    }
+    utils::set_interactive(interactive);
+
+    // Color only for terminals, unless turned off (https://no-color.org)
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    utils::set_color(!cli.no_color && !no_color_env && interactive);
+
+    // Progress spinners go to stderr; keep them out of logs and redirected output
+    ui::spinner::set_enabled(!cli.verbose && interactive && std::io::stderr().is_terminal());
+
+    // Report a missing API key before any git work
+    if needs_ai(&cli.command) {
+        engine.require_provider()?;
     }
 
+    let result = run(&cli, &engine, &config).await;
+
+    // Report what this run cost, if it talked to a provider at all
+    let usage = engine.usage();
+    if cli.verbose && usage.total_tokens() > 0 {
+        eprintln!("{}", usage);
+    }
+
+    result
+}
+
+/// Execute the selected subcommand
+async fn run(cli: &Cli, engine: &ai::AiEngine, config: &config::Config) -> Result<()> {
     match &cli.command {
-        Commands::Add { prompt } => {
+        Commands::Add { prompt, prompt_file, group, hunks, split, commit_each, interactive, preview, max_groups } => {
+            let prompt = utils::read_prompt(prompt.as_deref(), prompt_file.as_deref())?;
             let repo = Repository::open_from_env()?;
             
             // Get staged and unstaged changes
@@ -172,30 +1041,157 @@ async fn main() -> Result<()> {
                 return Ok(());
             }
             
+            if *preview {
+                print!("{}", engine.format_changes_for_ai(&staged_diff, &unstaged_diff, *hunks)?);
+                return Ok(());
+            }
+
             // Analyze changes and group them by feature
-            let groups = engine.analyze_changes(&staged_diff, &unstaged_diff, prompt.as_deref()).await?;
+            let groups = engine.analyze_changes(&staged_diff, &unstaged_diff, prompt.as_deref(), *hunks, *split, *max_groups).await?;
+            let mut groups = staging::filter_selections(groups, &unstaged_files);
             
             if groups.is_empty() {
                 println!("No changes to stage.");
                 return Ok(());
             }
 
-            // Take the first group as our suggestion
-            let selected_group = &groups[0];
+            if *interactive {
+                let mut tui = ui::Tui::new()?;
+                let chosen = tui.select_files(groups, |files| {
+                    let repo = &repo;
+                    async move {
+                        staging::stage_selections(repo, &files)?;
+                        let paths: Vec<String> = files.iter().map(|file| file.path.clone()).collect();
+                        let staged_diff = staging::get_staged_changes_for_paths(repo, &paths)?;
+                        Ok(engine.generate_commit_message(&staged_diff).await?)
+                    }
+                }).await?;
+                // Restore the terminal before printing
+                drop(tui);
+
+                match chosen {
+                    Some((files, message)) => {
+                        println!("Staged:");
+                        for file in &files {
+                            println!("  {}", file);
+                        }
+                        println!("\nSuggested commit message:\n{}", message);
+                    }
+                    None => println!("Aborted; nothing was staged."),
+                }
+                return Ok(());
+            }
+
+            println!("\nSuggested feature groups:");
+            for (i, files) in groups.iter().enumerate() {
+                println!("\n  [{}]", i + 1);
+                for file in files {
+                    println!("    {}", file);
+                }
+            }
+
+            if *commit_each {
+                let signer = git::sign::Signer::from_config(&repo, false)?;
+                if !cli.yes && !utils::confirm(&format!("\nCommit each of these {} groups separately?", groups.len()))? {
+                    println!("Aborted; nothing was staged. Pass --yes to commit without confirmation.");
+                    return Ok(());
+                }
+
+                let mut committed = 0;
+                while let Some(selected_group) = groups.first().cloned() {
+                    staging::stage_selections(&repo, &selected_group)?;
+
+                    let staged_diff = staging::get_staged_changes(&repo)?;
+                    if staged_diff.deltas().len() == 0 {
+                        warn!("Nothing was staged for the next group; stopping");
+                        break;
+                    }
+                    let message = engine.generate_commit_message(&staged_diff).await?;
+                    let oid = commit_index(&repo, &message, signer.as_ref())?;
+                    println!("\nCommitted {}: {}", &oid.to_string()[..7], message.lines().next().unwrap_or(""));
+                    committed += 1;
+
+                    // Re-diff the working tree so later groups only cover what is left
+                    let (_staged_files, unstaged_files) = staging::get_change_groups(&repo)?;
+                    if unstaged_files.is_empty() {
+                        break;
+                    }
+                    groups = if *hunks {
+                        // Committing shifts hunk numbers, so group the remaining changes again
+                        let staged_diff = staging::get_staged_changes(&repo)?;
+                        let unstaged_diff = staging::get_unstaged_changes(&repo)?;
+                        // Regrouping counts towards the same --max-groups
+                        let remaining = max_groups.map(|max| max.saturating_sub(committed).max(1));
+                        let regrouped = engine.analyze_changes(&staged_diff, &unstaged_diff, prompt.as_deref(), true, true, remaining).await?;
+                        staging::filter_selections(regrouped, &unstaged_files)
+                    } else {
+                        staging::filter_selections(groups.split_off(1), &unstaged_files)
+                    };
+                }
+                return Ok(());
+            }
+
+            // Pick a group: explicit flag, the only group, or ask the user
+            let index = match group {
+                Some(n) => *n,
+                None if groups.len() == 1 => 1,
+                None => {
+                    let answer = utils::prompt_line(&format!("\nSelect a group to stage [1-{}] (default 1): ", groups.len()))?
+                        .ok_or_else(|| anyhow!("Multiple feature groups found; pass --group N to choose one non-interactively"))?;
+                    if answer.is_empty() {
+                        1
+                    } else {
+                        answer.parse::<usize>()
+                            .with_context(|| format!("Invalid group selection: {}", answer))?
+                    }
+                }
+            };
+
+            let selected_group = index.checked_sub(1)
+                .and_then(|i| groups.get(i))
+                .ok_or_else(|| anyhow!("Group {} does not exist (choose 1-{})", index, groups.len()))?;
             
-            println!("\nStaging files for feature:");
+            println!("\nFiles to stage for feature {}:", index);
             for file in selected_group {
                 println!("  {}", file);
-                staging::stage_file(&repo, file)?;
             }
 
-            // Get fresh diff after staging
-            let new_staged_diff = staging::get_staged_changes(&repo)?;
+            if !cli.yes && !utils::confirm("\nStage these files?")? {
+                println!("Aborted; nothing was staged. Pass --yes to stage without confirmation.");
+                return Ok(());
+            }
+
+            staging::stage_selections(&repo, selected_group)?;
+
+            // Get fresh diff of just the staged group
+            let paths: Vec<String> = selected_group.iter().map(|file| file.path.clone()).collect();
+            let new_staged_diff = staging::get_staged_changes_for_paths(&repo, &paths)?;
             let commit_msg = engine.generate_commit_message(&new_staged_diff).await?;
             
             println!("\nSuggested commit message:\n{}", commit_msg);
         }
-        Commands::Pr { base, title, body } => {
+        Commands::Unstage { files } => {
+            let repo = Repository::open_from_env()?;
+
+            let files = if files.is_empty() {
+                let (staged_files, _unstaged_files) = staging::get_change_groups(&repo)?;
+                staged_files
+            } else {
+                files.clone()
+            };
+
+            if files.is_empty() {
+                println!("No staged changes to unstage.");
+                return Ok(());
+            }
+
+            println!("Unstaging files:");
+            for file in &files {
+                staging::unstage_file(&repo, file)?;
+                println!("  {}", file);
+            }
+        }
+        Commands::Pr { base, head, title, title_len: _, body, draft, reviewer, label, from_commits, template, web, host, polish, remote } => {
             let mut pr = git::pr::PullRequest::new();
             
             if let Some(t) = title {
@@ -207,71 +1203,221 @@ async fn main() -> Result<()> {
             if let Some(base_branch) = base {
                 pr = pr.with_base(base_branch.clone());
             }
+            if let Some(head_branch) = head {
+                pr = pr.with_head(head_branch.clone());
+            }
+            if let Some(path) = template {
+                pr = pr.with_template(path.clone());
+            }
+            if let Some(host) = host {
+                pr = pr.with_host(host.clone());
+            }
+            if let Some(remote) = remote {
+                pr = pr.with_remote(remote.clone());
+            }
+            pr = pr.with_draft(*draft)
+                .with_web(*web)
+                .with_polish(*polish)
+                .with_github_hosts(config.github.hosts.clone())
+                .with_from_commits(*from_commits)
+                .with_reviewers(reviewer.iter().map(|r| r.trim().to_string()).filter(|r| !r.is_empty()).collect())
+                .with_labels(label.iter().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect());
             
-            pr.create().await?;
+            let url = pr.create(engine).await?;
             println!("✨ Pull request created successfully!");
+            if let Some(url) = url {
+                println!("{}", url);
+            }
         }
-        Commands::Diff { from, to, staged, prompt } => {
+        Commands::Diff { from, to, staged, prompt, prompt_file, no_ai, tui, no_stream, files, api_only, lang, file_types } => {
+            let prompt = utils::read_prompt(prompt.as_deref(), prompt_file.as_deref())?;
             let repo = Repository::open_from_env()?;
             let diff = if *staged {
-                // Get diff of staged changes
-                let mut opts = git2::DiffOptions::new();
-                let head_tree = repo.head()?.peel_to_tree()?;
-                repo.diff_tree_to_index(Some(&head_tree), None, Some(&mut opts))?
+                staging::get_staged_changes_matching(&repo, files)?
             } else {
                 // Get diff between references
-                let from_commit = repo.find_commit(resolve_reference(&repo, &from)?)?;
-                let from_tree = from_commit.tree()?;
-
-                let to_tree = if let Some(to) = to {
-                    let to_commit = repo.find_commit(resolve_reference(&repo, &to)?)?;
-                    to_commit.tree()?
-                } else {
-                    // If no 'to' reference is provided, use the working directory
-                    repo.head()?.peel_to_tree()?
+                let (from_tree, to_tree) = match git::resolve_range(&repo, from)? {
+                    Some(_) if to.is_some() => {
+                        return Err(anyhow!("Pass either a range such as {} or two references, not both", from));
+                    }
+                    Some((from_id, to_id)) => (repo.find_commit(from_id)?.tree()?, repo.find_commit(to_id)?.tree()?),
+                    None => {
+                        let from_commit = repo.find_commit(git::resolve_reference(&repo, from)?)?;
+                        let to_tree = if let Some(to) = to {
+                            let to_commit = repo.find_commit(git::resolve_reference(&repo, to)?)?;
+                            to_commit.tree()?
+                        } else {
+                            // If no 'to' reference is provided, use the working directory
+                            repo.head()?.peel_to_tree()?
+                        };
+                        (from_commit.tree()?, to_tree)
+                    }
                 };
 
-                repo.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)?
+                repo.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), Some(&mut git::pathspec_options(files)))?
+            };
+
+            let type_table = if *file_types {
+                file_type_table(&ai::file_types(&diff)?)
+            } else {
+                String::new()
             };
 
-            let summary = engine.summarize_diff(&diff, prompt.as_deref()).await?;
-            println!("Changes Summary:\n{}", summary);
+            // Stream to the terminal, unless the result is going to a file or the TUI
+            if !*no_ai && !*api_only && !*tui && !*no_stream && cli.output.is_none() {
+                print!("{}", type_table);
+                println!("Changes Summary:");
+                engine.summarize_diff_streaming(&diff, prompt.as_deref(), |token| {
+                    print!("{}", token);
+                    let _ = std::io::stdout().flush();
+                }).await?;
+                println!();
+                return Ok(());
+            }
+
+            let summary = if *no_ai {
+                ai::diff_stats(&diff)?
+            } else if *api_only {
+                engine.summarize_api_changes(&diff, (*lang).into(), prompt.as_deref()).await?
+            } else {
+                engine.summarize_diff(&diff, prompt.as_deref()).await?
+            };
+            if *tui {
+                ui::Tui::new()?.run("Changes Summary (↑/↓ PgUp/PgDn to scroll, q to quit)", &format!("{}{}", type_table, summary))?;
+            } else {
+                emit(cli, &format!("{}Changes Summary:\n{}\n", type_table, summary))?;
+            }
         }
-        Commands::Commit => {
+        Commands::Commit { sign, amend, no_edit, force, co_authors, trailers, signoff, compare, no_lint, watch, no_ticket, polish, json, .. } => {
             let repo = Repository::open_from_env()?;
+            if *watch {
+                return watch_commit_message(engine, &repo).await;
+            }
+
+            // Resolve signing up front so a missing key fails before any work
+            let signer = git::sign::Signer::from_config(&repo, *sign)?;
+
+            if *amend {
+                let head = repo.head()?.peel_to_commit()
+                    .context("Cannot amend: there is no commit yet")?;
+
+                if !*force {
+                    if let Some(upstream) = git::pushed_upstream(&repo)? {
+                        return Err(anyhow!(
+                            "HEAD has already been pushed to {}; amending would rewrite published history. Pass --force to amend anyway",
+                            upstream
+                        ));
+                    }
+                }
+
+                let mut index = repo.index()?;
+                let tree = repo.find_tree(index.write_tree()?)?;
+
+                let message = if *no_edit {
+                    String::from_utf8_lossy(head.message_bytes()).into_owned()
+                } else {
+                    // Describe everything the amended commit will contain, not just the new changes
+                    let parent_tree = head.parent(0).ok().map(|parent| parent.tree()).transpose()?;
+                    let mut diff = repo.diff_tree_to_index(parent_tree.as_ref(), None, Some(&mut git::diff_options()))?;
+                    git::find_renames(&mut diff)?;
+                    let message = if *compare {
+                        compare_commit_messages(engine, &diff, cli.yes, *json).await?
+                    } else {
+                        engine.generate_commit_message(&diff).await?
+                    };
+                    let message = if *no_ticket { message } else { add_branch_ticket(&repo, &message, &config.ticket)? };
+                    let message = if *no_lint { message } else { lint_commit_message(engine, &diff, message, !*compare).await? };
+                    let message = if *polish { polish_commit_message(engine, message).await } else { message };
+                    if *no_ticket { message } else { restore_branch_ticket(engine, &repo, message, &config.ticket)? }
+                };
+                let message = add_trailers(&repo, &message, co_authors, trailers, *signoff)?;
+
+                let signature = repo.signature()?;
+                let oid = git::sign::amend_head(&repo, &signature, &message, &tree, signer.as_ref())?;
+
+                print_commit("Amended", oid, &message, *json);
+                warn_if_detached(&repo);
+                return Ok(());
+            }
             
             // Check if there are staged changes
-            let mut index = repo.index()?;
-            if index.is_empty() {
+            // Get the diff of staged changes
+            let diff = staging::get_staged_changes(&repo)?;
+            if diff.deltas().len() == 0 {
+                if *json {
+                    println!("{}", serde_json::json!({ "sha": null, "message": null }));
+                    return Ok(());
+                }
                 println!("No changes to commit");
                 return Ok(());
             }
             
-            // Get the diff of staged changes
-            let mut opts = git2::DiffOptions::new();
-            let head_tree = repo.head()?.peel_to_tree()?;
-            let diff = repo.diff_tree_to_index(Some(&head_tree), None, Some(&mut opts))?;
-            
-            let message = engine.generate_commit_message(&diff).await?;
-            
-            // Create the commit
-            let signature = repo.signature()?;
-            let tree_id = index.write_tree()?;
-            let tree = repo.find_tree(tree_id)?;
-            let parent = repo.head()?.peel_to_commit()?;
+            let message = if *compare {
+                compare_commit_messages(engine, &diff, cli.yes, *json).await?
+            } else {
+                engine.generate_commit_message(&diff).await?
+            };
+            let message = if *no_ticket { message } else { add_branch_ticket(&repo, &message, &config.ticket)? };
+            let message = if *no_lint { message } else { lint_commit_message(engine, &diff, message, !*compare).await? };
+            let message = if *polish { polish_commit_message(engine, message).await } else { message };
+            let message = if *no_ticket { message } else { restore_branch_ticket(engine, &repo, message, &config.ticket)? };
+            let message = add_trailers(&repo, &message, co_authors, trailers, *signoff)?;
             
-            repo.commit(
-                Some("HEAD"),
-                &signature,
-                &signature,
-                &message,
-                &tree,
-                &[&parent],
-            )?;
+            let oid = commit_index(&repo, &message, signer.as_ref())?;
             
-            println!("Created commit with message:\n{}", message);
+            print_commit("Created", oid, &message, *json);
+            warn_if_detached(&repo);
+        }
+        Commands::Squash { count, sign, force } => {
+            let repo = Repository::open_from_env()?;
+            let signer = git::sign::Signer::from_config(&repo, *sign)?;
+            let head = repo.head()?.peel_to_commit()
+                .context("Cannot squash: there is no commit yet")?;
+
+            // Walk back along first parents, refusing to flatten merges
+            let mut squashed = vec![head.clone()];
+            while squashed.len() < *count as usize {
+                let parent = squashed.last().unwrap().parent(0)
+                    .map_err(|_| anyhow!("Cannot squash {} commits: the branch only has {}", count, squashed.len()))?;
+                squashed.push(parent);
+            }
+            if let Some(merge) = squashed.iter().find(|commit| commit.parent_count() > 1) {
+                return Err(anyhow!("Cannot squash merge commit {}", &merge.id().to_string()[..7]));
+            }
+            let oldest = squashed.last().unwrap();
+            let base = oldest.parent(0)
+                .map_err(|_| anyhow!("Cannot squash the root commit; choose a count below {}", count))?;
+
+            if !*force {
+                if let Some(upstream) = git::upstream_containing(&repo, oldest.id())? {
+                    return Err(anyhow!(
+                        "Some of these commits have already been pushed to {}; squashing would rewrite published history. Pass --force to squash anyway",
+                        upstream
+                    ));
+                }
+            }
+
+            // The index holds HEAD plus anything staged, which is what a soft reset keeps
+            let mut diff = repo.diff_tree_to_index(Some(&base.tree()?), None, Some(&mut git::diff_options()))?;
+            git::find_renames(&mut diff)?;
+            let message = engine.generate_commit_message(&diff).await?;
+
+            println!("Squashing {} commits:", count);
+            for commit in &squashed {
+                println!("  {} {}", &commit.id().to_string()[..7], git::commit_summary(commit));
+            }
+            println!("\nProposed message:\n{}", message);
+            if !cli.yes && !utils::confirm("\nReplace these commits with a single commit?")? {
+                println!("Aborted; history was not changed. Pass --yes to squash without confirmation.");
+                return Ok(());
+            }
+
+            repo.reset(base.as_object(), git2::ResetType::Soft, None)?;
+            let oid = commit_index(&repo, &message, signer.as_ref())?;
+            println!("\nCreated commit {}", &oid.to_string()[..7]);
         }
-        Commands::History { reference, count, prompt } => {
+        Commands::History { reference, count, all, prompt, prompt_file, no_cache, no_ai, no_merges, reverse, from_start, paths } => {
+            let prompt = utils::read_prompt(prompt.as_deref(), prompt_file.as_deref())?;
             let repo = Repository::open_from_env()?;
             let branch = if reference == "HEAD" {
                 None
@@ -279,90 +1425,427 @@ async fn main() -> Result<()> {
                 Some(reference.as_str())
             };
             
-            let commits = git::get_log(&repo, branch, Some(*count))?;
-            
-            let mut revwalk = repo.revwalk()?;
-            revwalk.push(repo.head()?.target().ok_or_else(|| anyhow!("Invalid HEAD reference"))?)?;
-            revwalk.set_sorting(git2::Sort::TIME)?;
-
-            let mut summaries = Vec::new();
-            for (i, oid) in revwalk.take(*count as usize).enumerate() {
-                let oid = oid?;
-                let commit = repo.find_commit(oid)?;
-                let tree = commit.tree()?;
-                
-                let parent = commit.parent(0).ok();
-                let parent_tree = parent.as_ref().map(|c| c.tree()).transpose()?;
-                
-                let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
-                let summary = engine.summarize_diff(&diff, prompt.as_deref()).await?;
-                
+            let cap = history_cap();
+            let all = *all || *count == 0;
+            let count = if all {
+                // Walk one past the cap to tell whether it cut the history short
+                cap + 1
+            } else if *count > cap {
+                warn!("Limiting history to {} commits (set {} to raise the cap)", cap, HISTORY_CAP_ENV);
+                cap
+            } else {
+                *count
+            };
+
+            let order = git::LogOrder::new(*reverse, *from_start);
+            // The count applies to commits that touched the paths; a failed diff leaves a commit out
+            let mut commits = git::get_ordered_log(&repo, branch, Some(count), order, |commit| {
+                (!*no_merges || commit.parent_count() <= 1)
+                    && (paths.is_empty() || git::touches_paths(&repo, commit, paths).unwrap_or(false))
+            })?;
+            if all && commits.len() > cap as usize {
+                warn!("Limiting history to {} commits (set {} to raise the cap)", cap, HISTORY_CAP_ENV);
+                // The extra commit is the one furthest from where the walk started
+                if order == git::LogOrder::Reverse {
+                    commits.remove(0);
+                } else {
+                    commits.pop();
+                }
+            }
+            let diffs = commits.iter()
+                .map(|commit| if paths.is_empty() {
+                    git::get_commit_diff(&repo, commit)
+                } else {
+                    git::get_commit_diff_matching(&repo, commit, paths)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let cache = SummaryCache::new(!*no_cache);
+            let mut cache_key = format!("history:{}:{}", engine.cache_key(), prompt.as_deref().unwrap_or(""));
+            if !paths.is_empty() {
+                cache_key.push_str(&format!(":paths={}", paths.join(",")));
+            }
+
+            // Summarize concurrently; `buffered` yields results in input order
+            let ai_summaries: Vec<String> = stream::iter(commits.iter().zip(&diffs))
+                .map(|(commit, diff)| {
+                    let (engine, cache, cache_key, prompt) = (&engine, &cache, &cache_key, &prompt);
+                    async move {
+                        if *no_ai {
+                            return Ok(ai::diff_stats(diff)?);
+                        }
+                        if let Some(summary) = cache.get(commit.id(), cache_key) {
+                            return Ok(summary);
+                        }
+                        let summary = engine.summarize_diff(diff, prompt.as_deref()).await?;
+                        cache.put(commit.id(), cache_key, &summary);
+                        Ok::<_, anyhow::Error>(summary)
+                    }
+                })
+                .buffered(HISTORY_CONCURRENCY)
+                .try_collect()
+                .await?;
+
+            let total = commits.len();
+            let mut summaries = vec![String::from("Git History Summary:\n\n")];
+            for (i, (commit, summary)) in commits.iter().zip(ai_summaries).enumerate() {
                 summaries.push(format!(
                     "Commit {} - {}\n{}\n",
-                    &oid.to_string()[..7],
-                    commit.summary().unwrap_or("No summary"),
+                    &commit.id().to_string()[..7],
+                    git::commit_summary(commit),
                     summary
                 ));
 
-                if i < *count as usize - 1 {
+                if i < total - 1 {
                     summaries.push(String::from("\n---\n\n"));
                 }
             }
 
-            println!("Git History Summary:\n");
-            for summary in summaries {
-                print!("{}", summary);
+            emit(cli, &summaries.concat())?;
+        }
+        Commands::Show { reference, no_cache, no_ai } => {
+            let repo = Repository::open_from_env()?;
+            let commit = repo.find_commit(git::resolve_reference(&repo, reference)?)?;
+            // Merges are diffed against their first parent: what the merge brought in
+            let diff = git::get_commit_diff(&repo, &commit)?;
+
+            let summary = if *no_ai {
+                ai::diff_stats(&diff)?
+            } else {
+                // Shares history's cache, which keys summaries by prompt
+                let cache = SummaryCache::new(!*no_cache);
+                let cache_key = format!("history:{}:", engine.cache_key());
+                match cache.get(commit.id(), &cache_key) {
+                    Some(summary) => summary,
+                    None => {
+                        let summary = engine.summarize_diff(&diff, None).await?;
+                        cache.put(commit.id(), &cache_key, &summary);
+                        summary
+                    }
+                }
+            };
+
+            let mut output = format!("{}\n", commit_header(commit.id()));
+            if commit.parent_count() > 1 {
+                let parents: Vec<String> = commit.parent_ids().map(|id| id.to_string()[..7].to_string()).collect();
+                output.push_str(&format!("Merge:  {}\n", parents.join(" ")));
             }
+            output.push_str(&format!("Author: {}\n", commit.author()));
+            output.push_str(&format!("Date:   {}\n\n", git::format_commit_date(&commit)));
+            output.push_str(&format!("    {}\n\n", git::commit_message(&commit).replace('\n', "\n    ")));
+            output.push_str(&format!("{}\n{}\n", section_heading("AI Summary"), summary));
+            emit(cli, &output)?;
         }
-        Commands::Log { branch, limit } => {
+        Commands::Log { branch, limit, since, author, show_diff, diff_context, no_pager, no_cache, no_merges, reverse, from_start, format, no_ai } => {
             let repo = Repository::open_from_env()?;
-            let commits = git::get_log(&repo, branch.as_deref(), Some(*limit))?;
+            let cutoff = since.as_deref()
+                .map(|since| utils::parse_since(since, chrono::Local::now()))
+                .transpose()?;
+            // Filter while walking so --limit counts only matching commits
+            let order = git::LogOrder::new(*reverse, *from_start);
+            let commits = git::get_ordered_log(&repo, branch.as_deref(), Some(*limit), order, |commit| {
+                cutoff.is_none_or(|cutoff| commit.time().seconds() >= cutoff)
+                    && author.as_deref().is_none_or(|author| git::author_matches(commit, author))
+                    && (!*no_merges || commit.parent_count() <= 1)
+            })?;
+            let cache = SummaryCache::new(!*no_cache);
+            let cache_key = format!("log:{}", engine.cache_key());
             
             // Build the log output
             let mut output = String::new();
             
             for commit in commits {
                 let hash = commit.id();
-                let time = commit.time();
-                let datetime = chrono::DateTime::<chrono::Utc>::from_timestamp(time.seconds(), 0)
-                    .unwrap()
-                    .format("%Y-%m-%d %H:%M:%S");
-                
+
+                // AI Summary
+                let summary = match cache.get(hash, &cache_key) {
+                    _ if *no_ai => None,
+                    Some(summary) => Some(summary),
+                    None => {
+                        let diff = git::get_commit_diff(&repo, &commit)?;
+                        let summary = engine.generate_commit_message(&diff).await?;
+                        cache.put(hash, &cache_key, &summary);
+                        Some(summary)
+                    }
+                };
+
+                if *format == LogFormat::Jsonl {
+                    let mut record = serde_json::json!({
+                        "sha": hash.to_string(),
+                        "author": commit.author().to_string(),
+                        "date": git::commit_time(&commit).to_rfc3339(),
+                        // The real message for scripts, without the empty-message placeholder
+                        "original_message": String::from_utf8_lossy(commit.message_bytes()).trim(),
+                        "ai_summary": summary,
+                    });
+                    if *show_diff {
+                        let diff = git::get_commit_diff_with_context(&repo, &commit, diff_context.unwrap_or_else(git::context_lines))?;
+                        record["diff"] = utils::strip_ansi(&git::format_patch(&diff)?).into();
+                    }
+                    output.push_str(&format!("{}\n", record));
+                    continue;
+                }
+
                 // Commit header
-                output.push_str(&format!("\n\x1b[33mcommit {}\x1b[0m\n", hash));
+                output.push_str(&format!("\n{}\n", commit_header(hash)));
                 output.push_str(&format!("Author: {}\n", commit.author()));
-                output.push_str(&format!("Date:   {}\n\n", datetime));
+                output.push_str(&format!("Date:   {}\n\n", git::format_commit_date(&commit)));
                 
-                // AI Summary
-                let diff = git::get_commit_diff(&repo, &commit)?;
-                let summary = engine.generate_commit_message(&diff).await?;
-                output.push_str("\x1b[36mAI Summary:\x1b[0m\n");
-                output.push_str(&format!("{}\n", summary.replace("\n", "\n    ")));
+                if let Some(summary) = &summary {
+                    output.push_str(&format!("{}\n", section_heading("AI Summary")));
+                    output.push_str(&format!("{}\n", summary.replace("\n", "\n    ")));
+                }
+
+                if *show_diff {
+                    let diff = git::get_commit_diff_with_context(&repo, &commit, diff_context.unwrap_or_else(git::context_lines))?;
+                    output.push('\n');
+                    output.push_str(&git::format_patch(&diff)?);
+                }
                 
                 // Separator
-                output.push_str("\n\x1b[90m----------------------------------------\x1b[0m\n");
+                output.push_str(&format!("\n{}\n", utils::paint(utils::GRAY, "-".repeat(40))));
                 
                 // Original message
-                if let Some(msg) = commit.message() {
-                    output.push_str("\x1b[32mOriginal Message:\x1b[0m\n");
-                    output.push_str(&format!("{}\n", msg.trim().replace("\n", "\n    ")));
-                }
+                output.push_str(&format!("{}\n", utils::paint(utils::GREEN, "Original Message:")));
+                output.push_str(&format!("{}\n", git::commit_message(&commit).replace("\n", "\n    ")));
                 
-                output.push_str("\n");
+                output.push('\n');
             }
             
-            // Open in pager
-            let mut child = std::process::Command::new("less")
-                .arg("-R")  // Enable color codes
-                .stdin(std::process::Stdio::piped())
-                .spawn()?;
-            
-            if let Some(mut stdin) = child.stdin.take() {
-                use std::io::Write;
-                stdin.write_all(output.as_bytes())?;
+            if cli.output.is_some() || *format == LogFormat::Jsonl {
+                emit(cli, &output)?;
+            } else {
+                utils::page_output(&output, !*no_pager)?;
             }
-            
-            child.wait()?;
+        }
+        Commands::Review { base } => {
+            let repo = Repository::open_from_env()?;
+            let head = repo.head()?.peel_to_commit()?;
+            // Against the branch a pull request would target
+            let remote = git::pr::PrRemotes::detect(&repo, git::current_branch_name(&repo).as_deref(), None)?.base;
+            let base_branch = match base {
+                Some(base) => base.clone(),
+                None => git::pr::detect_default_branch(&repo, &remote),
+            };
+            let base_commit = git::find_base_commit(&repo, &base_branch, &remote)?;
+
+            // Only review what the branch adds on top of where it forked
+            let fork_point = repo.merge_base(base_commit.id(), head.id())
+                .and_then(|oid| repo.find_commit(oid))
+                .unwrap_or(base_commit);
+            let mut diff = repo.diff_tree_to_tree(Some(&fork_point.tree()?), Some(&head.tree()?), Some(&mut git::diff_options()))?;
+            git::find_renames(&mut diff)?;
+
+            let comments = engine.review_diff(&diff).await?;
+            if comments.is_empty() {
+                println!("No issues found.");
+                return Ok(());
+            }
+
+            // Group by file, keeping the order the files were first mentioned
+            let mut files: Vec<(&str, Vec<&ai::ReviewComment>)> = Vec::new();
+            for comment in &comments {
+                match files.iter_mut().find(|(file, _)| *file == comment.file) {
+                    Some((_, group)) => group.push(comment),
+                    None => files.push((&comment.file, vec![comment])),
+                }
+            }
+
+            for (file, group) in files {
+                println!("\n{}", utils::paint(utils::BOLD, file));
+                for comment in group {
+                    let (color, label) = match comment.severity {
+                        ai::Severity::Error => (utils::RED, "error"),
+                        ai::Severity::Warning => (utils::YELLOW, "warning"),
+                        ai::Severity::Info => (utils::CYAN, "info"),
+                    };
+                    let location = comment.line.map(|line| format!("line {}: ", line)).unwrap_or_default();
+                    println!("  {} {}{}", utils::paint(color, label), location, comment.comment);
+                }
+            }
+        }
+        Commands::Explain { file, line } => {
+            let repo = Repository::open_from_env()?;
+            let blamed = git::blame::blame_line(&repo, file, *line)?;
+
+            println!("{}:{}: {}", blamed.path, line, blamed.text.trim());
+
+            let oid = match blamed.origin {
+                git::blame::LineOrigin::Commit(oid) => oid,
+                git::blame::LineOrigin::Uncommitted => {
+                    println!("\nThis line has not been committed yet, so there is no history to explain.");
+                    return Ok(());
+                }
+            };
+
+            let commit = repo.find_commit(oid)?;
+            println!("\n{}", commit_header(oid));
+            println!("Author: {}", commit.author());
+            println!("Date:   {}", git::format_commit_date(&commit));
+            if commit.parent_count() == 0 {
+                println!("(introduced in the initial commit and unchanged since)");
+            }
+
+            let diff = git::get_commit_diff(&repo, &commit)?;
+            let explanation = engine.explain_line(&diff, &git::commit_message(&commit), &blamed.path, &blamed.text).await?;
+            println!("\n{}\n{}", section_heading("Explanation"), explanation);
+        }
+        Commands::Changelog { from, to, no_cache } => {
+            let repo = Repository::open_from_env()?;
+            let base = repo.find_commit(git::resolve_reference(&repo, from)?)?;
+            let to = to.as_deref().unwrap_or("HEAD");
+            let head = repo.find_commit(git::resolve_reference(&repo, to)?)?;
+
+            // Merges only repeat the changes of the commits they bring in
+            let commits: Vec<_> = git::get_commits_between(&repo, &base, &head)?
+                .into_iter()
+                .filter(|commit| commit.parent_count() <= 1)
+                .collect();
+            if commits.is_empty() {
+                println!("No commits between {} and {}", from, to);
+                return Ok(());
+            }
+            let diffs = commits.iter()
+                .map(|commit| git::get_commit_diff(&repo, commit))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Shares cached summaries with `history`
+            let cache = SummaryCache::new(!*no_cache);
+            let cache_key = format!("history:{}:", engine.cache_key());
+            let summaries: Vec<String> = stream::iter(commits.iter().zip(&diffs))
+                .map(|(commit, diff)| {
+                    let (cache, cache_key) = (&cache, &cache_key);
+                    async move {
+                        if let Some(summary) = cache.get(commit.id(), cache_key) {
+                            return Ok(summary);
+                        }
+                        let summary = engine.summarize_diff(diff, None).await?;
+                        cache.put(commit.id(), cache_key, &summary);
+                        Ok::<_, anyhow::Error>(summary)
+                    }
+                })
+                .buffered(HISTORY_CONCURRENCY)
+                .try_collect()
+                .await?;
+
+            let entries: Vec<(String, String)> = commits.iter()
+                .map(git::commit_message)
+                .zip(summaries)
+                .collect();
+            let changelog = engine.generate_changelog(&entries).await?;
+            emit(cli, &format!("## {}\n\n{}\n", to, changelog.trim()))?;
+        }
+        Commands::Stats { branch, count, no_ai } => {
+            let repo = Repository::open_from_env()?;
+            let commits = git::get_log(&repo, branch.as_deref(), Some(*count))?;
+            let (Some(newest), Some(oldest)) = (commits.first(), commits.last()) else {
+                println!("No commits found");
+                return Ok(());
+            };
+
+            let authors = git::stats::author_stats(&repo, &commits)?;
+            let width = authors.iter().map(|author| author.name.len()).max().unwrap_or(0).max("Author".len());
+            println!("{}", utils::paint(utils::BOLD, format!("{:<width$}  {:>7}  {:>10}  {:>10}", "Author", "Commits", "Insertions", "Deletions", width = width)));
+            for author in &authors {
+                println!(
+                    "{:<width$}  {:>7}  {}  {}",
+                    author.name,
+                    author.commits,
+                    utils::paint(utils::GREEN, format!("{:>10}", author.insertions)),
+                    utils::paint(utils::RED, format!("{:>10}", author.deletions)),
+                    width = width
+                );
+            }
+            let merges = commits.iter().filter(|commit| commit.parent_count() > 1).count();
+            if merges > 0 {
+                println!("({} merge commits not counted)", merges);
+            }
+
+            if *no_ai {
+                return Ok(());
+            }
+
+            // One diff across the whole range, so the narrative covers the combined result
+            let base_tree = match oldest.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let mut diff = repo.diff_tree_to_tree(base_tree.as_ref(), Some(&newest.tree()?), Some(&mut git::diff_options()))?;
+            git::find_renames(&mut diff)?;
+
+            let mut prompt = String::from("Write a short narrative of what the team worked on in these commits, grouped by theme, noting who worked on what. Commits:\n");
+            for commit in &commits {
+                prompt.push_str(&format!(
+                    "- {} ({}): {}\n",
+                    &commit.id().to_string()[..7],
+                    commit.author().name().unwrap_or("Unknown"),
+                    git::commit_summary(commit)
+                ));
+            }
+            let narrative = engine.summarize_diff(&diff, Some(&prompt)).await?;
+            println!("\n{}\n{}", section_heading("Summary"), narrative);
+        }
+        Commands::Stash { action: StashAction::List { no_cache } } => {
+            let mut repo = Repository::open_from_env()?;
+            let entries = git::stash::list_stashes(&mut repo)?;
+            if entries.is_empty() {
+                println!("No stash entries.");
+                return Ok(());
+            }
+
+            // The engine only writes subject lines for this command
+            let cache = SummaryCache::new(!*no_cache);
+            let cache_key = format!("stash-list:{}", engine.cache_key());
+            let mut output = String::new();
+            for entry in &entries {
+                let summary = match cache.get(entry.oid, &cache_key) {
+                    Some(summary) => summary,
+                    None => {
+                        let diff = git::stash::stash_diff(&repo, entry)?;
+                        let summary = engine.generate_commit_message(&diff).await?;
+                        cache.put(entry.oid, &cache_key, &summary);
+                        summary
+                    }
+                };
+                output.push_str(&format!("{} {}\n", utils::paint(utils::YELLOW, format!("stash@{{{}}}:", entry.index)), summary));
+                output.push_str(&format!("    {}\n", utils::paint(utils::GRAY, &entry.message)));
+            }
+            emit(cli, &output)?;
+        }
+        Commands::Stash { action: StashAction::Show { index, no_cache } } => {
+            let mut repo = Repository::open_from_env()?;
+            let entry = git::stash::find_stash(&mut repo, *index)?;
+            let diff = git::stash::stash_diff(&repo, &entry)?;
+
+            let cache = SummaryCache::new(!*no_cache);
+            let cache_key = format!("stash:{}", engine.cache_key());
+            let summary = match cache.get(entry.oid, &cache_key) {
+                Some(summary) => summary,
+                None => {
+                    let summary = engine.summarize_diff(&diff, None).await?;
+                    cache.put(entry.oid, &cache_key, &summary);
+                    summary
+                }
+            };
+
+            let stash = repo.find_commit(entry.oid)?;
+            let mut output = format!("{}\n", utils::paint(utils::YELLOW, format!("stash@{{{}}}", entry.index)));
+            output.push_str(&format!("Message: {}\n", entry.message));
+            output.push_str(&format!("Base:    {}\n", &stash.parent_id(0)?.to_string()[..7]));
+            output.push_str(&format!("Date:    {}\n", git::format_commit_date(&stash)));
+            let staged = git::stash::stash_staged_paths(&repo, &entry)?;
+            if !staged.is_empty() {
+                output.push_str(&format!("Staged:  {}\n", staged.join(", ")));
+            }
+            output.push_str(&format!("\n{}\n{}\n", section_heading("AI Summary"), summary));
+            emit(cli, &output)?;
+        }
+        Commands::Doctor { no_ping } => {
+            run_doctor(engine, config, !*no_ping).await?;
+        }
+        Commands::Cache { action: CacheAction::Clear } => {
+            let count = SummaryCache::clear()?;
+            println!("Cleared {} cached summaries.", count);
         }
     }
 
@@ -1,16 +1,121 @@
 use anyhow::Result;
 
+use crate::ai::FileSelection;
+
+/// A line of the group selection list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Row {
+    /// Header for the group at this index
+    Group(usize),
+    /// A file within a group: (group index, file index)
+    File(usize, usize),
+}
+
 pub struct App {
     pub title: String,
     pub content: String,
+    pub scroll: u16,
+    /// Suggested groups, each file paired with whether it is selected
+    pub groups: Vec<Vec<(FileSelection, bool)>>,
+    /// Index into `rows()` of the highlighted line
+    pub cursor: usize,
+    /// Commit message for the confirmed selection, once generated
+    pub message: Option<String>,
 }
 
 impl App {
     pub fn new(title: String, content: String) -> Self {
-        Self { title, content }
+        Self { title, content, scroll: 0, groups: Vec::new(), cursor: 0, message: None }
+    }
+
+    /// Show these groups for selection, with the first one selected
+    pub fn with_groups(mut self, groups: Vec<Vec<FileSelection>>) -> Self {
+        self.groups = groups.into_iter()
+            .enumerate()
+            .map(|(i, files)| files.into_iter().map(|file| (file, i == 0)).collect())
+            .collect();
+        self
     }
 
     pub fn update(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Scroll towards the start of the content
+    pub fn scroll_up(&mut self, lines: u16) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// Scroll towards the end of the content, stopping at its last line
+    pub fn scroll_down(&mut self, lines: u16) {
+        let max = u16::try_from(self.content.lines().count().saturating_sub(1)).unwrap_or(u16::MAX);
+        self.scroll = self.scroll.saturating_add(lines).min(max);
+    }
+
+    /// Every line of the selection list, in display order
+    pub fn rows(&self) -> Vec<Row> {
+        self.groups.iter()
+            .enumerate()
+            .flat_map(|(i, files)| std::iter::once(Row::Group(i)).chain((0..files.len()).map(move |j| Row::File(i, j))))
+            .collect()
+    }
+
+    /// Move the highlight up (negative) or down, staying within the list
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.rows().len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+
+    /// Toggle the highlighted file, or every file of the highlighted group
+    pub fn toggle(&mut self) {
+        match self.rows().get(self.cursor) {
+            Some(Row::File(i, j)) => {
+                let (i, j) = (*i, *j);
+                self.groups[i][j].1 = !self.groups[i][j].1;
+            }
+            Some(Row::Group(i)) => {
+                let files = &mut self.groups[*i];
+                let select = !files.iter().all(|(_, selected)| *selected);
+                files.iter_mut().for_each(|(_, selected)| *selected = select);
+            }
+            None => {}
+        }
+    }
+
+    /// The files currently checked, across all groups
+    pub fn selected_files(&self) -> Vec<FileSelection> {
+        self.groups.iter()
+            .flatten()
+            .filter(|(_, selected)| *selected)
+            .map(|(file, _)| file.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str) -> FileSelection {
+        FileSelection { path: path.to_string(), hunks: None }
+    }
+
+    #[test]
+    fn test_group_selection() {
+        let mut app = App::new(String::new(), String::new())
+            .with_groups(vec![vec![file("a.rs"), file("b.rs")], vec![file("c.rs")]]);
+        assert_eq!(app.rows(), vec![Row::Group(0), Row::File(0, 0), Row::File(0, 1), Row::Group(1), Row::File(1, 0)]);
+        assert_eq!(app.selected_files(), vec![file("a.rs"), file("b.rs")]);
+
+        app.move_cursor(2);
+        app.toggle();
+        app.move_cursor(10);
+        app.toggle();
+        assert_eq!(app.selected_files(), vec![file("a.rs"), file("c.rs")]);
+
+        // Toggling a partly selected group selects all of it
+        app.move_cursor(-10);
+        app.toggle();
+        assert_eq!(app.selected_files(), vec![file("a.rs"), file("b.rs"), file("c.rs")]);
+    }
 }
@@ -7,13 +7,20 @@ use ratatui::{
     backend::CrosstermBackend,
     Terminal,
 };
+use std::future::Future;
 use std::io;
 
+use crate::ai::FileSelection;
+
 mod app;
+pub mod spinner;
 mod widgets;
 
+use app::App;
+
 pub struct Tui {
     terminal: Terminal<CrosstermBackend<io::Stdout>>,
+    raw_mode: bool,
 }
 
 impl Tui {
@@ -21,35 +28,93 @@ impl Tui {
     pub fn new() -> Result<Self> {
         let backend = CrosstermBackend::new(io::stdout());
         let terminal = Terminal::new(backend)?;
-        enable_raw_mode()?;
+        // Progress spinners would draw over the interface
+        spinner::suppress();
         
-        Ok(Self { terminal })
+        Ok(Self { terminal, raw_mode: false })
     }
 
-    /// Run the TUI with the given content
+    /// Run the TUI with the given content until the user quits
     pub fn run(&mut self, title: &str, content: &str) -> Result<()> {
+        let mut app = App::new(title.to_string(), content.to_string());
+        // Raw mode is only entered once we actually take over the terminal
+        enable_raw_mode()?;
+        self.raw_mode = true;
         self.terminal.clear()?;
         
         loop {
             self.terminal.draw(|frame| {
-                widgets::draw_main_layout(frame, title, content);
+                widgets::draw_main_layout(frame, &app.title, &app.content, app.scroll);
             })?;
+
+            // Title block, borders and margins take up six rows
+            let page = self.terminal.size()?.height.saturating_sub(6).max(1);
             
             if let Event::Key(key) = event::read()? {
-                if key.code == KeyCode::Char('q') {
-                    break;
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    KeyCode::Up | KeyCode::Char('k') => app.scroll_up(1),
+                    KeyCode::Down | KeyCode::Char('j') => app.scroll_down(1),
+                    KeyCode::PageUp => app.scroll_up(page),
+                    KeyCode::PageDown | KeyCode::Char(' ') => app.scroll_down(page),
+                    KeyCode::Home => app.scroll = 0,
+                    _ => (),
                 }
             }
+            app.update()?;
         }
         
         Ok(())
     }
+
+    /// Let the user check files from the suggested groups. On Enter, `describe` is
+    /// called with the selection and its result is shown next to the list.
+    /// Returns the selection and message, or `None` if the user cancelled.
+    pub async fn select_files<F, Fut>(&mut self, groups: Vec<Vec<FileSelection>>, describe: F) -> Result<Option<(Vec<FileSelection>, String)>>
+    where
+        F: FnOnce(Vec<FileSelection>) -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let mut app = App::new("Select files to stage".to_string(), String::new()).with_groups(groups);
+        enable_raw_mode()?;
+        self.raw_mode = true;
+        self.terminal.clear()?;
+
+        let selection = loop {
+            self.terminal.draw(|frame| widgets::draw_selection_layout(frame, &app))?;
+
+            if let Event::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                    KeyCode::Up | KeyCode::Char('k') => app.move_cursor(-1),
+                    KeyCode::Down | KeyCode::Char('j') => app.move_cursor(1),
+                    KeyCode::Char(' ') => app.toggle(),
+                    KeyCode::Enter if !app.selected_files().is_empty() => break app.selected_files(),
+                    _ => (),
+                }
+            }
+        };
+
+        app.message = Some("Generating commit message...".to_string());
+        self.terminal.draw(|frame| widgets::draw_selection_layout(frame, &app))?;
+        let message = describe(selection.clone()).await?;
+
+        app.title = "Staged; press any key to exit".to_string();
+        app.message = Some(message.clone());
+        self.terminal.draw(|frame| widgets::draw_selection_layout(frame, &app))?;
+        while !matches!(event::read()?, Event::Key(_)) {}
+
+        Ok(Some((selection, message)))
+    }
 }
 
 impl Drop for Tui {
     fn drop(&mut self) {
-        disable_raw_mode().unwrap();
-        self.terminal.show_cursor().unwrap();
+        if self.raw_mode {
+            disable_raw_mode().unwrap();
+            self.terminal.show_cursor().unwrap();
+        }
+        spinner::resume();
     }
 }
 
@@ -0,0 +1,75 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const FRAME_INTERVAL: Duration = Duration::from_millis(80);
+
+/// Whether spinners may be drawn at all; off unless `set_enabled` is called
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Set while a spinner is on screen, so concurrent requests share one line
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Number of full-screen UIs open; spinners would draw over them
+static SUPPRESSED: AtomicUsize = AtomicUsize::new(0);
+
+/// Allow spinners, for interactive sessions without verbose logging
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Hide spinners until the matching `resume`
+pub fn suppress() {
+    SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn resume() {
+    SUPPRESSED.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// An animated progress message on stderr, cleared when dropped
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Show `message` with a spinner, unless spinners are disabled or one is already shown
+    pub fn start(message: &str) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let visible = ENABLED.load(Ordering::Relaxed)
+            && SUPPRESSED.load(Ordering::Relaxed) == 0
+            && !ACTIVE.swap(true, Ordering::Relaxed);
+        if !visible {
+            return Self { stop, thread: None };
+        }
+
+        let message = message.to_string();
+        let flag = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut stderr = io::stderr();
+            for frame in FRAMES.iter().cycle() {
+                if flag.load(Ordering::Relaxed) {
+                    break;
+                }
+                let _ = write!(stderr, "\r{} {}", frame, message);
+                let _ = stderr.flush();
+                thread::sleep(FRAME_INTERVAL);
+            }
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        });
+        Self { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            let _ = thread.join();
+            ACTIVE.store(false, Ordering::Relaxed);
+        }
+    }
+}
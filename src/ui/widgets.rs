@@ -1,11 +1,13 @@
 use ratatui::{
     layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, Paragraph},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
     Frame,
 };
 
-pub fn draw_main_layout(frame: &mut Frame, title: &str, content: &str) {
+use super::app::{App, Row};
+
+pub fn draw_main_layout(frame: &mut Frame, title: &str, content: &str, scroll: u16) {
     let size = frame.size();
     
     let chunks = Layout::default()
@@ -27,8 +29,61 @@ pub fn draw_main_layout(frame: &mut Frame, title: &str, content: &str) {
     
     let content_widget = Paragraph::new(content)
         .block(content_block)
-        .wrap(ratatui::widgets::Wrap { trim: true });
+        .wrap(ratatui::widgets::Wrap { trim: true })
+        .scroll((scroll, 0));
     
     frame.render_widget(title_block, chunks[0]);
     frame.render_widget(content_widget, chunks[1]);
 }
+
+/// Group selection list on the left, commit message (or key help) on the right
+pub fn draw_selection_layout(frame: &mut Frame, app: &App) {
+    let size = frame.size();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(1),
+        ].as_ref())
+        .split(size);
+
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(50),
+            Constraint::Percentage(50),
+        ].as_ref())
+        .split(chunks[1]);
+
+    let title_block = Block::default()
+        .title(app.title.as_str())
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::Cyan));
+
+    let items: Vec<ListItem> = app.rows().into_iter()
+        .map(|row| match row {
+            Row::Group(i) => ListItem::new(format!("Group {}", i + 1))
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+            Row::File(i, j) => {
+                let (file, selected) = &app.groups[i][j];
+                ListItem::new(format!("  [{}] {}", if *selected { "x" } else { " " }, file))
+            }
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::default().title("Files").borders(Borders::ALL))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(Some(app.cursor));
+
+    let message = app.message.as_deref()
+        .unwrap_or("Up/Down: move   Space: toggle   Enter: stage selection   q: cancel");
+    let message_widget = Paragraph::new(message)
+        .block(Block::default().title("Commit message").borders(Borders::ALL))
+        .wrap(ratatui::widgets::Wrap { trim: false });
+
+    frame.render_widget(title_block, chunks[0]);
+    frame.render_stateful_widget(list, panes[0], &mut state);
+    frame.render_widget(message_widget, panes[1]);
+}
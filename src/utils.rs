@@ -1,7 +1,247 @@
-use anyhow::Result;
-use git2::Repository;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-/// Get the current git repository
-fn get_current_repo() -> Result<Repository> {
-    Ok(Repository::open_from_env()?)
+/// Whether prompts and the pager may be used; off unless `set_interactive` is called
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Allow prompts and the pager, for sessions with someone at the terminal
+pub fn set_interactive(enabled: bool) {
+    INTERACTIVE.store(enabled, Ordering::Relaxed);
+}
+
+/// Print a prompt and read a single trimmed line from stdin.
+/// Returns `None` when the session is not interactive or stdin has been closed.
+pub fn prompt_line(prompt: &str) -> io::Result<Option<String>> {
+    if !INTERACTIVE.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Ask a yes/no question, defaulting to "no".
+/// A non-interactive session is treated as a refusal.
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    let answer = prompt_line(&format!("{} [y/N]: ", prompt))?;
+    Ok(matches!(answer.as_deref().map(str::to_lowercase).as_deref(), Some("y" | "yes")))
+}
+
+/// The custom prompt given inline or, with `--prompt-file`, read from disk
+pub fn read_prompt(prompt: Option<&str>, prompt_file: Option<&Path>) -> Result<Option<String>> {
+    let Some(path) = prompt_file else {
+        return Ok(prompt.map(str::to_string));
+    };
+
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read prompt file '{}': {}", path.display(), e))?;
+    if contents.trim().is_empty() {
+        return Err(anyhow!("Prompt file '{}' is empty", path.display()));
+    }
+    Ok(Some(contents.trim().to_string()))
+}
+
+/// ANSI styles for `paint`
+pub const BOLD: &str = "1";
+pub const RED: &str = "31";
+pub const GREEN: &str = "32";
+pub const YELLOW: &str = "33";
+pub const CYAN: &str = "36";
+pub const GRAY: &str = "90";
+
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Turn colored output on or off for the whole process (`--no-color`, `NO_COLOR`)
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Wrap `text` in an ANSI style, or leave it plain when color is off
+pub fn paint(style: &str, text: impl std::fmt::Display) -> String {
+    styled(COLOR.load(Ordering::Relaxed), style, text)
+}
+
+fn styled(color: bool, style: &str, text: impl std::fmt::Display) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Width of text in terminal columns: emoji take two, variation selectors and joiners none
+pub fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c as u32 {
+            0xFE0F | 0x200D => 0,
+            0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x1F000..=0x1FAFF => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Stable 64-bit FNV-1a hash, so cache keys and file names survive toolchain upgrades
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Remove ANSI escape sequences (e.g. `\x1b[33m`) from text
+pub fn strip_ansi(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the CSI sequence up to and including its final byte
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Write rendered output to a file, without color codes, replacing it unless `append` is set
+pub fn write_output(path: &Path, output: &str, append: bool) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open output file '{}': {}", path.display(), e))?;
+    file.write_all(strip_ansi(output).as_bytes())
+        .map_err(|e| anyhow!("Failed to write output file '{}': {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Display output through `$PAGER` (or `less`), printing directly when
+/// paging is disabled, stdout is not a terminal, or no pager can be started.
+pub fn page_output(output: &str, use_pager: bool) -> Result<()> {
+    let stdout = io::stdout();
+    if !stdout.is_terminal() {
+        stdout.lock().write_all(strip_ansi(output).as_bytes())?;
+        return Ok(());
+    }
+
+    if use_pager && INTERACTIVE.load(Ordering::Relaxed) {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+        let mut parts = pager.split_whitespace();
+        if let Some(program) = parts.next() {
+            let mut command = Command::new(program);
+            command.args(parts).stdin(Stdio::piped());
+            // Same defaults git uses so colors render and short output doesn't page
+            if std::env::var_os("LESS").is_none() {
+                command.env("LESS", "FRX");
+            }
+
+            if let Ok(mut child) = command.spawn() {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // The user may quit the pager before reading everything
+                    let _ = stdin.write_all(output.as_bytes());
+                }
+                child.wait()?;
+                return Ok(());
+            }
+        }
+    }
+
+    stdout.lock().write_all(output.as_bytes())?;
+    Ok(())
+}
+
+/// Parse a `--since` value into a Unix timestamp.
+/// Accepts dates (`2024-01-01`, `2024-01-01 12:00:00`, RFC 3339) and
+/// relative forms like `2.weeks`, `3 days ago` or `yesterday`.
+pub fn parse_since(input: &str, now: DateTime<Local>) -> Result<i64> {
+    let input = input.trim();
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Ok(datetime.timestamp());
+    }
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S") {
+        return local_timestamp(datetime, input);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return local_timestamp(date.and_hms_opt(0, 0, 0).unwrap_or_default(), input);
+    }
+
+    let lower = input.to_lowercase();
+    if lower == "yesterday" {
+        return Ok((now - Duration::days(1)).timestamp());
+    }
+
+    // Relative forms: "2.weeks", "2.weeks.ago", "2 weeks ago"
+    let relative = lower.replace('.', " ");
+    let relative = relative.trim_end_matches("ago").trim();
+    let (amount, unit) = relative.split_once(' ')
+        .ok_or_else(|| anyhow!("Could not parse date '{}' (try 2024-01-01 or 2.weeks)", input))?;
+    let amount: i64 = amount.trim().parse()
+        .map_err(|_| anyhow!("Could not parse date '{}' (try 2024-01-01 or 2.weeks)", input))?;
+
+    let duration = match unit.trim().trim_end_matches('s') {
+        "second" | "sec" => Duration::seconds(amount),
+        "minute" | "min" => Duration::minutes(amount),
+        "hour" => Duration::hours(amount),
+        "day" => Duration::days(amount),
+        "week" => Duration::weeks(amount),
+        "month" => Duration::days(amount * 30),
+        "year" => Duration::days(amount * 365),
+        other => return Err(anyhow!("Unknown time unit '{}' in '{}'", other, input)),
+    };
+
+    Ok((now - duration).timestamp())
+}
+
+fn local_timestamp(datetime: NaiveDateTime, input: &str) -> Result<i64> {
+    Local.from_local_datetime(&datetime)
+        .earliest()
+        .map(|datetime| datetime.timestamp())
+        .ok_or_else(|| anyhow!("Date '{}' does not exist in the local timezone", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_styled() {
+        assert_eq!(styled(true, YELLOW, "commit abc"), "\x1b[33mcommit abc\x1b[0m");
+        assert_eq!(styled(false, YELLOW, "commit abc"), "commit abc");
+        assert_eq!(strip_ansi(&styled(true, BOLD, 42)), "42");
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = Local.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+
+        assert_eq!(parse_since("2.weeks", now).unwrap(), (now - Duration::weeks(2)).timestamp());
+        assert_eq!(parse_since("3 days ago", now).unwrap(), (now - Duration::days(3)).timestamp());
+        assert_eq!(parse_since("1.month.ago", now).unwrap(), (now - Duration::days(30)).timestamp());
+        assert_eq!(parse_since("yesterday", now).unwrap(), (now - Duration::days(1)).timestamp());
+        assert_eq!(
+            parse_since("2024-01-01", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap().timestamp()
+        );
+        assert_eq!(parse_since("2024-01-01T00:00:00Z", now).unwrap(), 1704067200);
+        assert!(parse_since("soon", now).is_err());
+        assert!(parse_since("2.fortnights", now).is_err());
+    }
 }